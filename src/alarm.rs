@@ -0,0 +1,277 @@
+use crate::library::Library;
+use crate::smart_playlist::{load_playlists, RuleContext, PLAYLISTS_FILE};
+use crate::stats::PlayStats;
+use chrono::{NaiveDateTime, NaiveTime};
+use crossterm::event;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Modifier, Color}};
+use std::{io, error::Error};
+
+/// Ramp length of alarms that do not give one, e.g. the `--at` option.
+pub const DEFAULT_RAMP_SECS: u64 = 60;
+/// Volume an alarm starts at before ramping up to full.
+const RAMP_FLOOR_DB: f32 = -40.0;
+
+/// A daily wake-up alarm: at `time`, play `playlist` (a smart playlist name,
+/// or the current queue when empty) with the volume ramping up over `ramp_secs`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alarm {
+    pub time: NaiveTime,
+    pub playlist: String,
+    pub ramp_secs: u64,
+    pub enabled: bool,
+}
+
+/// Parses "7:00" or "07:00".
+pub fn parse_time(value: &str) -> Option<NaiveTime> {
+    let (hour, minute) = value.trim().split_once(':')?;
+    NaiveTime::from_hms_opt(hour.trim().parse().ok()?, minute.trim().parse().ok()?, 0)
+}
+
+impl Alarm {
+    pub fn new(time: NaiveTime, playlist: &str) -> Self {
+        Self { time, playlist: playlist.trim().to_string(), ramp_secs: DEFAULT_RAMP_SECS, enabled: true }
+    }
+
+    /// Parses `07:00, Playlist, 60, on`; everything after the time is optional.
+    /// A playlist name with a comma is quoted: `07:00, "Rise, Shine", 60`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (time, rest) = value.split_once(',').unwrap_or((value, ""));
+        let rest = rest.trim_start();
+        let (playlist, rest) = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let (name, after) = quoted.split_once('"')?;
+                let after = after.trim_start();
+                if !after.is_empty() && !after.starts_with(',') {
+                    return None;
+                }
+                (name, after.strip_prefix(',').unwrap_or(""))
+            }
+            None => rest.split_once(',').unwrap_or((rest, "")),
+        };
+        let mut alarm = Self::new(parse_time(time)?, playlist);
+        let mut fields = rest.split(',').map(str::trim);
+        if let Some(ramp) = fields.next().filter(|f| !f.is_empty()) {
+            alarm.ramp_secs = ramp.trim_end_matches('s').trim().parse().ok()?;
+        }
+        match fields.next().map(|f| f.to_ascii_lowercase()).as_deref() {
+            None | Some("") | Some("on") => {}
+            Some("off") => alarm.enabled = false,
+            Some(_) => return None,
+        }
+        Some(alarm)
+    }
+
+    /// The playlist as written in the config and the editor, quoted when it has a comma.
+    fn playlist_field(&self) -> String {
+        if self.playlist.contains(',') {
+            format!("\"{}\"", self.playlist)
+        } else {
+            self.playlist.clone()
+        }
+    }
+
+    pub fn to_config_value(&self) -> String {
+        format!("{}, {}, {}, {}", self.time.format("%H:%M"), self.playlist_field(), self.ramp_secs, if self.enabled { "on" } else { "off" })
+    }
+
+    /// The first time after `after` the alarm goes off.
+    pub fn next_after(&self, after: NaiveDateTime) -> NaiveDateTime {
+        let today = after.date().and_time(self.time);
+        if today > after { today } else { today + chrono::Duration::days(1) }
+    }
+
+    /// Whether the alarm went off after `last_check` and by `now`.
+    pub fn is_due(&self, last_check: NaiveDateTime, now: NaiveDateTime) -> bool {
+        self.enabled && self.next_after(last_check) <= now
+    }
+}
+
+/// When the next enabled alarm goes off.
+pub fn next_alarm<'a>(alarms: impl IntoIterator<Item = &'a Alarm>, now: NaiveDateTime) -> Option<NaiveDateTime> {
+    alarms.into_iter().filter(|a| a.enabled).map(|a| a.next_after(now)).min()
+}
+
+/// Volume `elapsed_ms` into an alarm's ramp: rises evenly in dB from -40 dB
+/// to full, which sounds gentler than a linear fade.
+pub fn ramp_gain(elapsed_ms: u64, ramp_secs: u64) -> f32 {
+    let ramp_ms = ramp_secs * 1000;
+    if elapsed_ms >= ramp_ms {
+        return 1.0;
+    }
+    let done = elapsed_ms as f32 / ramp_ms as f32;
+    10f32.powf(RAMP_FLOOR_DB * (1.0 - done) / 20.0)
+}
+
+/// Tracks of an alarm's smart playlist, or `None` to keep the current queue
+/// (no playlist given, no such playlist, or nothing matches).
+pub fn alarm_tracks(playlist: &str, library: &Library, stats: &PlayStats) -> Option<Vec<String>> {
+    if playlist.is_empty() {
+        return None;
+    }
+    let playlists = load_playlists(PLAYLISTS_FILE);
+    let found = playlists.iter().find(|p| p.name.eq_ignore_ascii_case(playlist))?;
+    let tracks = found.evaluate(library, &RuleContext { stats, now: crate::stats::now_secs() });
+    (!tracks.is_empty()).then_some(tracks)
+}
+
+/// The one-off alarm of `--at 07:00 [--playlist NAME]`, if given.
+pub fn alarm_from_args(args: &[String]) -> Result<Option<Alarm>, Box<dyn Error>> {
+    let value = |flag: &str| args.iter().position(|a| a == flag).map(|i| args.get(i + 1).cloned());
+    let Some(at) = value("--at") else {
+        return Ok(None);
+    };
+    let time = at.as_deref().and_then(parse_time).ok_or("--at expects a time such as 07:00")?;
+    let playlist = value("--playlist").flatten().unwrap_or_default();
+    Ok(Some(Alarm::new(time, &playlist)))
+}
+
+/// Lists the alarms; lets the user add, edit, toggle and delete them.
+pub fn edit_alarms(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    alarms: &mut Vec<Alarm>,
+    debug_mode: bool,
+) -> Result<(), Box<dyn Error>> {
+    let playlist_names: Vec<String> = load_playlists(PLAYLISTS_FILE).into_iter().map(|p| p.name).collect();
+    let mut list_state = ListState::default();
+    list_state.select(if alarms.is_empty() { None } else { Some(0) });
+    // Text being edited as "07:00, Playlist, 60"; `editing` holds the index being replaced
+    let mut input: Option<String> = None;
+    let mut editing: Option<usize> = None;
+    let mut message = if playlist_names.is_empty() {
+        String::from("No smart playlists yet - alarms play the current queue")
+    } else {
+        format!("Playlists: {}", playlist_names.join(", "))
+    };
+
+    loop {
+        let now = chrono::Local::now().naive_local();
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Min(5),
+                    Constraint::Length(3),
+                    Constraint::Length(4),
+                ].as_ref())
+                .split(f.size());
+
+            let items: Vec<ListItem> = alarms.iter()
+                .map(|a| {
+                    let playlist = if a.playlist.is_empty() { "(current queue)" } else { a.playlist.as_str() };
+                    let status = if a.enabled {
+                        let minutes = (a.next_after(now) - now).num_minutes() + 1;
+                        format!("on, in {}:{:02}", minutes / 60, minutes % 60)
+                    } else {
+                        String::from("off")
+                    };
+                    ListItem::new(format!("{}  {}  - ramp {} s  [{}]", a.time.format("%H:%M"), playlist, a.ramp_secs, status))
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title("Alarms"))
+                .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+                .highlight_symbol(">> ");
+            f.render_stateful_widget(list, chunks[0], &mut list_state);
+
+            let (editor_title, editor_text) = match &input {
+                Some(text) => ("Time, playlist, ramp seconds  ([Enter] Save  [ESC] Cancel)", format!("{}_", text)),
+                None => ("Alarm", message.clone()),
+            };
+            let editor = Paragraph::new(editor_text)
+                .block(Block::default().borders(Borders::ALL).title(editor_title));
+            f.render_widget(editor, chunks[1]);
+
+            let help = Paragraph::new("[N] New  [E] Edit  [Space] On/Off  [D] Delete  [ESC] Back   Alarms: 07:00, Morning, 60 plays the 'Morning' smart playlist at 7:00, getting louder over 60 s")
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[2]);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(500))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+
+            if let Some(text) = input.as_mut() {
+                match key.code {
+                    event::KeyCode::Esc => {
+                        input = None;
+                        editing = None;
+                    },
+                    event::KeyCode::Backspace => {
+                        text.pop();
+                    },
+                    event::KeyCode::Char(c) => text.push(c),
+                    event::KeyCode::Enter => match Alarm::parse(text) {
+                        Some(mut alarm) => {
+                            if !alarm.playlist.is_empty() && !playlist_names.iter().any(|n| n.eq_ignore_ascii_case(&alarm.playlist)) {
+                                message = format!("Error: no smart playlist named '{}'", alarm.playlist);
+                                continue;
+                            }
+                            match editing.take() {
+                                Some(idx) if idx < alarms.len() => {
+                                    alarm.enabled = alarms[idx].enabled;
+                                    alarms[idx] = alarm;
+                                }
+                                _ => alarms.push(alarm),
+                            }
+                            alarms.sort_by_key(|a| a.time);
+                            message = String::from("Saved");
+                            input = None;
+                            if list_state.selected().is_none() {
+                                list_state.select(Some(0));
+                            }
+                        }
+                        None => message = String::from("Error: expected 'HH:MM, playlist, ramp seconds'"),
+                    },
+                    _ => {}
+                }
+                continue;
+            }
+
+            let selected = list_state.selected().filter(|i| *i < alarms.len());
+            match key.code {
+                event::KeyCode::Esc | event::KeyCode::Enter => {
+                    if debug_mode {
+                        println!("[DEBUG] {} alarms set", alarms.iter().filter(|a| a.enabled).count());
+                    }
+                    return Ok(());
+                },
+                event::KeyCode::Down if !alarms.is_empty() => {
+                    let i = selected.map(|i| if i >= alarms.len() - 1 { 0 } else { i + 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Up if !alarms.is_empty() => {
+                    let i = selected.map(|i| if i == 0 { alarms.len() - 1 } else { i - 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Char('n') | event::KeyCode::Char('N') => {
+                    input = Some(String::new());
+                    editing = None;
+                },
+                event::KeyCode::Char('e') | event::KeyCode::Char('E') => {
+                    if let Some(i) = selected {
+                        let a = &alarms[i];
+                        input = Some(format!("{}, {}, {}", a.time.format("%H:%M"), a.playlist_field(), a.ramp_secs));
+                        editing = Some(i);
+                    }
+                },
+                event::KeyCode::Char(' ') => {
+                    if let Some(i) = selected {
+                        alarms[i].enabled = !alarms[i].enabled;
+                    }
+                },
+                event::KeyCode::Char('d') | event::KeyCode::Char('D') => {
+                    if let Some(i) = selected {
+                        alarms.remove(i);
+                        list_state.select(if alarms.is_empty() { None } else { Some(i.min(alarms.len() - 1)) });
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
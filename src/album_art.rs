@@ -0,0 +1,449 @@
+use crate::metadata::TrackTags;
+use crossterm::{cursor::MoveTo, queue};
+use image::{ImageEncoder, RgbaImage};
+use ratatui::{backend::CrosstermBackend, Terminal, layout::Rect, style::{Color, Style}, text::{Line, Span}};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+
+/// Covers are scaled down to this many pixels on their longest side when
+/// loaded; that is more than any terminal panel needs.
+const MAX_ART_SIZE: u32 = 512;
+
+/// Sidecar images looked up next to a track without embedded art, in order
+/// of preference. Matched case-insensitively.
+const SIDECAR_NAMES: [&str; 8] = [
+    "cover.jpg", "cover.jpeg", "cover.png",
+    "folder.jpg", "folder.jpeg", "folder.png",
+    "front.jpg", "front.png",
+];
+
+/// Image id used for the kitty graphics protocol, so the cover can be removed again.
+const KITTY_IMAGE_ID: u32 = 7341;
+
+/// How cover art is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArtProtocol {
+    /// kitty graphics protocol (kitty, Ghostty, WezTerm).
+    Kitty,
+    /// iTerm2 inline images (iTerm2, WezTerm).
+    Iterm2,
+    /// DEC sixel graphics (foot, mlterm, xterm -ti vt340, ...).
+    Sixel,
+    /// Coloured `▀` characters, two pixels per cell.
+    HalfBlocks,
+    /// Plain characters by brightness, for terminals without colour.
+    Ascii,
+}
+
+impl ArtProtocol {
+    /// Whether the picture is written straight to the terminal rather than drawn as text.
+    pub fn is_graphics(self) -> bool {
+        matches!(self, ArtProtocol::Kitty | ArtProtocol::Iterm2 | ArtProtocol::Sixel)
+    }
+
+    /// Picks the best protocol from the environment (`TERM`, `TERM_PROGRAM`, ...).
+    pub fn detect(env: impl Fn(&str) -> Option<String>) -> Self {
+        let term = env("TERM").unwrap_or_default().to_ascii_lowercase();
+        let program = env("TERM_PROGRAM").unwrap_or_default().to_ascii_lowercase();
+        if env("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || program == "ghostty" {
+            ArtProtocol::Kitty
+        } else if program == "iterm.app" || program == "wezterm" {
+            ArtProtocol::Iterm2
+        } else if term.starts_with("foot") || term.starts_with("mlterm") || term.contains("sixel") || program == "contour" {
+            ArtProtocol::Sixel
+        } else if term == "linux" || term == "dumb" || term.starts_with("vt") {
+            ArtProtocol::Ascii
+        } else {
+            ArtProtocol::HalfBlocks
+        }
+    }
+
+    /// Resolves the `album_art` setting: `auto`, `off` or a protocol name.
+    /// Returns `None` when art is turned off; unknown values mean `auto`.
+    pub fn from_setting(setting: &str, env: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        match setting.trim().to_ascii_lowercase().as_str() {
+            "off" | "none" | "false" | "no" => None,
+            "kitty" => Some(ArtProtocol::Kitty),
+            "iterm2" | "iterm" => Some(ArtProtocol::Iterm2),
+            "sixel" => Some(ArtProtocol::Sixel),
+            "halfblock" | "halfblocks" | "blocks" => Some(ArtProtocol::HalfBlocks),
+            "ascii" | "text" => Some(ArtProtocol::Ascii),
+            _ => Some(Self::detect(env)),
+        }
+    }
+}
+
+/// Cache key shared by all tracks of one album: album artist and album when
+/// tagged, otherwise the track's folder (and album, if any).
+pub fn album_key(track_path: &str, tags: &TrackTags) -> String {
+    match (&tags.album_artist, &tags.album) {
+        (Some(artist), Some(album)) => format!("{}\u{1f}{}", artist.to_lowercase(), album.to_lowercase()),
+        _ => {
+            let folder = Path::new(track_path).parent().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+            format!("{}\u{1f}{}", folder, tags.album.as_deref().unwrap_or("").to_lowercase())
+        }
+    }
+}
+
+/// The embedded `APIC` picture, preferring the front cover.
+pub fn embedded_art(track_path: &str) -> Option<Vec<u8>> {
+    let tag = id3::Tag::read_from_path(track_path).ok()?;
+    let pictures: Vec<&id3::frame::Picture> = tag.pictures().collect();
+    pictures.iter()
+        .find(|p| p.picture_type == id3::frame::PictureType::CoverFront)
+        .or_else(|| pictures.first())
+        .map(|p| p.data.clone())
+}
+
+/// A `cover.jpg`/`folder.jpg`-style image in the track's folder.
+pub fn sidecar_art(track_path: &str) -> Option<std::path::PathBuf> {
+    let folder = Path::new(track_path).parent()?;
+    let folder = if folder.as_os_str().is_empty() { Path::new(".") } else { folder };
+    let names: HashMap<String, std::path::PathBuf> = fs::read_dir(folder).ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| (e.file_name().to_string_lossy().to_lowercase(), e.path()))
+        .collect();
+    SIDECAR_NAMES.iter().find_map(|name| names.get(*name).cloned())
+}
+
+/// Loads and downsizes the cover of a track: embedded art first, then sidecars.
+pub fn load_art(track_path: &str) -> Option<RgbaImage> {
+    let data = embedded_art(track_path)
+        .or_else(|| sidecar_art(track_path).and_then(|p| fs::read(p).ok()))?;
+    let image = image::load_from_memory(&data).ok()?.to_rgba8();
+    let (width, height) = (image.width(), image.height());
+    if width.max(height) <= MAX_ART_SIZE {
+        return Some(image);
+    }
+    let scale = MAX_ART_SIZE as f64 / width.max(height) as f64;
+    let scaled_width = ((width as f64 * scale).round() as u32).max(1);
+    let scaled_height = ((height as f64 * scale).round() as u32).max(1);
+    Some(image::imageops::thumbnail(&image, scaled_width, scaled_height))
+}
+
+/// Cover art per album, loaded by a background thread.
+///
+/// Like the metadata cache, lookups never touch the disk: misses are queued
+/// and `get` returns `None` until the worker has decoded the picture.
+pub struct ArtCache {
+    entries: Arc<Mutex<HashMap<String, Option<Arc<RgbaImage>>>>>,
+    pending: Arc<Mutex<HashSet<String>>>,
+    requests: Sender<(String, String)>,
+}
+
+impl ArtCache {
+    pub fn new() -> Self {
+        let entries = Arc::new(Mutex::new(HashMap::new()));
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+        let (requests, rx) = mpsc::channel::<(String, String)>();
+
+        std::thread::spawn({
+            let entries = entries.clone();
+            let pending = pending.clone();
+            move || {
+                for (key, track_path) in rx {
+                    let art = load_art(&track_path).map(Arc::new);
+                    entries.lock().unwrap().insert(key.clone(), art);
+                    pending.lock().unwrap().remove(&key);
+                }
+            }
+        });
+
+        Self { entries, pending, requests }
+    }
+
+    /// `Some(None)` when the album has no art, `None` while it is being loaded.
+    pub fn get(&self, key: &str, track_path: &str) -> Option<Option<Arc<RgbaImage>>> {
+        if let Some(art) = self.entries.lock().unwrap().get(key) {
+            return Some(art.clone());
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.insert(key.to_string()) {
+            let _ = self.requests.send((key.to_string(), track_path.to_string()));
+        }
+        None
+    }
+}
+
+/// Pixel size of one terminal cell, from the terminal when it reports it.
+pub fn cell_size() -> (u16, u16) {
+    match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => {
+            ((size.width / size.columns).max(1), (size.height / size.rows).max(1))
+        }
+        _ => (8, 16),
+    }
+}
+
+/// The largest area at the top of `panel` that shows `image` undistorted,
+/// using at most half the panel's height. Centred horizontally.
+pub fn art_area(panel: Rect, image_size: (u32, u32), cell: (u16, u16)) -> Option<Rect> {
+    let max_rows = panel.height / 2;
+    if max_rows < 3 || panel.width < 6 || image_size.0 == 0 || image_size.1 == 0 {
+        return None;
+    }
+    // Image width over height, measured in cells
+    let aspect = image_size.0 as f64 / image_size.1 as f64 * cell.1 as f64 / cell.0 as f64;
+    let mut rows = max_rows;
+    let mut cols = (rows as f64 * aspect).round() as u16;
+    if cols > panel.width {
+        cols = panel.width;
+        rows = ((cols as f64 / aspect).round() as u16).clamp(1, max_rows);
+    }
+    let cols = cols.max(1);
+    Some(Rect::new(panel.x + (panel.width - cols) / 2, panel.y, cols, rows))
+}
+
+fn resize(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    image::imageops::resize(image, width.max(1), height.max(1), image::imageops::FilterType::Triangle)
+}
+
+/// Blends a pixel onto black.
+fn opaque(pixel: &image::Rgba<u8>) -> (u8, u8, u8) {
+    let [r, g, b, a] = pixel.0;
+    let blend = |c: u8| (c as u16 * a as u16 / 255) as u8;
+    (blend(r), blend(g), blend(b))
+}
+
+/// Renders the image with `▀`: the foreground is the top pixel, the background the bottom one.
+pub fn half_block_lines(image: &RgbaImage, cols: u16, rows: u16) -> Vec<Line<'static>> {
+    let scaled = resize(image, cols as u32, rows as u32 * 2);
+    (0..rows as u32)
+        .map(|row| {
+            let spans: Vec<Span> = (0..cols as u32)
+                .map(|col| {
+                    let (tr, tg, tb) = opaque(scaled.get_pixel(col, row * 2));
+                    let (br, bg, bb) = opaque(scaled.get_pixel(col, row * 2 + 1));
+                    Span::styled("▀", Style::default().fg(Color::Rgb(tr, tg, tb)).bg(Color::Rgb(br, bg, bb)))
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect()
+}
+
+/// Renders the image as characters of increasing density, one per cell.
+pub fn ascii_lines(image: &RgbaImage, cols: u16, rows: u16) -> Vec<Line<'static>> {
+    const RAMP: &[u8] = b" .:-=+*#%@";
+    let scaled = resize(image, cols as u32, rows as u32);
+    (0..rows as u32)
+        .map(|row| {
+            let text: String = (0..cols as u32)
+                .map(|col| {
+                    let (r, g, b) = opaque(scaled.get_pixel(col, row));
+                    let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+                    RAMP[(luma as usize * (RAMP.len() - 1) + 127) / 255] as char
+                })
+                .collect();
+            Line::from(text)
+        })
+        .collect()
+}
+
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn encode_png(image: &RgbaImage) -> Vec<u8> {
+    let mut data = Vec::new();
+    let _ = image::codecs::png::PngEncoder::new(&mut data)
+        .write_image(image.as_raw(), image.width(), image.height(), image::ExtendedColorType::Rgba8);
+    data
+}
+
+/// kitty graphics: a PNG sent in 4096-byte base64 chunks and scaled to the cell area.
+pub fn kitty_sequence(image: &RgbaImage, cols: u16, rows: u16) -> String {
+    let payload = base64_encode(&encode_png(image));
+    let chunks: Vec<&str> = payload.as_bytes().chunks(4096).map(|c| std::str::from_utf8(c).unwrap_or("")).collect();
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        if i == 0 {
+            out.push_str(&format!("\x1b_Ga=T,f=100,i={},c={},r={},C=1,q=2,m={};{}\x1b\\", KITTY_IMAGE_ID, cols, rows, more, chunk));
+        } else {
+            out.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk));
+        }
+    }
+    out
+}
+
+/// Removes the cover placed with `kitty_sequence` and frees its data.
+pub fn kitty_delete_sequence() -> String {
+    format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", KITTY_IMAGE_ID)
+}
+
+/// iTerm2 inline image of the given size in cells.
+pub fn iterm2_sequence(image: &RgbaImage, cols: u16, rows: u16) -> String {
+    let png = encode_png(image);
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+        png.len(), cols, rows, base64_encode(&png),
+    )
+}
+
+/// Palette index in a 6x7x6 colour cube (252 colours).
+fn sixel_colour((r, g, b): (u8, u8, u8)) -> usize {
+    let level = |c: u8, levels: u32| ((c as u32 * (levels - 1) + 127) / 255) as usize;
+    level(r, 6) * 42 + level(g, 7) * 6 + level(b, 6)
+}
+
+fn push_sixel_run(out: &mut String, bits: u8, run: usize) {
+    let c = (63 + bits) as char;
+    if run > 3 {
+        out.push_str(&format!("!{}{}", run, c));
+    } else {
+        out.extend(std::iter::repeat_n(c, run));
+    }
+}
+
+/// DEC sixel image, quantised to a fixed 252-colour palette.
+pub fn sixel_sequence(image: &RgbaImage) -> String {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let indices: Vec<usize> = image.pixels().map(|p| sixel_colour(opaque(p))).collect();
+    let mut used = vec![false; 252];
+    for &i in &indices {
+        used[i] = true;
+    }
+
+    let mut out = format!("\x1bP0;1q\"1;1;{};{}", width, height);
+    for (i, _) in used.iter().enumerate().filter(|(_, u)| **u) {
+        let (r, g, b) = (i / 42, i / 6 % 7, i % 6);
+        out.push_str(&format!("#{};2;{};{};{}", i, r * 100 / 5, g * 100 / 6, b * 100 / 5));
+    }
+    for band in (0..height).step_by(6) {
+        let band_rows = (height - band).min(6);
+        let mut colours: Vec<usize> = (band..band + band_rows)
+            .flat_map(|y| indices[y * width..(y + 1) * width].iter().copied())
+            .collect();
+        colours.sort_unstable();
+        colours.dedup();
+        for (n, colour) in colours.iter().enumerate() {
+            if n > 0 {
+                out.push('$');
+            }
+            out.push_str(&format!("#{}", colour));
+            let mut run = (0u8, 0usize);
+            for x in 0..width {
+                let bits = (0..band_rows)
+                    .filter(|dy| indices[(band + dy) * width + x] == *colour)
+                    .fold(0u8, |bits, dy| bits | 1 << dy);
+                if bits == run.0 || run.1 == 0 {
+                    run = (bits, run.1 + 1);
+                } else {
+                    push_sixel_run(&mut out, run.0, run.1);
+                    run = (bits, 1);
+                }
+            }
+            push_sixel_run(&mut out, run.0, run.1);
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Draws the cover of the playing track in the Now Playing panel.
+///
+/// Text renderings are drawn by ratatui like any other widget. Image
+/// protocols write the picture after `terminal.draw` into an area the widgets
+/// leave blank, and only when the album or the area changes. Either way the
+/// rendering is kept, so it is only redone when the album or panel changes.
+pub struct ArtView {
+    /// `None` when album art is turned off.
+    pub protocol: Option<ArtProtocol>,
+    pub cell: (u16, u16),
+    shown: Option<(String, Rect)>,
+    encoded: Option<(String, Rect, String)>,
+    lines: Option<(String, Rect, Vec<Line<'static>>)>,
+}
+
+impl ArtView {
+    pub fn new(protocol: Option<ArtProtocol>) -> Self {
+        Self { protocol, cell: cell_size(), shown: None, encoded: None, lines: None }
+    }
+
+    /// The text rendering for half-block and ASCII output; `None` for image protocols.
+    pub fn text_lines(&mut self, key: &str, image: &RgbaImage, area: Rect) -> Option<Vec<Line<'static>>> {
+        let render: fn(&RgbaImage, u16, u16) -> Vec<Line<'static>> = match self.protocol? {
+            ArtProtocol::HalfBlocks => half_block_lines,
+            ArtProtocol::Ascii => ascii_lines,
+            _ => return None,
+        };
+        if !matches!(&self.lines, Some((k, a, _)) if k == key && *a == area) {
+            self.lines = Some((key.to_string(), area, render(image, area.width, area.height)));
+        }
+        self.lines.as_ref().map(|(_, _, lines)| lines.clone())
+    }
+
+    /// Takes down the picture on screen when the next one differs. Call before drawing.
+    pub fn prepare(&mut self, terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, wanted: Option<(&str, Rect)>) -> io::Result<()> {
+        let unchanged = match (&self.shown, wanted) {
+            (Some((key, area)), Some((wanted_key, wanted_area))) => key == wanted_key && *area == wanted_area,
+            (None, _) => true,
+            (Some(_), None) => false,
+        };
+        if !unchanged {
+            self.hide(terminal)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the picture into `area` unless it is already there. Call after drawing.
+    pub fn present(&mut self, out: &mut impl Write, key: &str, image: &RgbaImage, area: Rect) -> io::Result<()> {
+        let Some(protocol) = self.protocol.filter(|p| p.is_graphics()) else {
+            return Ok(());
+        };
+        if matches!(&self.shown, Some((k, a)) if k == key && *a == area) {
+            return Ok(());
+        }
+        if !matches!(&self.encoded, Some((k, a, _)) if k == key && *a == area) {
+            let scaled = resize(image, (area.width * self.cell.0) as u32, (area.height * self.cell.1) as u32);
+            let sequence = match protocol {
+                ArtProtocol::Kitty => kitty_sequence(&scaled, area.width, area.height),
+                ArtProtocol::Iterm2 => iterm2_sequence(&scaled, area.width, area.height),
+                _ => sixel_sequence(&scaled),
+            };
+            self.encoded = Some((key.to_string(), area, sequence));
+        }
+        if let Some((_, _, sequence)) = &self.encoded {
+            queue!(out, MoveTo(area.x, area.y))?;
+            out.write_all(sequence.as_bytes())?;
+            out.flush()?;
+        }
+        self.shown = Some((key.to_string(), area));
+        Ok(())
+    }
+
+    /// Removes the picture, e.g. before a full-screen dialog draws over it.
+    pub fn hide(&mut self, terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> io::Result<()> {
+        if self.shown.take().is_none() {
+            return Ok(());
+        }
+        if self.protocol == Some(ArtProtocol::Kitty) {
+            let backend = terminal.backend_mut();
+            backend.write_all(kitty_delete_sequence().as_bytes())?;
+            backend.flush()?;
+        } else {
+            // Sixel and iTerm2 pixels live in the cells; redraw all of them
+            terminal.clear()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use crate::alarm::Alarm;
+use crate::equalizer::{EqPreset, EqSettings};
+use crate::replaygain::{ReplayGainMode, ReplayGainSettings};
+use crate::silence::{parse_seconds, SilenceSettings};
+use std::fs;
+
+pub const CONFIG_FILE: &str = ".rdaio_config";
+
+/// User settings read from `.rdaio_config`.
+///
+/// The file is a list of `key = value` lines; `#` starts a comment and
+/// unknown keys are ignored so older builds can read newer files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Persist track metadata to `.rdaio_meta_cache` between runs.
+    pub metadata_cache: bool,
+    /// Folders indexed by the music library (`library_root`, may be repeated).
+    pub library_roots: Vec<String>,
+    /// Watch library roots and the loaded folder for added/removed files.
+    pub watch_library: bool,
+    /// Moving on before this percentage of a track counts as a skip.
+    pub skip_threshold: u32,
+    /// Identify tracks by an audio hash too, so renamed files keep their play counts.
+    pub stats_audio_hash: bool,
+    /// Also store ratings in the file as an ID3 `POPM` frame.
+    pub write_ratings_to_tags: bool,
+    /// Default template of the rename-by-pattern screen.
+    pub rename_template: String,
+    /// Default pattern of the tags-from-file-name screen.
+    pub filename_pattern: String,
+    /// How to draw cover art: `auto`, `off`, `kitty`, `iterm2`, `sixel`, `halfblocks` or `ascii`.
+    pub album_art: String,
+    /// Equaliser state (`eq_enabled`, `eq_bands`, `eq_preamp`), saved when the editor closes.
+    pub eq: EqSettings,
+    /// User equaliser presets (`eq_preset = Name: g1 ... g10`, may be repeated).
+    pub eq_presets: Vec<EqPreset>,
+    /// ReplayGain mode (`replaygain`), preamp (`replaygain_preamp`) and clipping prevention (`replaygain_clipping`).
+    pub replay_gain: ReplayGainSettings,
+    /// Playback speed while an A–B loop is active (`ab_loop_speed`); 1.0 leaves the speed alone.
+    pub ab_loop_speed: f32,
+    /// Skipping silence in tracks (`skip_silence`, `skip_silence_threshold`, `skip_silence_min`) and
+    /// trimming it at their ends (`trim_silence`, `trim_silence_threshold`, `trim_silence_min`).
+    pub silence: SilenceSettings,
+    /// Wake-up alarms (`alarm = 07:00, Playlist, 60, on`, may be repeated), saved when the alarm screen closes.
+    pub alarms: Vec<Alarm>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            metadata_cache: true,
+            library_roots: Vec::new(),
+            watch_library: false,
+            skip_threshold: 50,
+            stats_audio_hash: false,
+            write_ratings_to_tags: false,
+            rename_template: crate::renamer::DEFAULT_RENAME_TEMPLATE.to_string(),
+            filename_pattern: crate::filename_tags::DEFAULT_FILENAME_PATTERN.to_string(),
+            album_art: String::from("auto"),
+            eq: EqSettings::default(),
+            eq_presets: Vec::new(),
+            replay_gain: ReplayGainSettings::default(),
+            ab_loop_speed: 1.0,
+            silence: SilenceSettings::default(),
+            alarms: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        Self::load_from(CONFIG_FILE)
+    }
+
+    /// Reads a config file, falling back to defaults when it does not exist.
+    pub fn load_from(path: &str) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Library roots to scan; the working directory when none are configured.
+    pub fn library_roots_or_default(&self) -> Vec<String> {
+        if self.library_roots.is_empty() {
+            vec![String::from(".")]
+        } else {
+            self.library_roots.clone()
+        }
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut config = Self::default();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "metadata_cache" => config.metadata_cache = parse_bool(value).unwrap_or(config.metadata_cache),
+                "watch_library" => config.watch_library = parse_bool(value).unwrap_or(config.watch_library),
+                "write_ratings_to_tags" => config.write_ratings_to_tags = parse_bool(value).unwrap_or(config.write_ratings_to_tags),
+                "stats_audio_hash" => config.stats_audio_hash = parse_bool(value).unwrap_or(config.stats_audio_hash),
+                "skip_threshold" => {
+                    if let Ok(percent) = value.trim_end_matches('%').trim().parse::<u32>() {
+                        config.skip_threshold = percent.min(100);
+                    }
+                },
+                "rename_template" if !value.is_empty() => config.rename_template = value.to_string(),
+                "filename_pattern" if !value.is_empty() => config.filename_pattern = value.to_string(),
+                "album_art" if !value.is_empty() => config.album_art = value.to_string(),
+                "library_root" if !value.is_empty() => config.library_roots.push(value.to_string()),
+                "replaygain" => config.replay_gain.mode = ReplayGainMode::parse(value).unwrap_or(config.replay_gain.mode),
+                "replaygain_preamp" => {
+                    if let Ok(db) = value.trim_end_matches("dB").trim().parse::<f32>() {
+                        config.replay_gain.preamp_db = db.clamp(-15.0, 15.0);
+                    }
+                },
+                "replaygain_clipping" => config.replay_gain.prevent_clipping = parse_bool(value).unwrap_or(config.replay_gain.prevent_clipping),
+                "ab_loop_speed" => {
+                    if let Ok(speed) = value.trim_end_matches(['x', 'X']).trim().parse::<f32>() {
+                        config.ab_loop_speed = speed.clamp(crate::timestretch::MIN_SPEED, crate::timestretch::MAX_SPEED);
+                    }
+                },
+                "skip_silence" => config.silence.skip.enabled = parse_bool(value).unwrap_or(config.silence.skip.enabled),
+                "trim_silence" => config.silence.trim.enabled = parse_bool(value).unwrap_or(config.silence.trim.enabled),
+                "skip_silence_threshold" | "trim_silence_threshold" => {
+                    if let Ok(db) = value.trim_end_matches("dB").trim().parse::<f32>() {
+                        let rule = if key.trim() == "skip_silence_threshold" { &mut config.silence.skip } else { &mut config.silence.trim };
+                        rule.threshold_db = db.clamp(-96.0, -20.0);
+                    }
+                },
+                "skip_silence_min" => config.silence.skip.min_ms = parse_seconds(value).unwrap_or(config.silence.skip.min_ms),
+                "trim_silence_min" => config.silence.trim.min_ms = parse_seconds(value).unwrap_or(config.silence.trim.min_ms),
+                "alarm" => {
+                    if let Some(alarm) = Alarm::parse(value) {
+                        config.alarms.push(alarm);
+                    }
+                },
+                "eq_enabled" => config.eq.enabled = parse_bool(value).unwrap_or(config.eq.enabled),
+                "eq_bands" => {
+                    if let Some(gains) = crate::equalizer::parse_gains(value) {
+                        config.eq.gains = gains;
+                    }
+                },
+                "eq_preamp" => {
+                    if value.eq_ignore_ascii_case("auto") {
+                        config.eq.preamp_db = None;
+                    } else if let Ok(db) = value.trim_end_matches("dB").trim().parse::<f32>() {
+                        config.eq.preamp_db = Some(db.clamp(-crate::equalizer::EQ_MAX_DB, crate::equalizer::EQ_MAX_DB));
+                    }
+                },
+                "eq_preset" => {
+                    if let Some(preset) = EqPreset::parse(value) {
+                        config.eq_presets.retain(|p| p.name != preset.name);
+                        config.eq_presets.push(preset);
+                    }
+                },
+                _ => {}
+            }
+        }
+        config
+    }
+}
+
+/// Config lines for the equaliser state and user presets.
+pub fn eq_values(eq: &EqSettings, presets: &[EqPreset]) -> Vec<(&'static str, Vec<String>)> {
+    vec![
+        ("eq_enabled", vec![eq.enabled.to_string()]),
+        ("eq_bands", vec![crate::equalizer::format_gains(&eq.gains)]),
+        ("eq_preamp", vec![eq.preamp_db.map(|db| db.to_string()).unwrap_or_else(|| String::from("auto"))]),
+        ("eq_preset", presets.iter().map(EqPreset::to_config_value).collect()),
+    ]
+}
+
+/// Config lines for the alarms.
+pub fn alarm_values(alarms: &[Alarm]) -> Vec<(&'static str, Vec<String>)> {
+    vec![("alarm", alarms.iter().map(Alarm::to_config_value).collect())]
+}
+
+/// Replaces every line of the given keys in a config file, keeping comments
+/// and other settings. Values of a key go where its first line was, or at the end.
+pub fn save_values(path: &str, values: &[(&str, Vec<String>)]) -> Result<(), Box<dyn std::error::Error>> {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    let mut written: Vec<&str> = Vec::new();
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let key = line.split_once('=').map(|(k, _)| k.trim()).filter(|_| !line.trim_start().starts_with('#'));
+        match key.and_then(|k| values.iter().find(|(name, _)| *name == k)) {
+            Some((name, new_values)) => {
+                if !written.contains(name) {
+                    lines.extend(new_values.iter().map(|v| format!("{} = {}", name, v)));
+                    written.push(name);
+                }
+            },
+            None => lines.push(line.to_string()),
+        }
+    }
+    for (name, new_values) in values {
+        if !written.contains(name) {
+            lines.extend(new_values.iter().map(|v| format!("{} = {}", name, v)));
+        }
+    }
+    let mut content = lines.join("\n");
+    content.push('\n');
+    fs::write(path, content)?;
+    Ok(())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
@@ -0,0 +1,331 @@
+use crossterm::event;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, Paragraph, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Modifier, Color}, text::{Line, Span}};
+use std::sync::{Arc, Mutex};
+use std::{io, error::Error};
+
+/// Centre frequencies of the ten bands, in Hz.
+pub const EQ_FREQUENCIES: [f32; 10] = [31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+
+/// Gains (and a manual preamp) are limited to ±12 dB.
+pub const EQ_MAX_DB: f32 = 12.0;
+
+/// Bandwidth of each peaking filter; about an octave, so neighbouring bands blend smoothly.
+const EQ_Q: f64 = 1.41;
+
+/// A named set of band gains.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EqPreset {
+    pub name: String,
+    pub gains: [f32; 10],
+}
+
+impl EqPreset {
+    fn new(name: &str, gains: [f32; 10]) -> Self {
+        Self { name: name.to_string(), gains }
+    }
+
+    /// Parses `Name: g1 g2 ... g10` as written in the `eq_preset` config key.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (name, gains) = value.rsplit_once(':')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        Some(Self { name: name.to_string(), gains: parse_gains(gains)? })
+    }
+
+    pub fn to_config_value(&self) -> String {
+        format!("{}: {}", self.name, format_gains(&self.gains))
+    }
+}
+
+/// Parses ten space-separated dB values, clamped to ±12 dB.
+pub fn parse_gains(value: &str) -> Option<[f32; 10]> {
+    let values: Vec<f32> = value.split_whitespace().map(|v| v.parse::<f32>().ok()).collect::<Option<_>>()?;
+    let gains: [f32; 10] = values.try_into().ok()?;
+    Some(gains.map(|g| g.clamp(-EQ_MAX_DB, EQ_MAX_DB)))
+}
+
+pub fn format_gains(gains: &[f32; 10]) -> String {
+    gains.iter().map(|g| format!("{}", g)).collect::<Vec<_>>().join(" ")
+}
+
+pub fn builtin_presets() -> Vec<EqPreset> {
+    vec![
+        EqPreset::new("Flat", [0.0; 10]),
+        EqPreset::new("Rock", [5.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 3.0, 4.0, 5.0]),
+        EqPreset::new("Bass Boost", [6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+        EqPreset::new("Vocal", [-2.0, -2.0, -1.0, 1.0, 3.0, 4.0, 3.0, 1.0, 0.0, -1.0]),
+        EqPreset::new("Loudness", [6.0, 4.0, 1.0, 0.0, -1.0, 0.0, 0.0, 1.0, 4.0, 5.0]),
+    ]
+}
+
+/// Equaliser state shared between the UI and the playback thread.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EqSettings {
+    pub enabled: bool,
+    pub gains: [f32; 10],
+    /// Gain before the filters in dB; `None` lowers it by the largest boost
+    /// so boosted bands cannot clip.
+    pub preamp_db: Option<f32>,
+}
+
+impl Default for EqSettings {
+    fn default() -> Self {
+        Self { enabled: false, gains: [0.0; 10], preamp_db: None }
+    }
+}
+
+impl EqSettings {
+    pub fn effective_preamp_db(&self) -> f32 {
+        match self.preamp_db {
+            Some(db) => db,
+            None => -self.gains.iter().copied().fold(0.0, f32::max),
+        }
+    }
+}
+
+/// Biquad filter in direct form I, one state per channel. `a` holds a1 and
+/// a2 normalised by a0.
+#[derive(Clone, Debug)]
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    state: Vec<[f64; 4]>,
+}
+
+impl Biquad {
+    pub fn new(b: [f64; 3], a: [f64; 2], channels: usize) -> Self {
+        Self { b, a, state: vec![[0.0; 4]; channels] }
+    }
+
+    /// RBJ peaking filter.
+    fn peaking(frequency: f32, gain_db: f32, sample_rate: u32, channels: usize) -> Self {
+        let a = 10f64.powf(gain_db as f64 / 40.0);
+        let w0 = 2.0 * std::f64::consts::PI * frequency as f64 / sample_rate as f64;
+        let alpha = w0.sin() / (2.0 * EQ_Q);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha / a;
+        Self::new(
+            [(1.0 + alpha * a) / a0, -2.0 * cos / a0, (1.0 - alpha * a) / a0],
+            [-2.0 * cos / a0, (1.0 - alpha / a) / a0],
+            channels,
+        )
+    }
+
+    pub fn process(&mut self, x: f64, channel: usize) -> f64 {
+        let [x1, x2, y1, y2] = self.state[channel];
+        let y = self.b[0] * x + self.b[1] * x1 + self.b[2] * x2 - self.a[0] * y1 - self.a[1] * y2;
+        self.state[channel] = [x, x1, y, y1];
+        y
+    }
+}
+
+/// The ten-band equaliser of the playback thread. Filters are rebuilt when
+/// the shared settings, sample rate or channel count change.
+pub struct Equalizer {
+    settings: Arc<Mutex<EqSettings>>,
+    active: Option<(EqSettings, u32, usize)>,
+    filters: Vec<Biquad>,
+}
+
+impl Equalizer {
+    pub fn new(settings: Arc<Mutex<EqSettings>>) -> Self {
+        Self { settings, active: None, filters: Vec::new() }
+    }
+
+    /// Filters interleaved samples in place.
+    pub fn process(&mut self, samples: &mut [f32], channels: usize, sample_rate: u32) {
+        let settings = self.settings.lock().unwrap().clone();
+        if !settings.enabled || channels == 0 {
+            self.active = None;
+            return;
+        }
+        if self.active.as_ref() != Some(&(settings.clone(), sample_rate, channels)) {
+            // Bands at or above Nyquist would be unstable; they are left out
+            self.filters = EQ_FREQUENCIES.iter().zip(settings.gains)
+                .filter(|(f, g)| **f < sample_rate as f32 * 0.45 && *g != 0.0)
+                .map(|(f, g)| Biquad::peaking(*f, g, sample_rate, channels))
+                .collect();
+            self.active = Some((settings.clone(), sample_rate, channels));
+        }
+        let preamp = 10f64.powf(settings.effective_preamp_db() as f64 / 20.0);
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let channel = i % channels;
+            let mut value = *sample as f64 * preamp;
+            for filter in &mut self.filters {
+                value = filter.process(value, channel);
+            }
+            *sample = value.clamp(-1.0, 1.0) as f32;
+        }
+    }
+}
+
+/// Draws one vertical slider per band; `selected` is highlighted.
+fn slider_lines(labels: &[String], values: &[f32], selected: usize, height: usize) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    let centre = height / 2;
+    for row in 0..height {
+        // Row 0 is +12 dB, the middle row 0 dB, the last row -12 dB
+        let step = EQ_MAX_DB / centre.max(1) as f32;
+        let level = step * (centre as f32 - row as f32);
+        let spans: Vec<Span> = values.iter()
+            .enumerate()
+            .flat_map(|(i, value)| {
+                let filled = (level > 0.0 && *value >= level - step / 2.0) || (level < 0.0 && *value <= level + step / 2.0);
+                let symbol = if row == centre { "──┼──" } else if filled { " ███ " } else { "  │  " };
+                let style = match (i == selected, filled || row == centre) {
+                    (true, true) => Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+                    (true, false) => Style::default().fg(Color::Yellow),
+                    (false, true) => Style::default().fg(Color::Green),
+                    (false, false) => Style::default().fg(Color::DarkGray),
+                };
+                [Span::styled(symbol, style), Span::raw(" ")]
+            })
+            .collect();
+        lines.push(Line::from(spans));
+    }
+    let label_line = |texts: Vec<String>| {
+        Line::from(texts.into_iter().enumerate().map(|(i, text)| {
+            let style = if i == selected { Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD) } else { Style::default() };
+            Span::styled(format!("{:^5} ", text), style)
+        }).collect::<Vec<_>>())
+    };
+    lines.push(label_line(values.iter().map(|v| format!("{:+}", v)).collect()));
+    lines.push(label_line(labels.to_vec()));
+    lines
+}
+
+fn band_label(frequency: f32) -> String {
+    if frequency >= 1000.0 {
+        format!("{}k", frequency / 1000.0)
+    } else {
+        format!("{}", frequency)
+    }
+}
+
+/// Equaliser editor with sliders. Changes apply to playback immediately;
+/// presets saved here are added to `user_presets`. Returns when closed.
+pub fn edit_equalizer(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    settings: &Arc<Mutex<EqSettings>>,
+    user_presets: &mut Vec<EqPreset>,
+    debug_mode: bool,
+) -> Result<(), Box<dyn Error>> {
+    // Column 0 is the preamp, 1..=10 the bands
+    let mut selected = 1usize;
+    let mut preset_index: Option<usize> = None;
+    let mut naming: Option<String> = None;
+    let mut message = String::new();
+    let mut labels = vec![String::from("pre")];
+    labels.extend(EQ_FREQUENCIES.iter().map(|f| band_label(*f)));
+
+    loop {
+        let current = settings.lock().unwrap().clone();
+        let presets: Vec<EqPreset> = builtin_presets().into_iter().chain(user_presets.iter().cloned()).collect();
+        let matching = presets.iter().find(|p| p.gains == current.gains).map(|p| p.name.clone());
+        let mut values = vec![current.effective_preamp_db()];
+        values.extend(current.gains);
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Min(8),
+                    Constraint::Length(4),
+                ].as_ref())
+                .split(f.size());
+
+            let height = (chunks[0].height as usize).saturating_sub(4).max(3) | 1;
+            let title = format!(
+                "Equaliser [{}] - preset: {} - preamp: {}{}",
+                if current.enabled { "on" } else { "off" },
+                matching.as_deref().unwrap_or("custom"),
+                if current.preamp_db.is_none() { "auto" } else { "manual" },
+                if message.is_empty() { String::new() } else { format!(" - {}", message) },
+            );
+            let sliders = Paragraph::new(slider_lines(&labels, &values, selected, height))
+                .block(Block::default().borders(Borders::ALL).title(title));
+            f.render_widget(sliders, chunks[0]);
+
+            let help = match &naming {
+                Some(name) => format!("Preset name: {}_   [Enter] Save  [ESC] Cancel", name),
+                None => String::from("[Left/Right] Band  [Up/Down] ±1 dB  [PgUp/PgDn] ±3 dB  [0] Zero band  [Space] On/Off  [P] Next preset  [A] Auto preamp  [S] Save preset  [ESC/Enter] Back"),
+            };
+            let help = Paragraph::new(help)
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[1]);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(200))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            if let Some(name) = naming.as_mut() {
+                match key.code {
+                    event::KeyCode::Esc => naming = None,
+                    event::KeyCode::Backspace => {
+                        name.pop();
+                    },
+                    // ':' separates the name from the gains in the config file
+                    event::KeyCode::Char(c) if c != ':' => name.push(c),
+                    event::KeyCode::Enter if !name.trim().is_empty() => {
+                        let preset = EqPreset { name: name.trim().to_string(), gains: current.gains };
+                        user_presets.retain(|p| !p.name.eq_ignore_ascii_case(&preset.name));
+                        message = format!("saved preset {}", preset.name);
+                        if debug_mode {
+                            println!("[DEBUG] EQ preset saved: {}", preset.to_config_value());
+                        }
+                        user_presets.push(preset);
+                        naming = None;
+                    },
+                    _ => {}
+                }
+                continue;
+            }
+
+            let mut eq = settings.lock().unwrap();
+            let adjust = |eq: &mut EqSettings, delta: f32| {
+                if selected == 0 {
+                    let preamp = eq.effective_preamp_db();
+                    eq.preamp_db = Some((preamp + delta).clamp(-EQ_MAX_DB, EQ_MAX_DB));
+                } else {
+                    let gain = &mut eq.gains[selected - 1];
+                    *gain = (*gain + delta).clamp(-EQ_MAX_DB, EQ_MAX_DB);
+                }
+            };
+            match key.code {
+                event::KeyCode::Esc | event::KeyCode::Enter => return Ok(()),
+                event::KeyCode::Left => selected = if selected == 0 { 10 } else { selected - 1 },
+                event::KeyCode::Right => selected = if selected == 10 { 0 } else { selected + 1 },
+                event::KeyCode::Up => adjust(&mut eq, 1.0),
+                event::KeyCode::Down => adjust(&mut eq, -1.0),
+                event::KeyCode::PageUp => adjust(&mut eq, 3.0),
+                event::KeyCode::PageDown => adjust(&mut eq, -3.0),
+                event::KeyCode::Char('0') => {
+                    let value = if selected == 0 { eq.effective_preamp_db() } else { eq.gains[selected - 1] };
+                    adjust(&mut eq, -value);
+                },
+                event::KeyCode::Char(' ') => eq.enabled = !eq.enabled,
+                event::KeyCode::Char('a') | event::KeyCode::Char('A') => {
+                    eq.preamp_db = if eq.preamp_db.is_some() { None } else { Some(eq.effective_preamp_db()) };
+                },
+                event::KeyCode::Char('p') | event::KeyCode::Char('P') => {
+                    let next = preset_index.map(|i| (i + 1) % presets.len()).unwrap_or(0);
+                    eq.gains = presets[next].gains;
+                    eq.enabled = true;
+                    preset_index = Some(next);
+                    message.clear();
+                },
+                event::KeyCode::Char('s') | event::KeyCode::Char('S') => {
+                    naming = Some(String::new());
+                },
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::metadata::{format_number_pair, TrackTags};
+use crate::tag_editor::{parse_number_pair, write_tags, TagField, TagVersion};
+use crossterm::event;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, Paragraph, ListState, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Color}};
+use std::path::Path;
+use std::{io, error::Error};
+
+pub const DEFAULT_FILENAME_PATTERN: &str = "{artist} - {title}";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Literal(String),
+    /// `None` for `{ignore}`, which matches text that is thrown away.
+    Field(Option<TagField>),
+}
+
+fn field_for(name: &str) -> Result<Option<TagField>, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "title" => Ok(Some(TagField::Title)),
+        "artist" => Ok(Some(TagField::Artist)),
+        "album" => Ok(Some(TagField::Album)),
+        "albumartist" | "album_artist" => Ok(Some(TagField::AlbumArtist)),
+        "track" => Ok(Some(TagField::Track)),
+        "disc" => Ok(Some(TagField::Disc)),
+        "year" => Ok(Some(TagField::Year)),
+        "genre" => Ok(Some(TagField::Genre)),
+        "comment" => Ok(Some(TagField::Comment)),
+        "ignore" | "*" => Ok(None),
+        other => Err(format!("unknown placeholder {{{}}}", other)),
+    }
+}
+
+fn tokenize(pattern: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = pattern;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('{') {
+            let end = after.find('}').ok_or("unclosed '{' in pattern")?;
+            if matches!(tokens.last(), Some(Token::Field(_))) {
+                return Err(String::from("two placeholders need text between them"));
+            }
+            tokens.push(Token::Field(field_for(&after[..end])?));
+            rest = &after[end + 1..];
+        } else {
+            let end = rest.find('{').unwrap_or(rest.len());
+            tokens.push(Token::Literal(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_numeric(field: Option<TagField>) -> bool {
+    matches!(field, Some(TagField::Track | TagField::Disc | TagField::Year))
+}
+
+/// Matches tokens against text; placeholders take as little as possible,
+/// except the last one which takes the rest.
+fn match_tokens(tokens: &[Token], text: &str) -> Option<Vec<(Option<TagField>, String)>> {
+    match tokens.first() {
+        None => text.is_empty().then(Vec::new),
+        Some(Token::Literal(literal)) => match_tokens(&tokens[1..], text.strip_prefix(literal.as_str())?),
+        Some(Token::Field(field)) => {
+            let ends: Vec<usize> = if tokens.len() == 1 {
+                vec![text.len()]
+            } else {
+                text.char_indices().skip(1).map(|(i, _)| i).chain(std::iter::once(text.len())).collect()
+            };
+            for end in ends {
+                let value = text[..end].trim();
+                if value.is_empty() {
+                    continue;
+                }
+                if is_numeric(*field) && !value.chars().all(|c| c.is_ascii_digit()) {
+                    // Digits only grow into non-digits from here
+                    break;
+                }
+                if let Some(mut rest) = match_tokens(&tokens[1..], &text[end..]) {
+                    rest.insert(0, (*field, value.to_string()));
+                    return Some(rest);
+                }
+            }
+            None
+        }
+    }
+}
+
+/// Parses the end of a path with a pattern such as `{artist} - {title}` or
+/// `{album}/{track}. {title}`; each `/` in the pattern consumes one directory.
+/// The extension is ignored. Returns `Ok(None)` when the path does not match.
+pub fn parse_filename(pattern: &str, path: &str, underscores_as_spaces: bool) -> Result<Option<Vec<(TagField, String)>>, String> {
+    let tokens = tokenize(pattern)?;
+    let depth = pattern.matches('/').count() + 1;
+    let path = Path::new(path);
+    let mut parts: Vec<String> = path.parent()
+        .map(|p| p.iter().map(|c| c.to_string_lossy().to_string()).collect())
+        .unwrap_or_default();
+    parts.push(path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default());
+    if parts.len() < depth {
+        return Ok(None);
+    }
+    let mut text = parts[parts.len() - depth..].join("/");
+    if underscores_as_spaces {
+        text = text.replace('_', " ");
+    }
+    Ok(match_tokens(&tokens, &text).map(|values| {
+        values.into_iter()
+            .filter_map(|(field, value)| field.map(|f| (f, value)))
+            .collect()
+    }))
+}
+
+/// The parsed fields that would actually change the file. Unless `overwrite`
+/// is set, fields that already have a value are kept. A track or disc number
+/// without a total keeps the file's existing total.
+pub fn changes_for(parsed: &[(TagField, String)], current: &TrackTags, overwrite: bool) -> Vec<(TagField, String)> {
+    parsed.iter()
+        .map(|(field, value)| {
+            let total = match field {
+                TagField::Track => current.total_tracks,
+                TagField::Disc => current.total_discs,
+                _ => None,
+            };
+            match (parse_number_pair(value), total) {
+                (Some((number, None)), Some(total)) => (*field, format_number_pair(Some(number), Some(total))),
+                _ => (*field, value.clone()),
+            }
+        })
+        .filter(|(field, value)| {
+            let existing = field.current(current);
+            if overwrite { existing != *value } else { existing.is_empty() }
+        })
+        .collect()
+}
+
+/// One row of the preview table.
+struct PreviewRow {
+    path: String,
+    changes: Result<Option<Vec<(TagField, String)>>, String>,
+}
+
+fn build_preview(tracks: &[(String, TrackTags)], pattern: &str, underscores: bool, overwrite: bool) -> Vec<PreviewRow> {
+    tracks.iter()
+        .map(|(path, tags)| {
+            let changes = parse_filename(pattern, path, underscores)
+                .map(|parsed| parsed.map(|fields| changes_for(&fields, tags, overwrite)));
+            PreviewRow { path: path.clone(), changes }
+        })
+        .collect()
+}
+
+/// Dry-run preview of tags parsed from file names; Enter writes them.
+/// Returns the paths whose tags were written.
+pub fn tags_from_filenames(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    paths: &[String],
+    pattern: &str,
+    debug_mode: bool,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut pattern = pattern.to_string();
+    let mut underscores = false;
+    let mut overwrite = false;
+    // Tags are read once; the preview is rebuilt on every keystroke
+    let mut tracks: Vec<(String, TrackTags)> = paths.iter().map(|p| (p.clone(), TrackTags::read(p))).collect();
+    let mut rows = build_preview(&tracks, &pattern, underscores, overwrite);
+    let mut list_state = ListState::default();
+    list_state.select(if rows.is_empty() { None } else { Some(0) });
+    let mut written: Vec<String> = Vec::new();
+    let mut message = String::new();
+    let mut dirty = false;
+
+    loop {
+        if dirty {
+            rows = build_preview(&tracks, &pattern, underscores, overwrite);
+            dirty = false;
+        }
+        let to_write = rows.iter().filter(|r| matches!(&r.changes, Ok(Some(c)) if !c.is_empty())).count();
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Min(5),
+                    Constraint::Length(4),
+                ].as_ref())
+                .split(f.size());
+
+            let input = Paragraph::new(format!("{}_", pattern))
+                .block(Block::default().borders(Borders::ALL).title("Pattern ({artist} {albumartist} {album} {title} {track} {disc} {year} {genre} {comment} {ignore}, '/' = folder)"));
+            f.render_widget(input, chunks[0]);
+
+            let items: Vec<ListItem> = rows.iter()
+                .map(|row| {
+                    let name = Path::new(&row.path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                    let (text, style) = match &row.changes {
+                        Err(e) => (e.clone(), Style::default().fg(Color::Red)),
+                        Ok(None) => (String::from("no match"), Style::default().fg(Color::Red)),
+                        Ok(Some(changes)) if changes.is_empty() => (String::from("nothing to change"), Style::default().fg(Color::DarkGray)),
+                        Ok(Some(changes)) => (
+                            changes.iter().map(|(field, value)| format!("{}={}", field.label(), value)).collect::<Vec<_>>().join("  "),
+                            Style::default().fg(Color::Green),
+                        ),
+                    };
+                    ListItem::new(format!("{:<40} {}", name, text)).style(style)
+                })
+                .collect();
+            let options = format!(
+                "underscores as spaces: {}, overwrite existing: {}",
+                if underscores { "on" } else { "off" },
+                if overwrite { "on" } else { "off" },
+            );
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(format!("Preview - {} files to tag ({}) {}", to_write, options, message)))
+                .highlight_symbol(">> ");
+            f.render_stateful_widget(list, chunks[1], &mut list_state);
+
+            let help = Paragraph::new("[Type] Pattern  [Up/Down] Scroll  [Ctrl+U] Underscores as spaces  [Ctrl+O] Overwrite existing tags  [Enter] Write tags  [ESC] Cancel")
+                .block(Block::default().borders(Borders::ALL).title("Tags from File Name"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[2]);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(200))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            let ctrl = key.modifiers.contains(event::KeyModifiers::CONTROL);
+            match key.code {
+                event::KeyCode::Esc => {
+                    if written.is_empty() {
+                        return Err("Cancelled".into());
+                    }
+                    return Ok(written);
+                },
+                event::KeyCode::Down if !rows.is_empty() => {
+                    let i = list_state.selected().map(|i| if i >= rows.len() - 1 { 0 } else { i + 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Up if !rows.is_empty() => {
+                    let i = list_state.selected().map(|i| if i == 0 { rows.len() - 1 } else { i - 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Char('u') if ctrl => {
+                    underscores = !underscores;
+                    dirty = true;
+                },
+                event::KeyCode::Char('o') if ctrl => {
+                    overwrite = !overwrite;
+                    dirty = true;
+                },
+                event::KeyCode::Backspace => {
+                    pattern.pop();
+                    dirty = true;
+                },
+                event::KeyCode::Char(c) if !ctrl => {
+                    pattern.push(c);
+                    dirty = true;
+                },
+                event::KeyCode::Enter if to_write > 0 => {
+                    let mut failures = Vec::new();
+                    for row in &rows {
+                        if let Ok(Some(changes)) = &row.changes
+                            && !changes.is_empty()
+                        {
+                            match write_tags(&row.path, changes, TagVersion::Keep) {
+                                Ok(()) => written.push(row.path.clone()),
+                                Err(e) => failures.push(format!("{}: {}", row.path, e)),
+                            }
+                        }
+                    }
+                    if debug_mode {
+                        println!("[DEBUG] Tags from file names: {} written, {} failed", written.len(), failures.len());
+                    }
+                    if failures.is_empty() {
+                        return Ok(written);
+                    }
+                    // Written files now preview as having nothing to change
+                    for (path, tags) in tracks.iter_mut().filter(|(p, _)| written.contains(p)) {
+                        *tags = TrackTags::read(path);
+                    }
+                    message = format!("- {} written, failed: {}", written.len(), failures[0]);
+                    dirty = true;
+                },
+                _ => {}
+            }
+        }
+    }
+}
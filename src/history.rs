@@ -0,0 +1,307 @@
+use crate::metadata::{escape_field, format_duration, unescape_field};
+use crate::stats::ListenOutcome;
+use crate::QueueAction;
+use chrono::{Local, TimeZone};
+use crossterm::event;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, Paragraph, ListState, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Modifier, Color}};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::error::Error;
+
+pub const HISTORY_FILE: &str = ".rdaio_history";
+pub const HISTORY_CSV_EXPORT: &str = "rdaio_history.csv";
+pub const HISTORY_JSON_EXPORT: &str = "rdaio_history.json";
+
+/// How a listen in the history ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryStatus {
+    Completed,
+    Skipped,
+    Stopped,
+}
+
+impl HistoryStatus {
+    pub fn from_outcome(outcome: ListenOutcome) -> Self {
+        match outcome {
+            ListenOutcome::Played => HistoryStatus::Completed,
+            ListenOutcome::Skipped => HistoryStatus::Skipped,
+            ListenOutcome::Ignored => HistoryStatus::Stopped,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HistoryStatus::Completed => "completed",
+            HistoryStatus::Skipped => "skipped",
+            HistoryStatus::Stopped => "stopped",
+        }
+    }
+
+    fn from_label(label: &str) -> Option<Self> {
+        match label {
+            "completed" => Some(HistoryStatus::Completed),
+            "skipped" => Some(HistoryStatus::Skipped),
+            "stopped" => Some(HistoryStatus::Stopped),
+            _ => None,
+        }
+    }
+}
+
+/// One listen, recorded when it ends.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    /// Unix time (seconds) the track started.
+    pub started_at: u64,
+    pub status: HistoryStatus,
+    pub listened_ms: u64,
+    pub path: String,
+    /// Tags at the time of listening, so renamed or deleted files stay recognisable.
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+impl HistoryEntry {
+    /// Local date and time, e.g. `2024-05-01 14:32`.
+    pub fn local_time(&self) -> String {
+        match Local.timestamp_opt(self.started_at as i64, 0).single() {
+            Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+            None => self.started_at.to_string(),
+        }
+    }
+
+    fn rfc3339(&self) -> String {
+        match Local.timestamp_opt(self.started_at as i64, 0).single() {
+            Some(time) => time.to_rfc3339(),
+            None => self.started_at.to_string(),
+        }
+    }
+
+    pub fn label(&self) -> String {
+        let file_name = std::path::Path::new(&self.path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.path.clone());
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.clone(),
+            _ => file_name,
+        }
+    }
+}
+
+fn encode_history_line(entry: &HistoryEntry) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}",
+        entry.started_at,
+        entry.status.label(),
+        entry.listened_ms,
+        escape_field(&entry.path),
+        escape_field(entry.artist.as_deref().unwrap_or("")),
+        escape_field(entry.title.as_deref().unwrap_or("")),
+    )
+}
+
+fn decode_history_line(line: &str) -> Option<HistoryEntry> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 6 {
+        return None;
+    }
+    let optional = |field: &str| (!field.is_empty()).then(|| unescape_field(field));
+    Some(HistoryEntry {
+        started_at: fields[0].parse().ok()?,
+        status: HistoryStatus::from_label(fields[1])?,
+        listened_ms: fields[2].parse().ok()?,
+        path: unescape_field(fields[3]),
+        artist: optional(fields[4]),
+        title: optional(fields[5]),
+    })
+}
+
+/// Appends one entry; the file only ever grows, oldest first.
+pub fn append_history(path: &str, entry: &HistoryEntry) -> Result<(), Box<dyn Error>> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", encode_history_line(entry))?;
+    Ok(())
+}
+
+pub fn load_history(path: &str) -> Vec<HistoryEntry> {
+    match fs::read_to_string(path) {
+        Ok(content) => content.lines().filter_map(decode_history_line).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn history_to_csv(entries: &[HistoryEntry]) -> String {
+    let mut out = String::from("played_at,status,listened_seconds,artist,title,path\n");
+    for entry in entries {
+        out.push_str(&format!(
+            "{},{},{},{},{},{}\n",
+            entry.rfc3339(),
+            entry.status.label(),
+            entry.listened_ms / 1000,
+            csv_field(entry.artist.as_deref().unwrap_or("")),
+            csv_field(entry.title.as_deref().unwrap_or("")),
+            csv_field(&entry.path),
+        ));
+    }
+    out
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn history_to_json(entries: &[HistoryEntry]) -> String {
+    let optional = |value: &Option<String>| value.as_deref().map(json_string).unwrap_or_else(|| String::from("null"));
+    let items: Vec<String> = entries.iter()
+        .map(|entry| format!(
+            "  {{\"played_at\": {}, \"timestamp\": {}, \"status\": {}, \"listened_seconds\": {}, \"artist\": {}, \"title\": {}, \"path\": {}}}",
+            json_string(&entry.rfc3339()),
+            entry.started_at,
+            json_string(entry.status.label()),
+            entry.listened_ms / 1000,
+            optional(&entry.artist),
+            optional(&entry.title),
+            json_string(&entry.path),
+        ))
+        .collect();
+    if items.is_empty() {
+        String::from("[]\n")
+    } else {
+        format!("[\n{}\n]\n", items.join(",\n"))
+    }
+}
+
+/// Recently played screen, newest first. Tracks can be re-enqueued and the
+/// whole history exported to CSV or JSON.
+pub fn browse_history(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    debug_mode: bool,
+) -> Result<QueueAction, Box<dyn Error>> {
+    let mut entries = load_history(HISTORY_FILE);
+    entries.reverse();
+    let mut list_state = ListState::default();
+    list_state.select(if entries.is_empty() { None } else { Some(0) });
+    let mut message = format!("{} listens", entries.len());
+
+    loop {
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Min(5),
+                    Constraint::Length(4),
+                ].as_ref())
+                .split(f.size());
+
+            let items: Vec<ListItem> = entries.iter()
+                .map(|entry| {
+                    let style = match entry.status {
+                        HistoryStatus::Completed => Style::default(),
+                        HistoryStatus::Skipped => Style::default().fg(Color::DarkGray),
+                        HistoryStatus::Stopped => Style::default().fg(Color::Gray),
+                    };
+                    ListItem::new(format!(
+                        "{}  {:<9}  {:>6}  {}",
+                        entry.local_time(),
+                        entry.status.label(),
+                        format_duration(entry.listened_ms),
+                        entry.label(),
+                    )).style(style)
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(format!("Recently Played - {}", message)))
+                .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+                .highlight_symbol(">> ");
+            f.render_stateful_widget(list, chunks[0], &mut list_state);
+
+            let help = Paragraph::new("[Up/Down/PgUp/PgDn] Select  [Enter] Play Now  [A] Append  [C] Export CSV  [J] Export JSON  [ESC] Back")
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[1]);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(200))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            let selected = list_state.selected().and_then(|i| entries.get(i)).map(|e| e.path.clone());
+            match key.code {
+                event::KeyCode::Esc => {
+                    return Err("Cancelled".into());
+                },
+                event::KeyCode::Down if !entries.is_empty() => {
+                    let i = list_state.selected().map(|i| if i >= entries.len() - 1 { 0 } else { i + 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Up if !entries.is_empty() => {
+                    let i = list_state.selected().map(|i| if i == 0 { entries.len() - 1 } else { i - 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::PageDown if !entries.is_empty() => {
+                    let i = list_state.selected().map(|i| (i + 20).min(entries.len() - 1)).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::PageUp if !entries.is_empty() => {
+                    let i = list_state.selected().map(|i| i.saturating_sub(20)).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Enter => {
+                    if let Some(path) = selected {
+                        if debug_mode {
+                            println!("[DEBUG] History -> play {}", path);
+                        }
+                        return Ok(QueueAction::PlayNow(vec![path]));
+                    }
+                },
+                event::KeyCode::Char('a') | event::KeyCode::Char('A') => {
+                    if let Some(path) = selected {
+                        return Ok(QueueAction::Append(vec![path]));
+                    }
+                },
+                event::KeyCode::Char('c') | event::KeyCode::Char('C') | event::KeyCode::Char('j') | event::KeyCode::Char('J') => {
+                    // Exports are chronological, oldest first, like the history file
+                    let chronological: Vec<HistoryEntry> = entries.iter().rev().cloned().collect();
+                    let (file, data) = if matches!(key.code, event::KeyCode::Char('c') | event::KeyCode::Char('C')) {
+                        (HISTORY_CSV_EXPORT, history_to_csv(&chronological))
+                    } else {
+                        (HISTORY_JSON_EXPORT, history_to_json(&chronological))
+                    };
+                    message = match fs::write(file, data) {
+                        Ok(()) => format!("exported {} listens to {}", entries.len(), file),
+                        Err(e) => format!("export failed: {}", e),
+                    };
+                    if debug_mode {
+                        println!("[DEBUG] History {}", message);
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
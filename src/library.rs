@@ -0,0 +1,287 @@
+use crate::metadata::TrackTags;
+use crate::metadata_cache::{decode_cache_line, encode_cache_line, file_stamp, CachedTags};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub const LIBRARY_FILE: &str = ".rdaio_library";
+
+pub const UNKNOWN_ARTIST: &str = "Unknown Artist";
+pub const UNKNOWN_ALBUM: &str = "Unknown Album";
+pub const UNKNOWN_GENRE: &str = "Unknown Genre";
+pub const UNKNOWN_YEAR: &str = "Unknown Year";
+
+/// One indexed file with the stamp it was scanned at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LibraryTrack {
+    pub path: String,
+    pub mtime: u64,
+    pub size: u64,
+    pub tags: TrackTags,
+}
+
+impl LibraryTrack {
+    /// Album artist if present, otherwise the track artist.
+    pub fn artist_name(&self) -> &str {
+        self.tags.album_artist.as_deref()
+            .or(self.tags.artist.as_deref())
+            .unwrap_or(UNKNOWN_ARTIST)
+    }
+
+    pub fn album_name(&self) -> &str {
+        self.tags.album.as_deref().unwrap_or(UNKNOWN_ALBUM)
+    }
+
+    pub fn genre_name(&self) -> &str {
+        self.tags.genre.as_deref().unwrap_or(UNKNOWN_GENRE)
+    }
+
+    pub fn year_name(&self) -> String {
+        self.tags.year.map(|y| y.to_string()).unwrap_or_else(|| UNKNOWN_YEAR.to_string())
+    }
+
+    pub fn title_or_file_name(&self) -> String {
+        self.tags.title.clone().unwrap_or_else(|| {
+            Path::new(&self.path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| self.path.clone())
+        })
+    }
+}
+
+/// How the first library pane groups tracks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrowseMode {
+    Artist,
+    Genre,
+    Year,
+}
+
+impl BrowseMode {
+    pub fn next(self) -> Self {
+        match self {
+            BrowseMode::Artist => BrowseMode::Genre,
+            BrowseMode::Genre => BrowseMode::Year,
+            BrowseMode::Year => BrowseMode::Artist,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BrowseMode::Artist => "Artist",
+            BrowseMode::Genre => "Genre",
+            BrowseMode::Year => "Year",
+        }
+    }
+
+    fn category_of(self, track: &LibraryTrack) -> String {
+        match self {
+            BrowseMode::Artist => track.artist_name().to_string(),
+            BrowseMode::Genre => track.genre_name().to_string(),
+            BrowseMode::Year => track.year_name(),
+        }
+    }
+}
+
+/// Albums are keyed by artist as well, so two "Greatest Hits" stay apart.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AlbumKey {
+    pub artist: String,
+    pub album: String,
+}
+
+/// The indexed music collection, persisted to `.rdaio_library`.
+#[derive(Clone, Debug, Default)]
+pub struct Library {
+    pub tracks: Vec<LibraryTrack>,
+    /// Bumped on every change so views know when to rebuild their lists.
+    pub generation: u64,
+}
+
+impl Library {
+    pub fn load_from(path: &str) -> Self {
+        let mut tracks: Vec<LibraryTrack> = match fs::read_to_string(path) {
+            Ok(content) => content
+                .lines()
+                .filter_map(decode_cache_line)
+                .map(|(path, c)| LibraryTrack { path, mtime: c.mtime, size: c.size, tags: c.tags })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        // upsert_file relies on path order
+        tracks.sort_by(|a, b| a.path.cmp(&b.path));
+        Self { tracks, generation: 1 }
+    }
+
+    pub fn save_to(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut data = String::new();
+        for track in &self.tracks {
+            let entry = CachedTags { mtime: track.mtime, size: track.size, tags: track.tags.clone() };
+            data.push_str(&encode_cache_line(&track.path, &entry));
+            data.push('\n');
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn replace_tracks(&mut self, mut tracks: Vec<LibraryTrack>) {
+        tracks.sort_by(|a, b| a.path.cmp(&b.path));
+        self.tracks = tracks;
+        self.generation += 1;
+    }
+
+    /// Adds or refreshes a single file, re-reading tags only if its stamp changed.
+    /// Returns whether the library was modified.
+    pub fn upsert_file(&mut self, path: &str) -> bool {
+        let Some((mtime, size)) = file_stamp(path) else {
+            return false;
+        };
+        match self.tracks.binary_search_by(|t| t.path.as_str().cmp(path)) {
+            Ok(idx) => {
+                let track = &mut self.tracks[idx];
+                if track.mtime == mtime && track.size == size {
+                    return false;
+                }
+                track.mtime = mtime;
+                track.size = size;
+                track.tags = TrackTags::read(path);
+            }
+            Err(idx) => {
+                let tags = TrackTags::read(path);
+                self.tracks.insert(idx, LibraryTrack { path: path.to_string(), mtime, size, tags });
+            }
+        }
+        self.generation += 1;
+        true
+    }
+
+    /// Removes a file, or every track below a removed directory. Returns how many went.
+    pub fn remove_path(&mut self, path: &str) -> usize {
+        let dir_prefix = format!("{}{}", path.trim_end_matches(std::path::MAIN_SEPARATOR), std::path::MAIN_SEPARATOR);
+        let before = self.tracks.len();
+        self.tracks.retain(|t| t.path != path && !t.path.starts_with(&dir_prefix));
+        let removed = before - self.tracks.len();
+        if removed > 0 {
+            self.generation += 1;
+        }
+        removed
+    }
+
+    /// Sorted, de-duplicated first-pane entries for a browse mode.
+    pub fn categories(&self, mode: BrowseMode) -> Vec<String> {
+        let set: BTreeSet<String> = self.tracks.iter().map(|t| mode.category_of(t)).collect();
+        let mut categories: Vec<String> = set.into_iter().collect();
+        if mode == BrowseMode::Year {
+            // Newest first reads better for years
+            categories.reverse();
+        }
+        categories
+    }
+
+    pub fn albums(&self, mode: BrowseMode, category: &str) -> Vec<AlbumKey> {
+        let set: BTreeSet<AlbumKey> = self.tracks.iter()
+            .filter(|t| mode.category_of(t) == category)
+            .map(|t| AlbumKey { artist: t.artist_name().to_string(), album: t.album_name().to_string() })
+            .collect();
+        set.into_iter().collect()
+    }
+
+    /// Tracks of an album in disc/track order (falling back to path).
+    pub fn album_tracks(&self, mode: BrowseMode, category: &str, album: &AlbumKey) -> Vec<&LibraryTrack> {
+        let mut tracks: Vec<&LibraryTrack> = self.tracks.iter()
+            .filter(|t| mode.category_of(t) == category
+                && t.artist_name() == album.artist
+                && t.album_name() == album.album)
+            .collect();
+        tracks.sort_by(|a, b| {
+            (a.tags.disc.unwrap_or(0), a.tags.track.unwrap_or(0), &a.path)
+                .cmp(&(b.tags.disc.unwrap_or(0), b.tags.track.unwrap_or(0), &b.path))
+        });
+        tracks
+    }
+}
+
+/// Recursively lists MP3 files below `root` as absolute paths.
+pub fn find_mp3_files(root: &Path, out: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_mp3_files(&path, out);
+        } else if let Some(ext) = path.extension()
+            && ext.eq_ignore_ascii_case("mp3")
+        {
+            let path = std::path::absolute(&path).unwrap_or(path);
+            out.push(path.to_string_lossy().to_string());
+        }
+    }
+}
+
+/// Indexes every MP3 below the given roots.
+///
+/// Files whose mtime and size match an entry in `previous` keep their tags;
+/// only new or changed files are reopened. Files missing from disk drop out.
+pub fn scan_roots(roots: &[String], previous: &[LibraryTrack], scanned: &AtomicUsize, reread: &AtomicUsize) -> Vec<LibraryTrack> {
+    let known: HashMap<&str, &LibraryTrack> = previous.iter().map(|t| (t.path.as_str(), t)).collect();
+    let mut paths = Vec::new();
+    for root in roots {
+        find_mp3_files(Path::new(root), &mut paths);
+    }
+    paths.sort();
+    paths.dedup();
+    paths.into_iter()
+        .filter_map(|path| {
+            let (mtime, size) = file_stamp(&path)?;
+            scanned.fetch_add(1, Ordering::Relaxed);
+            let tags = match known.get(path.as_str()) {
+                Some(old) if old.mtime == mtime && old.size == size => old.tags.clone(),
+                _ => {
+                    reread.fetch_add(1, Ordering::Relaxed);
+                    TrackTags::read(&path)
+                }
+            };
+            Some(LibraryTrack { path, mtime, size, tags })
+        })
+        .collect()
+}
+
+/// Runs library scans on a background thread and reports progress.
+#[derive(Clone, Default)]
+pub struct LibraryScanner {
+    pub running: Arc<AtomicBool>,
+    pub scanned: Arc<AtomicUsize>,
+    /// Files that were new or changed and had their tags read again.
+    pub reread: Arc<AtomicUsize>,
+}
+
+impl LibraryScanner {
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Starts a scan unless one is already running. The result replaces the
+    /// library contents and is saved to `save_path`.
+    pub fn start(&self, roots: Vec<String>, library: Arc<Mutex<Library>>, save_path: Option<String>) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.scanned.store(0, Ordering::SeqCst);
+        self.reread.store(0, Ordering::SeqCst);
+        let scanner = self.clone();
+        std::thread::spawn(move || {
+            let previous = library.lock().unwrap().tracks.clone();
+            let tracks = scan_roots(&roots, &previous, &scanner.scanned, &scanner.reread);
+            let mut library = library.lock().unwrap();
+            library.replace_tracks(tracks);
+            if let Some(path) = save_path {
+                let _ = library.save_to(&path);
+            }
+            scanner.running.store(false, Ordering::SeqCst);
+        });
+    }
+}
@@ -0,0 +1,224 @@
+use crate::library::{AlbumKey, BrowseMode, Library, LibraryScanner, LIBRARY_FILE};
+use crate::QueueAction;
+use crossterm::event;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, Paragraph, ListState, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Modifier, Color}};
+use std::{io, error::Error};
+use std::sync::{Arc, Mutex};
+
+/// Moves a list selection by one with wrap-around.
+fn step(state: &mut ListState, len: usize, down: bool) {
+    if len == 0 {
+        state.select(None);
+        return;
+    }
+    let i = match state.selected() {
+        Some(i) if down => if i >= len - 1 { 0 } else { i + 1 },
+        Some(i) => if i == 0 { len - 1 } else { i - 1 },
+        None => 0,
+    };
+    state.select(Some(i));
+}
+
+fn clamp(state: &mut ListState, len: usize) {
+    match state.selected() {
+        _ if len == 0 => state.select(None),
+        Some(i) if i >= len => state.select(Some(len - 1)),
+        None => state.select(Some(0)),
+        _ => {}
+    }
+}
+
+/// Three-pane library browser: Artist/Genre/Year -> Album -> Track.
+pub fn browse_library(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    library: &Arc<Mutex<Library>>,
+    scanner: &LibraryScanner,
+    roots: &[String],
+    debug_mode: bool,
+) -> Result<QueueAction, Box<dyn Error>> {
+    let mut mode = BrowseMode::Artist;
+    let mut focus = 0usize;
+    let mut states = [ListState::default(), ListState::default(), ListState::default()];
+    let mut seen_generation = u64::MAX;
+    let mut categories: Vec<String> = Vec::new();
+    let mut albums: Vec<AlbumKey> = Vec::new();
+    // (path, label) of the tracks in the selected album
+    let mut tracks: Vec<(String, String)> = Vec::new();
+    let mut dirty = true;
+
+    loop {
+        {
+            let lib = library.lock().unwrap();
+            if lib.generation != seen_generation {
+                seen_generation = lib.generation;
+                dirty = true;
+            }
+            if dirty {
+                categories = lib.categories(mode);
+                clamp(&mut states[0], categories.len());
+                albums = match states[0].selected().and_then(|i| categories.get(i)) {
+                    Some(category) => lib.albums(mode, category),
+                    None => Vec::new(),
+                };
+                clamp(&mut states[1], albums.len());
+                tracks = match (
+                    states[0].selected().and_then(|i| categories.get(i)),
+                    states[1].selected().and_then(|i| albums.get(i)),
+                ) {
+                    (Some(category), Some(album)) => lib.album_tracks(mode, category, album)
+                        .into_iter()
+                        .map(|t| {
+                            let number = t.tags.track.map(|n| format!("{:02}. ", n)).unwrap_or_default();
+                            (t.path.clone(), format!("{}{}", number, t.title_or_file_name()))
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                clamp(&mut states[2], tracks.len());
+                dirty = false;
+            }
+        }
+
+        let scanning = scanner.is_running();
+        let scanned = scanner.scanned.load(std::sync::atomic::Ordering::Relaxed);
+        let reread = scanner.reread.load(std::sync::atomic::Ordering::Relaxed);
+        let track_count = library.lock().unwrap().tracks.len();
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Min(10),
+                    Constraint::Length(4),
+                ].as_ref())
+                .split(f.size());
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Percentage(30),
+                    Constraint::Percentage(35),
+                    Constraint::Percentage(35),
+                ].as_ref())
+                .split(chunks[0]);
+
+            let pane_style = |pane: usize| if pane == focus {
+                Style::default().fg(Color::Cyan)
+            } else {
+                Style::default()
+            };
+            let show_album_artist = mode != BrowseMode::Artist;
+            let category_items: Vec<ListItem> = categories.iter().map(|c| ListItem::new(c.clone())).collect();
+            let album_items: Vec<ListItem> = albums.iter()
+                .map(|a| if show_album_artist {
+                    ListItem::new(format!("{} ({})", a.album, a.artist))
+                } else {
+                    ListItem::new(a.album.clone())
+                })
+                .collect();
+            let track_items: Vec<ListItem> = tracks.iter().map(|(_, label)| ListItem::new(label.clone())).collect();
+
+            let status = if scanning {
+                format!("Scanning... {} files, {} changed", scanned, reread)
+            } else {
+                format!("{} tracks", track_count)
+            };
+            let lists = [
+                (category_items, format!("{} - {}", mode.label(), status)),
+                (album_items, String::from("Albums")),
+                (track_items, String::from("Tracks")),
+            ];
+            for (pane, (items, title)) in lists.into_iter().enumerate() {
+                let list = List::new(items)
+                    .block(Block::default().borders(Borders::ALL).border_style(pane_style(pane)).title(title))
+                    .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+                    .highlight_symbol(">> ");
+                f.render_stateful_widget(list, panes[pane], &mut states[pane]);
+            }
+
+            let help = Paragraph::new("[Up/Down] Navigate  [Left/Right] Pane  [Tab] Artist/Genre/Year  [Enter] Play  [R] Replace Queue  [A] Append  [P] Play Now  [U] Rescan  [ESC] Back")
+                .block(Block::default().borders(Borders::ALL).title("Library"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[1]);
+        })?;
+
+        // Tracks to enqueue: the selected track in the track pane, the whole album otherwise
+        let selection = |from_track: bool| -> Vec<String> {
+            if from_track && let Some(i) = states[2].selected() {
+                return tracks.iter().skip(i).map(|(path, _)| path.clone()).collect();
+            }
+            tracks.iter().map(|(path, _)| path.clone()).collect()
+        };
+
+        if event::poll(std::time::Duration::from_millis(200))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                event::KeyCode::Esc => {
+                    return Err("Cancelled".into());
+                },
+                event::KeyCode::Down | event::KeyCode::Up => {
+                    let len = [categories.len(), albums.len(), tracks.len()][focus];
+                    step(&mut states[focus], len, key.code == event::KeyCode::Down);
+                    // Changing a parent pane resets its children
+                    for child in states.iter_mut().skip(focus + 1) {
+                        child.select(Some(0));
+                    }
+                    dirty = true;
+                },
+                event::KeyCode::Left => {
+                    focus = focus.saturating_sub(1);
+                },
+                event::KeyCode::Right => {
+                    focus = (focus + 1).min(2);
+                },
+                event::KeyCode::Tab => {
+                    mode = mode.next();
+                    for state in states.iter_mut() {
+                        state.select(Some(0));
+                    }
+                    focus = 0;
+                    dirty = true;
+                },
+                event::KeyCode::Enter => {
+                    if focus < 2 {
+                        focus += 1;
+                    } else {
+                        let tracks = selection(true);
+                        if !tracks.is_empty() {
+                            return Ok(QueueAction::PlayNow(tracks));
+                        }
+                    }
+                },
+                event::KeyCode::Char('r') | event::KeyCode::Char('R') => {
+                    let tracks = selection(false);
+                    if !tracks.is_empty() {
+                        return Ok(QueueAction::Replace(tracks));
+                    }
+                },
+                event::KeyCode::Char('a') | event::KeyCode::Char('A') => {
+                    let tracks = selection(false);
+                    if !tracks.is_empty() {
+                        return Ok(QueueAction::Append(tracks));
+                    }
+                },
+                event::KeyCode::Char('p') | event::KeyCode::Char('P') => {
+                    let tracks = selection(focus == 2);
+                    if !tracks.is_empty() {
+                        return Ok(QueueAction::PlayNow(tracks));
+                    }
+                },
+                event::KeyCode::Char('u') | event::KeyCode::Char('U') => {
+                    if debug_mode {
+                        println!("[DEBUG] Library rescan of {:?}", roots);
+                    }
+                    scanner.start(roots.to_vec(), library.clone(), Some(LIBRARY_FILE.to_string()));
+                },
+                _ => {}
+            }
+        }
+    }
+}
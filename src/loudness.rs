@@ -0,0 +1,541 @@
+use crate::album_art::album_key;
+use crate::equalizer::Biquad;
+use crate::library::{Library, LibraryTrack};
+use crate::metadata::TrackTags;
+use crate::tag_editor::write_tag_safely;
+use crossterm::event;
+use id3::TagLike;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, Gauge, List, ListItem, ListState, Paragraph, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Color}};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::{io, error::Error};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::default::{get_codecs, get_probe};
+
+/// ReplayGain 2 plays everything at -18 LUFS.
+pub const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+
+/// BS.1770 gating: blocks quieter than -70 LUFS, or 10 LU below the ungated mean, are ignored.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const RELATIVE_GATE_LU: f64 = -10.0;
+
+/// True peak is measured on a 4x oversampled signal.
+const OVERSAMPLING: usize = 4;
+const TAPS_PER_PHASE: usize = 12;
+
+fn lufs(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+/// The two-stage K-weighting filter of ITU-R BS.1770 for any sample rate.
+fn k_weighting(sample_rate: u32, channels: usize) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+    // High shelf modelling the acoustic effect of the head
+    let (f0, gain_db, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain_db / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        channels,
+    );
+    // High pass (RLB weighting)
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new([1.0, -2.0, 1.0], [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0], channels);
+    [shelf, high_pass]
+}
+
+/// Windowed-sinc interpolation filter for the oversampled true peak.
+fn interpolation_filter() -> Vec<f64> {
+    let len = OVERSAMPLING * TAPS_PER_PHASE;
+    let centre = (len - 1) as f64 / 2.0;
+    (0..len)
+        .map(|i| {
+            let t = (i as f64 - centre) / OVERSAMPLING as f64;
+            let sinc = if t == 0.0 { 1.0 } else { (std::f64::consts::PI * t).sin() / (std::f64::consts::PI * t) };
+            let window = 0.5 - 0.5 * (2.0 * std::f64::consts::PI * (i as f64 + 0.5) / len as f64).cos();
+            sinc * window
+        })
+        .collect()
+}
+
+/// Measures integrated loudness and true peak of interleaved audio.
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: [Biquad; 2],
+    /// Frames per 100 ms step; a 400 ms block is four steps, giving 75% overlap.
+    step_frames: usize,
+    step_sum: f64,
+    step_count: usize,
+    steps: Vec<f64>,
+    interpolation: Vec<f64>,
+    history: Vec<VecDeque<f64>>,
+    peak: f64,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let channels = channels.max(1);
+        Self {
+            channels,
+            filters: k_weighting(sample_rate, channels),
+            step_frames: (sample_rate as usize / 10).max(1),
+            step_sum: 0.0,
+            step_count: 0,
+            steps: Vec::new(),
+            interpolation: interpolation_filter(),
+            history: vec![VecDeque::from(vec![0.0; TAPS_PER_PHASE]); channels],
+            peak: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, &sample) in frame.iter().enumerate() {
+                let sample = sample as f64;
+                let weighted = self.filters.iter_mut().fold(sample, |x, f| f.process(x, channel));
+                self.step_sum += weighted * weighted;
+                self.track_peak(channel, sample);
+            }
+            self.step_count += 1;
+            if self.step_count == self.step_frames {
+                self.steps.push(self.step_sum / self.step_frames as f64);
+                self.step_sum = 0.0;
+                self.step_count = 0;
+            }
+        }
+    }
+
+    fn track_peak(&mut self, channel: usize, sample: f64) {
+        self.peak = self.peak.max(sample.abs());
+        let history = &mut self.history[channel];
+        history.pop_back();
+        history.push_front(sample);
+        for phase in 0..OVERSAMPLING {
+            let value: f64 = history.iter()
+                .enumerate()
+                .map(|(k, x)| self.interpolation[phase + k * OVERSAMPLING] * x)
+                .sum();
+            self.peak = self.peak.max(value.abs());
+        }
+    }
+
+    pub fn finish(self) -> Loudness {
+        // Each gating block is the mean of four consecutive 100 ms steps
+        let blocks = self.steps.windows(4).map(|w| w.iter().sum::<f64>() / 4.0).collect();
+        Loudness { blocks, true_peak: self.peak }
+    }
+}
+
+/// Measurement of one track (or, merged, an album).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Loudness {
+    /// Channel-summed mean square of each 400 ms block.
+    pub blocks: Vec<f64>,
+    /// Highest absolute sample value after oversampling, linear.
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// Gated integrated loudness in LUFS; `None` for silence or tracks under 400 ms.
+    pub fn integrated_lufs(&self) -> Option<f64> {
+        gated_loudness(&self.blocks)
+    }
+
+    /// Combines tracks as if they were played back to back.
+    pub fn merge<'a>(tracks: impl IntoIterator<Item = &'a Loudness>) -> Self {
+        let mut album = Self::default();
+        for track in tracks {
+            album.blocks.extend_from_slice(&track.blocks);
+            album.true_peak = album.true_peak.max(track.true_peak);
+        }
+        album
+    }
+}
+
+/// Integrated loudness of gating blocks following BS.1770-4.
+pub fn gated_loudness(blocks: &[f64]) -> Option<f64> {
+    let mean = |blocks: &[f64]| blocks.iter().sum::<f64>() / blocks.len() as f64;
+    let audible: Vec<f64> = blocks.iter().copied().filter(|&e| e > 0.0 && lufs(e) > ABSOLUTE_GATE_LUFS).collect();
+    if audible.is_empty() {
+        return None;
+    }
+    let threshold = lufs(mean(&audible)) + RELATIVE_GATE_LU;
+    let gated: Vec<f64> = audible.into_iter().filter(|&e| lufs(e) > threshold).collect();
+    Some(lufs(mean(&gated)))
+}
+
+/// Decodes a track and measures it. Returns `None` if `cancel` was set.
+pub fn measure_file(path: &str, cancel: &AtomicBool) -> Result<Option<Loudness>, Box<dyn Error>> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension("mp3");
+    let probed = get_probe().format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut format = probed.format;
+    let track = format.default_track().ok_or("No default track found")?;
+    let track_id = track.id;
+    let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut meter: Option<LoudnessMeter> = None;
+    while let Ok(packet) = format.next_packet() {
+        if cancel.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let Ok(decoded) = decoder.decode(&packet) else {
+            continue;
+        };
+        let spec = *decoded.spec();
+        let meter = meter.get_or_insert_with(|| LoudnessMeter::new(spec.channels.count(), spec.rate));
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        meter.push(buffer.samples());
+    }
+    Ok(Some(meter.ok_or("No audio decoded")?.finish()))
+}
+
+/// ReplayGain values worked out for one file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GainTags {
+    pub path: String,
+    pub track_lufs: f64,
+    pub track_gain_db: f64,
+    pub track_peak: f64,
+    /// Album gain and peak, when the file has an album tag.
+    pub album: Option<(f64, f64)>,
+}
+
+/// Writes `REPLAYGAIN_*` `TXXX` frames, replacing any earlier ones whatever their case.
+/// The file is replaced by a rewritten copy, as the tag editor does.
+pub fn write_gain_tags(tags: &GainTags) -> Result<(), Box<dyn Error>> {
+    let (mut tag, version) = match id3::Tag::read_from_path(&tags.path) {
+        Ok(tag) => {
+            let version = tag.version();
+            (tag, version)
+        }
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => (id3::Tag::new(), id3::Version::Id3v24),
+        Err(e) => return Err(e.into()),
+    };
+    let mut values = vec![
+        ("REPLAYGAIN_TRACK_GAIN", format!("{:+.2} dB", tags.track_gain_db)),
+        ("REPLAYGAIN_TRACK_PEAK", format!("{:.6}", tags.track_peak)),
+    ];
+    if let Some((gain, peak)) = tags.album {
+        values.push(("REPLAYGAIN_ALBUM_GAIN", format!("{:+.2} dB", gain)));
+        values.push(("REPLAYGAIN_ALBUM_PEAK", format!("{:.6}", peak)));
+    }
+    let stale: Vec<String> = tag.extended_texts()
+        .map(|t| t.description.clone())
+        .filter(|d| d.to_ascii_uppercase().starts_with("REPLAYGAIN_"))
+        .collect();
+    for description in stale {
+        tag.remove_extended_text(Some(&description), None);
+    }
+    for (description, value) in values {
+        tag.add_frame(id3::frame::ExtendedText { description: description.to_string(), value });
+    }
+    write_tag_safely(&tags.path, &tag, version)
+}
+
+/// `paths` followed by the other library tracks of their albums, as an album
+/// gain measured over part of an album would be wrong.
+pub fn with_album_tracks(paths: &[String], library: &Library) -> Vec<String> {
+    let album_of = |track: &LibraryTrack| track.tags.album.is_some().then(|| album_key(&track.path, &track.tags));
+    let given: HashSet<&str> = paths.iter().map(String::as_str).collect();
+    let albums: HashSet<String> = library.tracks.iter()
+        .filter(|t| given.contains(t.path.as_str()))
+        .filter_map(album_of)
+        .collect();
+    let mut all = paths.to_vec();
+    all.extend(library.tracks.iter()
+        .filter(|t| !given.contains(t.path.as_str()) && album_of(t).is_some_and(|key| albums.contains(&key)))
+        .map(|t| t.path.clone()));
+    all
+}
+
+/// Track and album gains for measured files. Tracks without an album key get
+/// no album gain; tracks that could not be measured are left out.
+pub fn compute_gains(measured: &[(String, Option<String>, Loudness)]) -> Vec<GainTags> {
+    let mut albums: HashMap<&str, Vec<&Loudness>> = HashMap::new();
+    for (_, album, loudness) in measured {
+        if let Some(album) = album {
+            albums.entry(album.as_str()).or_default().push(loudness);
+        }
+    }
+    let album_gains: HashMap<&str, (f64, f64)> = albums.into_iter()
+        .filter_map(|(key, tracks)| {
+            let album = Loudness::merge(tracks);
+            Some((key, (REPLAYGAIN_REFERENCE_LUFS - album.integrated_lufs()?, album.true_peak)))
+        })
+        .collect();
+    measured.iter()
+        .filter_map(|(path, album, loudness)| {
+            let track_lufs = loudness.integrated_lufs()?;
+            Some(GainTags {
+                path: path.clone(),
+                track_lufs,
+                track_gain_db: REPLAYGAIN_REFERENCE_LUFS - track_lufs,
+                track_peak: loudness.true_peak,
+                album: album.as_deref().and_then(|a| album_gains.get(a).copied()),
+            })
+        })
+        .collect()
+}
+
+/// Outcome of one file, reported as soon as it is measured.
+#[derive(Clone, Debug)]
+pub struct ScanEntry {
+    pub path: String,
+    /// Integrated loudness and true peak, or why the file could not be measured.
+    pub result: Result<(f64, f64), String>,
+}
+
+/// Measures files on all cores in the background, then writes ReplayGain tags.
+#[derive(Clone, Default)]
+pub struct LoudnessScan {
+    pub total: usize,
+    pub running: Arc<AtomicBool>,
+    pub cancel: Arc<AtomicBool>,
+    pub entries: Arc<Mutex<Vec<ScanEntry>>>,
+    /// Set once the tags have been written.
+    pub written: Arc<Mutex<Option<ScanOutcome>>>,
+}
+
+/// Files whose tags were written, and files that could not be written.
+#[derive(Clone, Debug, Default)]
+pub struct ScanOutcome {
+    pub tagged: Vec<GainTags>,
+    pub failed: Vec<String>,
+}
+
+impl LoudnessScan {
+    pub fn start(paths: Vec<String>) -> Self {
+        let scan = Self { total: paths.len(), running: Arc::new(AtomicBool::new(true)), ..Self::default() };
+        let workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2).clamp(1, paths.len().max(1));
+        let queue = Arc::new(Mutex::new(VecDeque::from(paths)));
+        let (tx, rx) = mpsc::channel::<(String, Option<String>, Result<Loudness, String>)>();
+        for _ in 0..workers {
+            let queue = queue.clone();
+            let tx = tx.clone();
+            let cancel = scan.cancel.clone();
+            std::thread::spawn(move || {
+                loop {
+                    let Some(path) = queue.lock().unwrap().pop_front() else { break };
+                    let tags = TrackTags::read(&path);
+                    let album = tags.album.is_some().then(|| album_key(&path, &tags));
+                    let result = match measure_file(&path, &cancel) {
+                        Ok(Some(loudness)) => Ok(loudness),
+                        Ok(None) => break,
+                        Err(e) => Err(e.to_string()),
+                    };
+                    if tx.send((path, album, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        std::thread::spawn({
+            let scan = scan.clone();
+            move || {
+                let mut measured = Vec::new();
+                for (path, album, result) in rx {
+                    let summary = match &result {
+                        Ok(loudness) => loudness.integrated_lufs()
+                            .map(|lufs| (lufs, loudness.true_peak))
+                            .ok_or_else(|| String::from("too quiet or short to measure")),
+                        Err(e) => Err(e.clone()),
+                    };
+                    scan.entries.lock().unwrap().push(ScanEntry { path: path.clone(), result: summary });
+                    if let Ok(loudness) = result {
+                        measured.push((path, album, loudness));
+                    }
+                }
+                // Album gains need every track, so nothing is written after a cancel
+                if !scan.cancel.load(Ordering::SeqCst) {
+                    let mut outcome = ScanOutcome::default();
+                    for gains in compute_gains(&measured) {
+                        match write_gain_tags(&gains) {
+                            Ok(()) => outcome.tagged.push(gains),
+                            Err(e) => outcome.failed.push(format!("{}: {}", gains.path, e)),
+                        }
+                    }
+                    *scan.written.lock().unwrap() = Some(outcome);
+                }
+                scan.running.store(false, Ordering::SeqCst);
+            }
+        });
+        scan
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| path.to_string())
+}
+
+fn describe(entry: &ScanEntry) -> String {
+    match &entry.result {
+        Ok((lufs, peak)) => format!(
+            "{:>6.1} LUFS  {:>5.1} dBTP  {:+6.2} dB  {}",
+            lufs, 20.0 * peak.max(1e-9).log10(), REPLAYGAIN_REFERENCE_LUFS - lufs, file_name(&entry.path)
+        ),
+        Err(e) => format!("error: {}  {}", e, file_name(&entry.path)),
+    }
+}
+
+/// `rdaio scan-loudness <dir>`: tags every MP3 below the folder and prints progress.
+pub fn run_scan_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let Some(root) = args.iter().find(|a| !a.starts_with("--")) else {
+        return Err("usage: rdaio scan-loudness <dir>".into());
+    };
+    let mut found = Vec::new();
+    crate::library::find_mp3_files(Path::new(root), &mut found);
+    found.sort();
+    if found.is_empty() {
+        println!("No MP3 files found in {}", root);
+        return Ok(());
+    }
+    // Albums reaching outside the folder (other discs) are measured whole
+    let found: Vec<String> = found.iter().map(|p| crate::stats::stats_key(p)).collect();
+    let paths = with_album_tracks(&found, &Library::load_from(crate::library::LIBRARY_FILE));
+    println!("Scanning {} files...", paths.len());
+    let scan = LoudnessScan::start(paths);
+    let mut printed = 0;
+    loop {
+        let running = scan.is_running();
+        let entries = scan.entries.lock().unwrap().clone();
+        for entry in &entries[printed..] {
+            printed += 1;
+            println!("[{}/{}] {}", printed, scan.total, describe(entry));
+        }
+        if !running {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    if let Some(outcome) = scan.written.lock().unwrap().as_ref() {
+        for failure in &outcome.failed {
+            println!("Could not write tags: {}", failure);
+        }
+        println!("Wrote ReplayGain tags to {} of {} files", outcome.tagged.len(), scan.total);
+    }
+    Ok(())
+}
+
+/// Loudness scan screen: shows progress and results, and returns the files
+/// whose tags were written. [ESC] while scanning cancels without writing tags.
+pub fn scan_loudness(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    paths: &[String],
+    debug_mode: bool,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let scan = LoudnessScan::start(paths.to_vec());
+    let mut list_state = ListState::default();
+    let mut follow = true;
+
+    loop {
+        let running = scan.is_running();
+        let entries = scan.entries.lock().unwrap().clone();
+        let written = scan.written.lock().unwrap().clone();
+        if follow && !entries.is_empty() {
+            list_state.select(Some(entries.len() - 1));
+        }
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Min(5),
+                    Constraint::Length(3),
+                ].as_ref())
+                .split(f.size());
+
+            let status = match (&written, running) {
+                (_, true) if scan.cancel.load(Ordering::SeqCst) => String::from("Cancelling"),
+                (_, true) if entries.len() == scan.total => String::from("Writing tags"),
+                (_, true) => format!("Scanning {}/{}", entries.len(), scan.total),
+                (Some(outcome), false) if outcome.failed.is_empty() => format!("Done - tagged {} files", outcome.tagged.len()),
+                (Some(outcome), false) => format!("Done - tagged {} files, {} could not be written", outcome.tagged.len(), outcome.failed.len()),
+                (None, false) => String::from("Cancelled - no tags written"),
+            };
+            let gauge = Gauge::default()
+                .block(Block::default().borders(Borders::ALL).title(format!("Loudness Scan (EBU R128) - {}", status)))
+                .gauge_style(Style::default().fg(Color::Green))
+                .ratio(entries.len() as f64 / scan.total.max(1) as f64);
+            f.render_widget(gauge, chunks[0]);
+
+            let items: Vec<ListItem> = entries.iter()
+                .map(|entry| {
+                    let style = if entry.result.is_ok() { Style::default() } else { Style::default().fg(Color::Red) };
+                    ListItem::new(describe(entry)).style(style)
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title("Loudness  True peak  Track gain  File"))
+                .highlight_symbol(">> ");
+            f.render_stateful_widget(list, chunks[1], &mut list_state);
+
+            let help = if running { "[Up/Down] Scroll  [ESC] Cancel (no tags are written)" } else { "[Up/Down] Scroll  [ESC/Enter] Back" };
+            let help = Paragraph::new(help)
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[2]);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(100))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                event::KeyCode::Esc if running => {
+                    scan.cancel.store(true, Ordering::SeqCst);
+                    if debug_mode {
+                        println!("[DEBUG] Loudness scan cancelled");
+                    }
+                },
+                event::KeyCode::Esc | event::KeyCode::Enter if !running => {
+                    let tagged: Vec<String> = written.map(|o| o.tagged.into_iter().map(|g| g.path).collect()).unwrap_or_default();
+                    if debug_mode {
+                        println!("[DEBUG] Loudness scan wrote tags to {} files", tagged.len());
+                    }
+                    return Ok(tagged);
+                },
+                event::KeyCode::Up => {
+                    follow = false;
+                    list_state.select(list_state.selected().map(|i| i.saturating_sub(1)));
+                },
+                event::KeyCode::Down => {
+                    let last = entries.len().saturating_sub(1);
+                    let next = list_state.selected().map(|i| (i + 1).min(last));
+                    follow = next == Some(last);
+                    list_state.select(next);
+                },
+                _ => {}
+            }
+        }
+    }
+}
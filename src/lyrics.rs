@@ -0,0 +1,238 @@
+use ratatui::{style::{Color, Modifier, Style}, text::Line};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+
+/// SYLT timestamps in MPEG frames are converted assuming 1152-sample frames at 44.1 kHz.
+const MPEG_FRAME_MS: f64 = 1152.0 * 1000.0 / 44100.0;
+
+/// One line of lyrics; `time_ms` is set for synchronised lyrics.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LyricLine {
+    pub time_ms: Option<u64>,
+    pub text: String,
+}
+
+/// Where a track's lyrics came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LyricsSource {
+    /// ID3 `SYLT` frame.
+    Sylt,
+    /// `.lrc` file next to the track.
+    LrcFile,
+    /// ID3 `USLT` frame (which may itself hold LRC text).
+    Uslt,
+}
+
+impl LyricsSource {
+    pub fn label(self) -> &'static str {
+        match self {
+            LyricsSource::Sylt => "SYLT",
+            LyricsSource::LrcFile => ".lrc",
+            LyricsSource::Uslt => "USLT",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lyrics {
+    pub lines: Vec<LyricLine>,
+    pub source: LyricsSource,
+}
+
+impl Lyrics {
+    /// Whether the lines carry timestamps (they either all do or none do).
+    pub fn is_synced(&self) -> bool {
+        self.lines.first().is_some_and(|l| l.time_ms.is_some())
+    }
+
+    /// Index of the line being sung at `position_ms`, if any has started yet.
+    pub fn current_line(&self, position_ms: u64) -> Option<usize> {
+        if !self.is_synced() {
+            return None;
+        }
+        let started = self.lines.partition_point(|l| l.time_ms.unwrap_or(0) <= position_ms);
+        started.checked_sub(1)
+    }
+}
+
+/// Parses `mm:ss`, `mm:ss.xx`, `mm:ss.xxx` or `mm:ss:xx` into milliseconds.
+fn parse_timestamp(value: &str) -> Option<u64> {
+    let (minutes, rest) = value.split_once(':')?;
+    let minutes: u64 = minutes.trim().parse().ok()?;
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((s, f)) => (s, f),
+        None => (rest, ""),
+    };
+    let seconds: u64 = seconds.trim().parse().ok()?;
+    if seconds >= 60 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // "5" is 500 ms, "05" is 50 ms, "005" is 5 ms
+    let fraction_ms = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<u64>().ok()? * 100,
+        2 => fraction.parse::<u64>().ok()? * 10,
+        _ => fraction[..3].parse::<u64>().ok()?,
+    };
+    Some(minutes * 60_000 + seconds * 1000 + fraction_ms)
+}
+
+/// Parses LRC text. Lines may carry several `[mm:ss.xx]` stamps; `[offset:±ms]`
+/// shifts every stamp and other `[key:value]` tags are ignored. Text without
+/// any timestamps is returned as plain, unsynchronised lines.
+pub fn parse_lrc(text: &str) -> Vec<LyricLine> {
+    let mut synced: Vec<LyricLine> = Vec::new();
+    let mut plain: Vec<LyricLine> = Vec::new();
+    let mut offset_ms: i64 = 0;
+    for line in text.lines() {
+        let mut rest = line.trim();
+        let mut stamps = Vec::new();
+        let mut tagged = false;
+        while let Some(after) = rest.strip_prefix('[') {
+            let Some(end) = after.find(']') else { break };
+            let tag = &after[..end];
+            if let Some(time) = parse_timestamp(tag) {
+                stamps.push(time);
+            } else if let Some((key, value)) = tag.split_once(':') {
+                if key.trim().eq_ignore_ascii_case("offset") {
+                    offset_ms = value.trim().parse().unwrap_or(0);
+                }
+                tagged = true;
+            } else {
+                break;
+            }
+            rest = after[end + 1..].trim_start();
+        }
+        if !stamps.is_empty() {
+            for time in stamps {
+                synced.push(LyricLine { time_ms: Some(time), text: rest.trim_end().to_string() });
+            }
+        } else if !tagged {
+            plain.push(LyricLine { time_ms: None, text: rest.trim_end().to_string() });
+        }
+    }
+    if synced.is_empty() {
+        while plain.last().is_some_and(|l| l.text.is_empty()) {
+            plain.pop();
+        }
+        let leading = plain.iter().take_while(|l| l.text.is_empty()).count();
+        return plain.split_off(leading);
+    }
+    // A positive offset makes the lyrics appear sooner
+    for line in &mut synced {
+        line.time_ms = line.time_ms.map(|t| (t as i64 - offset_ms).max(0) as u64);
+    }
+    synced.sort_by_key(|l| l.time_ms);
+    synced
+}
+
+/// The `.lrc` file that belongs to a track: same name, `.lrc` extension.
+pub fn lrc_path(track_path: &str) -> PathBuf {
+    Path::new(track_path).with_extension("lrc")
+}
+
+fn sylt_lines(sylt: &id3::frame::SynchronisedLyrics) -> Vec<LyricLine> {
+    let mut lines: Vec<LyricLine> = sylt.content.iter()
+        .map(|(stamp, text)| {
+            let time_ms = match sylt.timestamp_format {
+                id3::frame::TimestampFormat::Ms => *stamp as u64,
+                id3::frame::TimestampFormat::Mpeg => (*stamp as f64 * MPEG_FRAME_MS) as u64,
+            };
+            // SYLT text often starts with the line break that ends the previous line
+            LyricLine { time_ms: Some(time_ms), text: text.trim_matches(['\n', '\r']).to_string() }
+        })
+        .collect();
+    lines.sort_by_key(|l| l.time_ms);
+    lines
+}
+
+/// Finds lyrics for a track, preferring synchronised ones: `SYLT`, then a
+/// sidecar `.lrc`, then `USLT`.
+pub fn load_lyrics(track_path: &str) -> Option<Lyrics> {
+    let tag = id3::Tag::read_from_path(track_path).ok();
+    if let Some(sylt) = tag.as_ref().and_then(|t| t.synchronised_lyrics().find(|s| !s.content.is_empty())) {
+        return Some(Lyrics { lines: sylt_lines(sylt), source: LyricsSource::Sylt });
+    }
+    if let Ok(text) = fs::read_to_string(lrc_path(track_path)) {
+        let lines = parse_lrc(&text);
+        if !lines.is_empty() {
+            return Some(Lyrics { lines, source: LyricsSource::LrcFile });
+        }
+    }
+    let uslt = tag.as_ref().and_then(|t| t.lyrics().find(|l| !l.text.trim().is_empty()))?;
+    Some(Lyrics { lines: parse_lrc(&uslt.text), source: LyricsSource::Uslt })
+}
+
+/// Lyrics read by a background thread, so switching to the lyrics view never
+/// waits on the disk.
+pub struct LyricsCache {
+    entries: Arc<Mutex<HashMap<String, Option<Arc<Lyrics>>>>>,
+    pending: Arc<Mutex<HashSet<String>>>,
+    requests: Sender<String>,
+}
+
+impl LyricsCache {
+    pub fn new() -> Self {
+        let entries = Arc::new(Mutex::new(HashMap::new()));
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+        let (requests, rx) = mpsc::channel::<String>();
+
+        std::thread::spawn({
+            let entries = entries.clone();
+            let pending = pending.clone();
+            move || {
+                for track_path in rx {
+                    let lyrics = load_lyrics(&track_path).map(Arc::new);
+                    entries.lock().unwrap().insert(track_path.clone(), lyrics);
+                    pending.lock().unwrap().remove(&track_path);
+                }
+            }
+        });
+
+        Self { entries, pending, requests }
+    }
+
+    /// `Some(None)` when the track has no lyrics, `None` while they are being read.
+    pub fn get(&self, track_path: &str) -> Option<Option<Arc<Lyrics>>> {
+        if let Some(lyrics) = self.entries.lock().unwrap().get(track_path) {
+            return Some(lyrics.clone());
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.insert(track_path.to_string()) {
+            let _ = self.requests.send(track_path.to_string());
+        }
+        None
+    }
+}
+
+/// The lines that fit in `height` rows. Synchronised lyrics keep the current
+/// line in the middle and highlight it; plain lyrics scroll with the track's progress.
+pub fn lyric_lines(lyrics: &Lyrics, position_ms: u64, duration_ms: Option<u64>, height: usize) -> Vec<Line<'static>> {
+    let total = lyrics.lines.len();
+    let max_start = total.saturating_sub(height);
+    let current = lyrics.current_line(position_ms);
+    let start = match (current, duration_ms) {
+        (Some(i), _) => i.saturating_sub(height / 2).min(max_start),
+        (None, _) if lyrics.is_synced() => 0,
+        (None, Some(duration)) if duration > 0 => {
+            ((position_ms.min(duration) as f64 / duration as f64) * max_start as f64) as usize
+        }
+        _ => 0,
+    };
+    lyrics.lines.iter()
+        .enumerate()
+        .skip(start)
+        .take(height)
+        .map(|(i, line)| {
+            let style = match current {
+                Some(c) if c == i => Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+                Some(c) if i < c => Style::default().fg(Color::DarkGray),
+                _ => Style::default(),
+            };
+            Line::styled(line.text.clone(), style)
+        })
+        .collect()
+}
@@ -1,14 +1,61 @@
+mod alarm;
+mod album_art;
+mod config;
+mod equalizer;
+mod filename_tags;
+mod history;
+mod library;
+mod library_view;
+mod loudness;
+mod lyrics;
+mod metadata;
+mod metadata_cache;
+mod renamer;
+mod replaygain;
+mod search;
+mod silence;
+mod sleep_timer;
+mod smart_playlist;
+mod stats;
 mod symphonia_play;
 mod symphonia_control;
+mod tag_editor;
 mod tests;
+mod timers;
+mod timestretch;
+mod visualiser;
+mod watcher;
+mod waveform;
+use alarm::{alarm_from_args, edit_alarms};
+use album_art::{ArtCache, ArtProtocol, ArtView};
+use config::Config;
+use equalizer::edit_equalizer;
+use filename_tags::tags_from_filenames;
+use history::{append_history, browse_history, HistoryEntry, HistoryStatus, HISTORY_FILE};
+use library::{Library, LibraryScanner, LIBRARY_FILE};
+use library_view::browse_library;
+use loudness::{run_scan_command, scan_loudness, with_album_tracks};
+use lyrics::{lyric_lines, LyricsCache};
+use metadata::{now_playing_lines, progress_line};
+use metadata_cache::{MetadataCache, META_CACHE_FILE};
+use renamer::rename_files;
+use search::search_library;
+use sleep_timer::{choose_sleep_timer, SleepTimer};
+use smart_playlist::manage_playlists;
+use stats::{AudioHashCache, ListenEnd, ListenSession, PlayStats, QueueKeys, QueueSort, STATS_FILE};
 use symphonia_play::play_mp3_with_symphonia;
-use symphonia_control::PlaybackControl;
+use symphonia_control::{PlaybackControl, PlaybackSettings};
+use tag_editor::edit_tags;
+use timers::Timers;
+use visualiser::{scope_points, Spectrum};
+use watcher::{apply_to_queue, LibraryWatcher, QueueChange};
+use waveform::{seek_position, waveform_lines, WaveformCache, WAVEFORM_ROWS};
 use id3::TagLike;
 use rand::seq::SliceRandom;
 
-use crossterm::{event, execute, terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}};
-use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, Paragraph, ListState}, layout::{Layout, Constraint, Direction}, style::{Style, Modifier, Color}};
-use std::{io, error::Error, fs, path::PathBuf};
+use crossterm::{event, execute, event::{DisableMouseCapture, EnableMouseCapture}, terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}};
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, Paragraph, ListState, Wrap, canvas::{Canvas, Line as CanvasLine}}, layout::{Layout, Constraint, Direction, Rect}, style::{Style, Modifier, Color}, symbols, text::Line};
+use std::{io, error::Error, fs, path::{Path, PathBuf}};
 use std::env;
 
 const QUEUE_FILE: &str = ".rdaio_queue";
@@ -25,7 +72,9 @@ pub fn save_queue(files: &[String], directory: &str) -> Result<(), Box<dyn Error
     Ok(())
 }
 
-pub fn load_queue() -> Result<Option<(String, Vec<String>)>, Box<dyn Error>> {
+type SavedQueue = (String, Vec<String>);
+
+pub fn load_queue() -> Result<Option<SavedQueue>, Box<dyn Error>> {
     if let Ok(content) = fs::read_to_string(QUEUE_FILE) {
         let mut lines = content.lines();
         if let Some(directory) = lines.next() {
@@ -37,44 +86,56 @@ pub fn load_queue() -> Result<Option<(String, Vec<String>)>, Box<dyn Error>> {
 }
 
 pub fn load_mp3_files(directory: &str) -> Result<Vec<String>, Box<dyn Error>> {
-    let mp3_files: Vec<String> = fs::read_dir(directory)?
+    let mut mp3_files: Vec<String> = fs::read_dir(directory)?
         .filter_map(|entry| {
             entry.ok().and_then(|e| {
                 let path = e.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        if ext.eq_ignore_ascii_case("mp3") {
-                            return path.file_name().and_then(|n| n.to_str().map(|s| s.to_string()));
-                        }
-                    }
+                if path.is_file()
+                    && let Some(ext) = path.extension()
+                    && ext.eq_ignore_ascii_case("mp3")
+                {
+                    return path.file_name().and_then(|n| n.to_str().map(|s| s.to_string()));
                 }
                 None
             })
         })
         .collect();
+    mp3_files.sort();
     Ok(mp3_files)
 }
 
 pub fn get_mp3_title(file_path: &str) -> Option<String> {
-    if let Ok(tag) = id3::Tag::read_from_path(file_path) {
-        if let Some(title) = tag.title() {
-            return Some(title.to_string());
-        }
+    if let Ok(tag) = id3::Tag::read_from_path(file_path)
+        && let Some(title) = tag.title()
+    {
+        return Some(title.to_string());
     }
     None
 }
 
-pub fn get_display_name(file_name: &str, directory: &str, show_title: bool) -> String {
-    if !show_title {
+/// Resolves a queue entry against the directory it was loaded from.
+/// Entries picked in the folder browser are absolute and are returned as-is.
+pub fn get_track_path(file_name: &str, directory: &str) -> String {
+    if directory == "." {
         return file_name.to_string();
     }
-    
     let mut path = PathBuf::from(directory);
     path.push(file_name);
-    
-    match get_mp3_title(path.to_string_lossy().as_ref()) {
+    path.to_string_lossy().to_string()
+}
+
+pub fn get_display_name(file_name: &str, directory: &str, show_title: bool) -> String {
+    let short_name = Path::new(file_name)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_name.to_string());
+    if !show_title {
+        return short_name;
+    }
+
+    match get_mp3_title(&get_track_path(file_name, directory)) {
         Some(title) => title,
-        None => file_name.to_string(),
+        None => short_name,
     }
 }
 
@@ -88,19 +149,17 @@ pub fn get_folder_contents(directory: &str) -> Result<Vec<(String, bool)>, Box<d
     let mut folders = Vec::new();
     let mut files = Vec::new();
     
-    for entry in entries {
-        if let Ok(e) = entry {
-            let path = e.path();
-            if let Some(name) = path.file_name() {
-                if let Some(item_name) = name.to_str() {
-                    if path.is_dir() {
-                        folders.push((item_name.to_string(), true));
-                    } else if let Some(ext) = path.extension() {
-                        if ext.eq_ignore_ascii_case("mp3") {
-                            files.push((item_name.to_string(), false));
-                        }
-                    }
-                }
+    for e in entries.flatten() {
+        let path = e.path();
+        if let Some(name) = path.file_name()
+            && let Some(item_name) = name.to_str()
+        {
+            if path.is_dir() {
+                folders.push((item_name.to_string(), true));
+            } else if let Some(ext) = path.extension()
+                && ext.eq_ignore_ascii_case("mp3")
+            {
+                files.push((item_name.to_string(), false));
             }
         }
     }
@@ -113,16 +172,48 @@ pub fn get_folder_contents(directory: &str) -> Result<Vec<(String, bool)>, Box<d
     Ok(items)
 }
 
-fn browse_folders(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, _debug_mode: bool) -> Result<String, Box<dyn Error>> {
+/// What the folder browser or library asks the main loop to do with the user's choice.
+pub enum QueueAction {
+    /// Load every MP3 in a directory as the new queue (the classic [L] behaviour).
+    LoadDirectory(String),
+    /// Replace the queue with the selected tracks.
+    Replace(Vec<String>),
+    /// Append the selected tracks to the end of the queue.
+    Append(Vec<String>),
+    /// Append the selected tracks and start playing the first of them.
+    PlayNow(Vec<String>),
+}
+
+/// Expands a browser selection into absolute track paths.
+/// Directories contribute their MP3 files (non-recursive, sorted), files are taken as-is.
+pub fn collect_selection(paths: &[PathBuf]) -> Vec<String> {
+    let mut tracks = Vec::new();
+    for path in paths {
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+        if path.is_dir() {
+            if let Ok(files) = load_mp3_files(path.to_string_lossy().as_ref()) {
+                tracks.extend(files.iter().map(|f| path.join(f).to_string_lossy().to_string()));
+            }
+        } else {
+            tracks.push(path.to_string_lossy().to_string());
+        }
+    }
+    tracks
+}
+
+fn browse_folders(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, debug_mode: bool) -> Result<QueueAction, Box<dyn Error>> {
     let mut current_path = String::from(".");
     let mut folder_state = ListState::default();
+    let mut marked: Vec<PathBuf> = Vec::new();
 
     loop {
         let contents = get_folder_contents(&current_path)?;
         let folder_items: Vec<ListItem> = contents.iter()
             .map(|(name, is_dir)| {
                 let prefix = if *is_dir { "[D] " } else { "[F] " };
-                ListItem::new(format!("{}{}", prefix, name))
+                let entry_path = PathBuf::from(&current_path).join(name);
+                let mark = if marked.contains(&entry_path) { "* " } else { "  " };
+                ListItem::new(format!("{}{}{}", mark, prefix, name))
             })
             .collect();
 
@@ -140,113 +231,342 @@ fn browse_folders(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, _debug_
                 ].as_ref())
                 .split(f.size());
 
+            let title = if marked.is_empty() {
+                format!("Browse Folders - {}", current_path)
+            } else {
+                format!("Browse Folders - {} [{} marked]", current_path, marked.len())
+            };
             let folder_list = List::new(folder_items.clone())
-                .block(Block::default().borders(Borders::ALL).title(format!("Browse Folders - {}", current_path)))
+                .block(Block::default().borders(Borders::ALL).title(title))
                 .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
                 .highlight_symbol(">> ");
             f.render_stateful_widget(folder_list, chunks[0], &mut folder_state);
 
-            let help = Paragraph::new("[Up/Down] Navigate  [Enter] Open Dir  [L] Load Files  [ESC] Cancel")
-                .block(Block::default().borders(Borders::ALL).title("Controls"));
+            let help = Paragraph::new("[Up/Down] Navigate  [Space] Mark  [Enter] Open Dir/Play File  [L] Load Dir  [R] Replace Queue  [A] Append  [P] Play Now  [ESC] Cancel")
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
             f.render_widget(help, chunks[1]);
         })?;
 
-        if event::poll(std::time::Duration::from_millis(200))? {
-            if let event::Event::Key(key) = event::read()? {
-                if key.kind != event::KeyEventKind::Press {
-                    continue;
-                }
-                match key.code {
-                    event::KeyCode::Esc => {
-                        return Err("Cancelled".into());
-                    },
-                    event::KeyCode::Down => {
-                        let i = match folder_state.selected() {
-                            Some(i) => {
-                                if i >= contents.len() - 1 { 0 } else { i + 1 }
-                            },
-                            None => 0,
-                        };
-                        folder_state.select(Some(i));
-                    },
-                    event::KeyCode::Up => {
-                        let i = match folder_state.selected() {
-                            Some(i) => {
-                                if i == 0 { contents.len() - 1 } else { i - 1 }
-                            },
-                            None => 0,
-                        };
-                        folder_state.select(Some(i));
-                    },
-                    event::KeyCode::Enter => {
-                        if let Some(idx) = folder_state.selected() {
-                            if let Some((name, is_dir)) = contents.get(idx) {
-                                if *is_dir {
-                                    if name == ".." {
-                                        let mut path = if current_path == "." {
-                                            std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
-                                        } else {
-                                            PathBuf::from(&current_path)
-                                        };
-                                        if path.pop() {
-                                            current_path = path.to_string_lossy().to_string();
-                                        }
-                                        folder_state.select(Some(0));
-                                    } else if name != "." {
-                                        let mut path = if current_path == "." {
-                                            std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
-                                        } else {
-                                            PathBuf::from(&current_path)
-                                        };
-                                        path.push(name);
-                                        current_path = path.to_string_lossy().to_string();
-                                        folder_state.select(Some(0));
-                                    }
-                                }
+        // Marked entries win; otherwise the entry under the cursor is the selection.
+        let selection = |idx: Option<usize>| -> Vec<PathBuf> {
+            if !marked.is_empty() {
+                return marked.clone();
+            }
+            match idx.and_then(|i| contents.get(i)) {
+                Some((name, _)) if name == ".." => Vec::new(),
+                Some((name, _)) if name == "." => vec![PathBuf::from(&current_path)],
+                Some((name, _)) => vec![PathBuf::from(&current_path).join(name)],
+                None => Vec::new(),
+            }
+        };
+
+        if event::poll(std::time::Duration::from_millis(200))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                event::KeyCode::Esc => {
+                    return Err("Cancelled".into());
+                },
+                event::KeyCode::Down => {
+                    let i = match folder_state.selected() {
+                        Some(i) => {
+                            if i >= contents.len() - 1 { 0 } else { i + 1 }
+                        },
+                        None => 0,
+                    };
+                    folder_state.select(Some(i));
+                },
+                event::KeyCode::Up => {
+                    let i = match folder_state.selected() {
+                        Some(i) => {
+                            if i == 0 { contents.len() - 1 } else { i - 1 }
+                        },
+                        None => 0,
+                    };
+                    folder_state.select(Some(i));
+                },
+                event::KeyCode::Char(' ') => {
+                    if let Some(idx) = folder_state.selected() {
+                        if let Some((name, _)) = contents.get(idx)
+                            && name != "."
+                            && name != ".."
+                        {
+                            let entry_path = PathBuf::from(&current_path).join(name);
+                            if let Some(pos) = marked.iter().position(|p| *p == entry_path) {
+                                marked.remove(pos);
+                            } else {
+                                marked.push(entry_path);
                             }
                         }
-                    },
-                    event::KeyCode::Char('l') | event::KeyCode::Char('L') => {
-                        if let Some(idx) = folder_state.selected() {
-                            if let Some((name, is_dir)) = contents.get(idx) {
-                                if *is_dir && name != "." && name != ".." {
-                                    let mut path = PathBuf::from(&current_path);
-                                    path.push(name);
-                                    return Ok(path.to_string_lossy().to_string());
-                                } else if *is_dir {
-                                    return Ok(current_path);
+                        folder_state.select(Some(if idx >= contents.len() - 1 { idx } else { idx + 1 }));
+                    }
+                },
+                event::KeyCode::Enter => {
+                    if let Some(idx) = folder_state.selected()
+                        && let Some((name, is_dir)) = contents.get(idx)
+                    {
+                        if *is_dir {
+                            if name == ".." {
+                                let mut path = if current_path == "." {
+                                    std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+                                } else {
+                                    PathBuf::from(&current_path)
+                                };
+                                if path.pop() {
+                                    current_path = path.to_string_lossy().to_string();
                                 }
+                                folder_state.select(Some(0));
+                            } else if name != "." {
+                                let mut path = if current_path == "." {
+                                    std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+                                } else {
+                                    PathBuf::from(&current_path)
+                                };
+                                path.push(name);
+                                current_path = path.to_string_lossy().to_string();
+                                folder_state.select(Some(0));
                             }
+                        } else {
+                            // Enter plays the highlighted file; marks are for [R], [A] and [P]
+                            let file = PathBuf::from(&current_path).join(name);
+                            if debug_mode {
+                                println!("[DEBUG] Browser play now: {}", file.display());
+                            }
+                            return Ok(QueueAction::PlayNow(collect_selection(&[file])));
                         }
-                    },
-                    _ => {}
-                }
+                    }
+                },
+                event::KeyCode::Char('l') | event::KeyCode::Char('L') => {
+                    if let Some(idx) = folder_state.selected()
+                        && let Some((name, is_dir)) = contents.get(idx)
+                    {
+                        if *is_dir && name != "." && name != ".." {
+                            let mut path = PathBuf::from(&current_path);
+                            path.push(name);
+                            return Ok(QueueAction::LoadDirectory(path.to_string_lossy().to_string()));
+                        } else if *is_dir {
+                            return Ok(QueueAction::LoadDirectory(current_path));
+                        } else {
+                            return Ok(QueueAction::Replace(collect_selection(&[PathBuf::from(&current_path).join(name)])));
+                        }
+                    }
+                },
+                event::KeyCode::Char('r') | event::KeyCode::Char('R') => {
+                    let tracks = collect_selection(&selection(folder_state.selected()));
+                    if !tracks.is_empty() {
+                        return Ok(QueueAction::Replace(tracks));
+                    }
+                },
+                event::KeyCode::Char('a') | event::KeyCode::Char('A') => {
+                    let tracks = collect_selection(&selection(folder_state.selected()));
+                    if !tracks.is_empty() {
+                        return Ok(QueueAction::Append(tracks));
+                    }
+                },
+                event::KeyCode::Char('p') | event::KeyCode::Char('P') => {
+                    let tracks = collect_selection(&selection(folder_state.selected()));
+                    if !tracks.is_empty() {
+                        return Ok(QueueAction::PlayNow(tracks));
+                    }
+                },
+                _ => {}
             }
         }
     }
 }
 
+/// Marked queue entries in queue order, or the selected entry when none are marked.
+fn marked_or_selected(files: &[String], marked: &std::collections::HashSet<String>, selected: Option<usize>) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    for file in files.iter().filter(|f| marked.contains(*f)) {
+        if !result.contains(file) {
+            result.push(file.clone());
+        }
+    }
+    if result.is_empty()
+        && let Some(file) = selected.and_then(|idx| files.get(idx))
+    {
+        result.push(file.clone());
+    }
+    result
+}
+
+/// Makes the metadata cache and the library pick up tags that were just written.
+fn refresh_tags(paths: &[String], metadata_cache: &MetadataCache, library: &std::sync::Mutex<Library>) {
+    let mut lib = library.lock().unwrap();
+    for path in paths {
+        metadata_cache.invalidate(path);
+        let key = stats::stats_key(path);
+        if lib.tracks.iter().any(|t| t.path == key) {
+            lib.upsert_file(&key);
+        }
+    }
+}
+
+/// Whether queue entry `idx` sits among tracks of its own album, which makes
+/// ReplayGain's auto mode use album gain.
+fn queue_album_context(files: &[String], idx: usize, directory: &str, metadata_cache: &MetadataCache) -> bool {
+    let first = idx.saturating_sub(1);
+    let albums: Vec<Option<String>> = (first..=idx + 1)
+        .map(|i| files.get(i).and_then(|f| metadata_cache.get(&get_track_path(f, directory))).and_then(|t| t.album))
+        .collect();
+    replaygain::album_context(&albums, idx - first)
+}
+
+/// Spawns a decode thread for a queue entry and returns its control handle.
+fn start_playback(file: &str, directory: &str, settings: &PlaybackSettings, album_context: bool) -> (PlaybackControl, std::thread::JoinHandle<()>) {
+    let mut ctrl = PlaybackControl::with_settings(settings.clone());
+    ctrl.album_context = album_context;
+    let fname = get_track_path(file, directory);
+    let handle = std::thread::spawn({
+        let ctrl = ctrl.clone();
+        move || {
+            let _ = play_mp3_with_symphonia(&fname, ctrl);
+        }
+    });
+    (ctrl, handle)
+}
+
+/// Closes the current listen and records it in the play statistics and history.
+fn finish_listen(stats: &mut PlayStats, listening: &mut Option<ListenSession>, ctrl: Option<&PlaybackControl>, metadata_cache: &MetadataCache, end: ListenEnd, skip_threshold: u32, debug_mode: bool) {
+    let Some(session) = listening.take() else {
+        return;
+    };
+    let duration_ms = ctrl.and_then(|c| c.get_stream_info()).and_then(|info| info.duration_ms);
+    let listened_ms = session.listened_ms();
+    let outcome = stats.record(&session.path, listened_ms, duration_ms, end, skip_threshold, stats::now_secs());
+    if debug_mode {
+        println!("[DEBUG] Listen of {} ({:?}) counted as {:?}", session.path, end, outcome);
+    }
+    if outcome != stats::ListenOutcome::Ignored {
+        let _ = stats.save_to(STATS_FILE);
+    }
+    // Tracks that failed to play are left out of the history
+    if end == ListenEnd::Finished && outcome == stats::ListenOutcome::Ignored {
+        return;
+    }
+    let _ = append_history(HISTORY_FILE, &history_entry(session, outcome, listened_ms, metadata_cache));
+}
+
+/// History row of a closed listen. The playing track's tags are normally
+/// cached by now; the history keeps the path either way.
+fn history_entry(session: ListenSession, outcome: stats::ListenOutcome, listened_ms: u64, metadata_cache: &MetadataCache) -> HistoryEntry {
+    let tags = metadata_cache.get(&session.track_path).unwrap_or_default();
+    HistoryEntry {
+        started_at: session.started_at,
+        status: HistoryStatus::from_outcome(outcome),
+        listened_ms,
+        path: session.path,
+        artist: tags.artist,
+        title: tags.title,
+    }
+}
+
+/// What the Now Playing panel shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PanelView {
+    /// Cover art, tags and stream details.
+    Info,
+    Lyrics,
+    Spectrum,
+    /// Oscilloscope trace of the output.
+    Scope,
+}
+
+impl PanelView {
+    fn next(self) -> Self {
+        match self {
+            PanelView::Info => PanelView::Lyrics,
+            PanelView::Lyrics => PanelView::Spectrum,
+            PanelView::Spectrum => PanelView::Scope,
+            PanelView::Scope => PanelView::Info,
+        }
+    }
+
+    /// Visualisers redraw at about 30 fps, everything else five times a second.
+    fn refresh_interval(self) -> std::time::Duration {
+        match self {
+            PanelView::Spectrum | PanelView::Scope => std::time::Duration::from_millis(33),
+            _ => std::time::Duration::from_millis(200),
+        }
+    }
+}
+
+/// The queue, Now Playing and controls areas of the main screen.
+fn screen_layout(area: Rect) -> [Rect; 3] {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(2)
+        .constraints([
+            Constraint::Min(5),
+            Constraint::Length(4),
+        ].as_ref())
+        .split(area);
+    let main_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(60),
+            Constraint::Percentage(40),
+        ].as_ref())
+        .split(chunks[0]);
+    [main_chunks[0], main_chunks[1], chunks[1]]
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = env::args().collect();
     let debug_mode = args.contains(&"--debug".to_string());
+    if args.get(1).is_some_and(|a| a == "scan-loudness") {
+        return run_scan_command(&args[2..]);
+    }
+    let mut config = Config::load();
+    // One-off alarm from `--at`, forgotten once it has gone off
+    let session_alarm = alarm_from_args(&args)?;
+    let playback_settings = PlaybackSettings { silence: config.silence, ..PlaybackSettings::new(config.eq.clone(), config.replay_gain) };
+    let metadata_cache = MetadataCache::new(config.metadata_cache.then_some(META_CACHE_FILE));
+    let library = std::sync::Arc::new(std::sync::Mutex::new(Library::load_from(LIBRARY_FILE)));
+    let library_scanner = LibraryScanner::default();
+    let mut play_stats = PlayStats::load_from(STATS_FILE);
+    play_stats.use_audio_hash = config.stats_audio_hash;
+    let audio_hashes = AudioHashCache::new();
+    let mut queue_keys = QueueKeys::default();
+    let library_generation = library.lock().unwrap().generation;
+    let mut library_watcher = if config.watch_library {
+        LibraryWatcher::start(&config.library_roots_or_default(), library.clone()).ok()
+    } else {
+        None
+    };
 
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
+    let art_cache = ArtCache::new();
+    let waveform_cache = WaveformCache::new();
+    let lyrics_cache = LyricsCache::new();
+    // Speed to go back to when an A-B loop played at `ab_loop_speed` ends
+    let mut speed_before_loop: Option<f32> = None;
+    // Sleep timer and alarms, kept running while sub-screens are open
+    let timers = Timers::start(playback_settings.clone(), library.clone());
+    timers.set_alarms(&config.alarms);
+    timers.set_one_off_alarm(session_alarm);
+    let mut art_view = ArtView::new(ArtProtocol::from_setting(&config.album_art, |name| env::var(name).ok()));
 
     // List real MP3 files in the current directory
     let mut current_directory = String::from(".");
     let mut mp3_files = load_mp3_files(&current_directory)?;
     
     // Try to load saved queue
-    if let Ok(Some((saved_dir, saved_files))) = load_queue() {
-        if !saved_files.is_empty() {
-            current_directory = saved_dir;
-            mp3_files = saved_files;
-        }
+    if let Ok(Some((saved_dir, saved_files))) = load_queue()
+        && !saved_files.is_empty()
+    {
+        current_directory = saved_dir;
+        mp3_files = saved_files;
     }
     
     let mut state = ListState::default();
@@ -258,312 +578,928 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut _symphonia_thread: Option<std::thread::JoinHandle<()>> = None;
     let mut current_playing_idx: Option<usize> = None;
     let mut show_title = true;
-    let original_mp3_files = mp3_files.clone();
+    let mut panel_view = PanelView::Info;
+    let mut spectrum = Spectrum::new();
+    let mut original_mp3_files = mp3_files.clone();
+    let mut listening: Option<ListenSession> = None;
+    let mut queue_sort = QueueSort::Original;
+    // Queue entries marked with Space for batch tag editing
+    let mut marked: std::collections::HashSet<String> = std::collections::HashSet::new();
     
     while running {
-        // Auto-play next song if current finished
-        if let Some(ctrl) = &symphonia_ctrl {
-            if ctrl.is_stopped() && current_playing_idx.is_some() {
-                let current_idx = current_playing_idx.unwrap();
-                if current_idx + 1 < mp3_files.len() {
-                    // Play next song
-                    let next_idx = current_idx + 1;
-                    if let Some(file) = mp3_files.get(next_idx) {
-                        if debug_mode {
-                            println!("[DEBUG] Auto-playing next track: {}", file);
-                        }
-                        let new_ctrl = PlaybackControl::new();
-                        let fname = if current_directory == "." {
-                            file.clone()
-                        } else {
-                            let mut path = PathBuf::from(&current_directory);
-                            path.push(file);
-                            path.to_string_lossy().to_string()
+        // Apply files added to or removed from the loaded folder
+        if let Some(watcher) = library_watcher.as_mut() {
+            watcher.watch_folder(&current_directory);
+            for change in watcher.poll() {
+                apply_to_queue(&mut original_mp3_files, &current_directory, &change);
+                match apply_to_queue(&mut mp3_files, &current_directory, &change) {
+                    QueueChange::Removed(idx) => {
+                        current_playing_idx = match current_playing_idx {
+                            Some(p) if p == idx => None,
+                            Some(p) if p > idx => Some(p - 1),
+                            other => other,
                         };
-                        let handle = std::thread::spawn({
-                            let ctrl = new_ctrl.clone();
-                            move || {
-                                let _ = play_mp3_with_symphonia(&fname, ctrl);
-                            }
-                        });
-                        symphonia_ctrl = Some(new_ctrl);
-                        _symphonia_thread = Some(handle);
-                        current_playing_idx = Some(next_idx);
+                        if let Some(sel) = state.selected()
+                            && sel >= mp3_files.len()
+                        {
+                            state.select(mp3_files.len().checked_sub(1));
+                        }
+                        let _ = save_queue(&mp3_files, &current_directory);
+                    },
+                    QueueChange::Added => {
+                        if state.selected().is_none() {
+                            state.select(Some(0));
+                        }
+                        let _ = save_queue(&mp3_files, &current_directory);
+                    },
+                    QueueChange::Unchanged => {}
+                }
+                if debug_mode {
+                    println!("[DEBUG] Filesystem change: {:?}", change);
+                }
+            }
+        }
+
+        // Alarms start quietly on the timer thread, with their playlist replacing the queue
+        if let Some(fired) = timers.take_fired_alarm() {
+            if debug_mode {
+                println!("[DEBUG] Alarm {} went off", fired.alarm.to_config_value());
+            }
+            // The alarm interrupted the track rather than the user skipping it
+            finish_listen(&mut play_stats, &mut listening, symphonia_ctrl.as_ref(), &metadata_cache, ListenEnd::Stopped, config.skip_threshold, debug_mode);
+            for ctrl in symphonia_ctrl.iter().chain(fired.interrupted.iter()) {
+                ctrl.stop();
+            }
+            if let Some(tracks) = fired.tracks {
+                marked.clear();
+                mp3_files = tracks;
+                original_mp3_files = mp3_files.clone();
+                let _ = save_queue(&mp3_files, &current_directory);
+            }
+            listening = Some(ListenSession::start(&fired.path, playback_settings.speed()));
+            symphonia_ctrl = Some(fired.ctrl);
+            _symphonia_thread = Some(fired.handle);
+            current_playing_idx = Some(fired.index);
+            state.select(Some(fired.index));
+        }
+
+        // Sleep timer: the timer thread fades out over the last 30 seconds and
+        // pauses at the deadline; playback is stopped here
+        let playing = symphonia_ctrl.as_ref().filter(|_| current_playing_idx.is_some());
+        let last_in_queue = current_playing_idx.is_some_and(|idx| idx + 1 >= mp3_files.len());
+        let start_idx = state.selected().unwrap_or(0);
+        let queue_start = mp3_files.get(start_idx).map(|file| (get_track_path(file, &current_directory), start_idx));
+        timers.set_playing(playing, last_in_queue, queue_start);
+        let track_ended = playing.is_some_and(|ctrl| ctrl.is_stopped());
+        let expired = timers.take_sleep_expired() || match timers.sleep_timer() {
+            Some(SleepTimer::EndOfTrack) => track_ended,
+            Some(SleepTimer::EndOfQueue) => track_ended && last_in_queue,
+            _ => false,
+        };
+        if expired {
+            if let Some(ctrl) = playing
+                && !ctrl.is_stopped()
+            {
+                finish_listen(&mut play_stats, &mut listening, Some(ctrl), &metadata_cache, ListenEnd::Stopped, config.skip_threshold, debug_mode);
+                ctrl.stop();
+            }
+            if debug_mode {
+                println!("[DEBUG] Sleep timer stopped playback");
+            }
+            current_playing_idx = None;
+            timers.set_sleep_timer(None);
+        }
+
+        if play_stats.apply_audio_hashes(&audio_hashes) {
+            let _ = play_stats.save_to(STATS_FILE);
+        }
+
+        // A track that stopped on its own was listened to the end
+        if let Some(ctrl) = &symphonia_ctrl
+            && ctrl.is_stopped()
+        {
+            finish_listen(&mut play_stats, &mut listening, Some(ctrl), &metadata_cache, ListenEnd::Finished, config.skip_threshold, debug_mode);
+        }
+
+        // Auto-play next song if current finished
+        if let Some(ctrl) = &symphonia_ctrl
+            && ctrl.is_stopped()
+            && let Some(current_idx) = current_playing_idx
+        {
+            if current_idx + 1 < mp3_files.len() {
+                // Play next song
+                let next_idx = current_idx + 1;
+                if let Some(file) = mp3_files.get(next_idx) {
+                    if debug_mode {
+                        println!("[DEBUG] Auto-playing next track: {}", file);
                     }
-                } else {
-                    current_playing_idx = None;
+                    let (new_ctrl, handle) = start_playback(file, &current_directory, &playback_settings, queue_album_context(&mp3_files, next_idx, &current_directory, &metadata_cache));
+                    listening = Some(ListenSession::start(&get_track_path(file, &current_directory), playback_settings.speed()));
+                    symphonia_ctrl = Some(new_ctrl);
+                    _symphonia_thread = Some(handle);
+                    current_playing_idx = Some(next_idx);
                 }
+            } else {
+                current_playing_idx = None;
             }
         }
         
+        // Tags come from the metadata cache so drawing never touches the disk
+        let now_playing = current_playing_idx
+            .and_then(|idx| mp3_files.get(idx))
+            .map(|file| get_track_path(file, &current_directory))
+            .map(|path| {
+                let tags = metadata_cache.get(&path).unwrap_or_default();
+                (path, tags)
+            });
+        let stream_info = symphonia_ctrl.as_ref().and_then(|ctrl| ctrl.get_stream_info());
+        let playback_status = match &symphonia_ctrl {
+            Some(ctrl) if current_playing_idx.is_some() && ctrl.is_paused() => "Paused",
+            Some(_) if current_playing_idx.is_some() => "Playing",
+            _ => "Stopped",
+        };
+        let ab_loop = symphonia_ctrl.as_ref().filter(|_| current_playing_idx.is_some()).and_then(|ctrl| ctrl.get_loop());
+        // The A-B loop speed lasts only as long as the loop
+        if ab_loop.and_then(|l| l.section()).is_none()
+            && let Some(speed) = speed_before_loop.take()
+        {
+            *playback_settings.speed.lock().unwrap() = speed;
+        }
+        let speed = playback_settings.speed();
+        if let Some(session) = listening.as_mut() {
+            session.set_speed(speed);
+        }
+        let mut playback_status = if timestretch::is_normal_speed(speed) {
+            playback_status.to_string()
+        } else {
+            format!("{} {:.1}x", playback_status, speed)
+        };
+        if let Some(timer) = timers.sleep_timer() {
+            playback_status.push(' ');
+            playback_status.push_str(&timer.label(std::time::Instant::now()));
+        }
+        if let Some(next) = timers.next_alarm(chrono::Local::now().naive_local()) {
+            playback_status.push_str(&format!(" Alarm {}", next.format("%H:%M")));
+        }
+        match ab_loop.map(|l| (l.a_ms, l.b_ms)) {
+            Some((a, Some(b))) => playback_status.push_str(&format!(" Loop {}-{}", metadata::format_duration(a), metadata::format_duration(b))),
+            Some((a, None)) => playback_status.push_str(&format!(" A {}", metadata::format_duration(a))),
+            None => {}
+        }
+        let position_ms = symphonia_ctrl.as_ref().map(|ctrl| ctrl.get_position()).unwrap_or(0);
+        let duration_ms = stream_info.as_ref().and_then(|info| info.duration_ms);
+
+        // Lyrics of the playing track, read in the background when the lyrics view needs them
+        let lyrics = now_playing.as_ref()
+            .filter(|_| panel_view == PanelView::Lyrics)
+            .and_then(|(path, _)| lyrics_cache.get(path));
+
+        let [_, panel_area, _] = screen_layout(terminal.size()?);
+        let panel_inner = Block::default().borders(Borders::ALL).inner(panel_area);
+
+        // Visualisers read what the output stream has played most recently
+        let tap = || symphonia_ctrl.as_ref()
+            .filter(|_| current_playing_idx.is_some())
+            .map(|ctrl| ctrl.tap.snapshot())
+            .unwrap_or_else(|| (Vec::new(), 0, 0));
+        let scope = match panel_view {
+            PanelView::Spectrum => {
+                let (samples, sample_rate, written) = tap();
+                spectrum.update(&samples, sample_rate, written, (panel_inner.width as usize / 2).clamp(1, 64));
+                Vec::new()
+            }
+            PanelView::Scope => scope_points(&tap().0, panel_inner.width as usize * 2),
+            _ => Vec::new(),
+        };
+
+        // Overview of the whole track along the bottom of the info view; click to seek
+        let waveform = now_playing.as_ref()
+            .filter(|_| panel_view == PanelView::Info)
+            .and_then(|(path, _)| waveform_cache.get(path).flatten());
+        let waveform_area = waveform.as_ref()
+            .filter(|_| panel_inner.height >= WAVEFORM_ROWS * 3)
+            .map(|_| Rect { y: panel_inner.bottom() - WAVEFORM_ROWS, height: WAVEFORM_ROWS, ..panel_inner });
+
+        // Cover art of the playing album, shown at the top of the Now Playing panel
+        let cover = now_playing.as_ref()
+            .filter(|_| art_view.protocol.is_some() && panel_view == PanelView::Info)
+            .and_then(|(path, tags)| {
+                let key = album_art::album_key(path, tags);
+                art_cache.get(&key, path).flatten().map(|image| (key, image))
+            });
+        let cover_area = cover.as_ref().and_then(|(_, image)| album_art::art_area(panel_inner, image.dimensions(), art_view.cell));
+        let cover_lines = match (&cover, cover_area) {
+            (Some((key, image)), Some(area)) => art_view.text_lines(key, image, area),
+            _ => None,
+        };
+        art_view.prepare(&mut terminal, cover.as_ref().zip(cover_area).map(|((key, _), area)| (key.as_str(), area)))?;
+
+        let track_keys = queue_keys.update(&mp3_files, &current_directory);
         terminal.draw(|f| {
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .margin(2)
-                .constraints([
-                    Constraint::Min(5),
-                    Constraint::Length(4),
-                ].as_ref())
-                .split(f.size());
+            let [queue_area, panel_area, controls_area] = screen_layout(f.size());
 
+            // Borders, highlight symbol and the playing marker take 6 columns
+            let now = stats::now_secs();
+            let name_width = (queue_area.width as usize).saturating_sub(6 + 29).max(10);
             let display_items: Vec<ListItem> = mp3_files.iter()
-                .map(|f| ListItem::new(get_display_name(f, &current_directory, show_title)))
+                .enumerate()
+                .map(|(idx, f)| {
+                    let name: String = metadata_cache.display_name(f, &current_directory, show_title).chars().take(name_width).collect();
+                    let track_stats = track_keys.get(idx).and_then(|key| play_stats.get(key));
+                    let name = format!("{:<width$}{}", name, stats::stats_columns(track_stats, now), width = name_width);
+                    let mark = if marked.contains(f) { "*" } else { " " };
+                    if Some(idx) == current_playing_idx {
+                        ListItem::new(format!("♪{}{}", mark, name))
+                            .style(Style::default().fg(Color::Green).add_modifier(Modifier::BOLD))
+                    } else {
+                        ListItem::new(format!(" {}{}", mark, name))
+                    }
+                })
                 .collect();
             
             let mode_str = if show_title { "Title" } else { "Filename" };
+            let loading = if metadata_cache.is_loading() { " (reading tags...)" } else { "" };
             let files_list = List::new(display_items)
-                .block(Block::default().borders(Borders::ALL).title(format!("MP3 Files [{}] - {}", mode_str, current_directory)))
+                .block(Block::default().borders(Borders::ALL).title(format!("MP3 Files [{}] [Sort: {}] - {}{}  (Rating Plays Skips Last)", mode_str, queue_sort.label(), current_directory, loading)))
                 .highlight_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
                 .highlight_symbol("▶ ");
-            f.render_stateful_widget(files_list, chunks[0], &mut state);
+            f.render_stateful_widget(files_list, queue_area, &mut state);
+
+            let mut info_lines: Vec<Line> = match &now_playing {
+                Some((path, tags)) => {
+                    let fallback = metadata_cache.display_name(path, ".", false);
+                    now_playing_lines(tags, stream_info.as_ref(), &fallback)
+                        .into_iter()
+                        .map(Line::from)
+                        .collect()
+                }
+                None => vec![Line::from("Nothing playing")],
+            };
+            if now_playing.is_some() {
+                info_lines.push(Line::from(""));
+                info_lines.push(Line::from(progress_line(position_ms, duration_ms, panel_inner.width as usize, ab_loop.map(|l| (l.a_ms, l.b_ms)))));
+            }
+            let text_area = match cover_area {
+                Some(area) => {
+                    if let Some(lines) = cover_lines {
+                        f.render_widget(Paragraph::new(lines), area);
+                    }
+                    let used = area.height + 1;
+                    Rect { y: panel_inner.y + used, height: panel_inner.height.saturating_sub(used), ..panel_inner }
+                }
+                None => panel_inner,
+            };
+            match panel_view {
+                PanelView::Info => {
+                    let text_area = match (&waveform, waveform_area) {
+                        (Some(waveform), Some(area)) => {
+                            f.render_widget(Paragraph::new(waveform_lines(waveform, area.width, area.height, position_ms)), area);
+                            Rect { height: area.y.saturating_sub(text_area.y + 1).min(text_area.height), ..text_area }
+                        }
+                        _ => text_area,
+                    };
+                    f.render_widget(Block::default().borders(Borders::ALL).title(format!("Now Playing [{}]", playback_status)), panel_area);
+                    f.render_widget(Paragraph::new(info_lines).wrap(Wrap { trim: false }), text_area);
+                }
+                PanelView::Lyrics => {
+                    let (title, lines) = match (&now_playing, &lyrics) {
+                        (Some(_), Some(Some(found))) => (
+                            format!("Lyrics ({}) [{}]", found.source.label(), playback_status),
+                            lyric_lines(found, position_ms, duration_ms, text_area.height as usize),
+                        ),
+                        (Some(_), Some(None)) => (format!("Lyrics [{}]", playback_status), vec![Line::from("No lyrics found (SYLT, .lrc file or USLT)")]),
+                        (Some(_), None) => (format!("Lyrics [{}]", playback_status), vec![Line::from("Reading lyrics...")]),
+                        (None, _) => (format!("Lyrics [{}]", playback_status), vec![Line::from("Nothing playing")]),
+                    };
+                    f.render_widget(Block::default().borders(Borders::ALL).title(title), panel_area);
+                    f.render_widget(Paragraph::new(lines), text_area);
+                }
+                PanelView::Spectrum => {
+                    let title = format!("Spectrum 40 Hz - 16 kHz [{}]", playback_status);
+                    f.render_widget(Block::default().borders(Borders::ALL).title(title), panel_area);
+                    f.render_widget(Paragraph::new(spectrum.lines(text_area.height)), text_area);
+                }
+                PanelView::Scope => {
+                    let width = scope.len().max(2) as f64;
+                    let canvas = Canvas::default()
+                        .block(Block::default().borders(Borders::ALL).title(format!("Oscilloscope [{}]", playback_status)))
+                        .marker(symbols::Marker::Braille)
+                        .x_bounds([0.0, width - 1.0])
+                        .y_bounds([-1.0, 1.0])
+                        .paint(|ctx| {
+                            for pair in scope.windows(2) {
+                                ctx.draw(&CanvasLine { x1: pair[0].0, y1: pair[0].1, x2: pair[1].0, y2: pair[1].1, color: Color::Green });
+                            }
+                        });
+                    f.render_widget(canvas, panel_area);
+                }
+            }
 
-            let controls = Paragraph::new("Controls: [Up/Down] Select  [P] Play  [Z] Pause/Resume  [S] Stop  [PgUp/PgDn] Prev/Next  [M] Mode  [H] Shuffle  [O] Original  [F] Folder  [C] Clear  [Q] Quit")
-                .block(Block::default().borders(Borders::ALL).title("Controls"));
-            f.render_widget(controls, chunks[1]);
+            let controls = Paragraph::new("Controls: [Up/Down] Select  [P] Play  [Z] Pause/Resume  [S] Stop  [Left/Right] Seek  [A/B] Loop  [ / ] Speed  [Backspace] 1x  [PgUp/PgDn] Prev/Next  [M] Mode  [V] Info/Lyrics/Spectrum/Scope  [G] Equaliser  [D] Sleep Timer  [W] Alarms  [H] Shuffle  [O] Original  [T] Sort  [1-5/0] Rate  [*] Favourite  [Space] Mark  [E] Edit Tags  [N] Rename  [I] Tags From Name  [U] Loudness Scan  [F] Folder  [L] Library  [/] Search  [Y] Smart Playlists  [R] History  [C] Clear  [Q] Quit")
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(controls, controls_area);
         })?;
+        if let (Some((key, image)), Some(area)) = (&cover, cover_area) {
+            art_view.present(terminal.backend_mut(), key, image, area)?;
+        }
 
-        if event::poll(std::time::Duration::from_millis(200))? {
-            if let event::Event::Key(key) = event::read()? {
-                // Only handle KeyPress events, ignore KeyRelease and KeyRepeat
-                if key.kind != event::KeyEventKind::Press {
-                    continue;
+        let seekable = symphonia_ctrl.as_ref()
+            .filter(|_| current_playing_idx.is_some())
+            .zip(waveform.as_ref().map(|w| w.duration_ms).or(duration_ms));
+        if event::poll(panel_view.refresh_interval())?
+            && let Some(key) = match event::read()? {
+                event::Event::Key(key) => Some(key),
+                event::Event::Mouse(mouse) => {
+                    // A click on the waveform jumps to that point of the track
+                    if let event::MouseEventKind::Down(event::MouseButton::Left) = mouse.kind
+                        && let (Some(area), Some((ctrl, duration))) = (waveform_area, seekable)
+                        && area.contains(ratatui::layout::Position { x: mouse.column, y: mouse.row })
+                    {
+                        ctrl.seek(seek_position(mouse.column, area, duration));
+                    }
+                    None
                 }
-                match key.code {
-                    event::KeyCode::Char('q') | event::KeyCode::Char('Q') => {
-                        if debug_mode {
-                            println!("[DEBUG] Quit pressed");
+                _ => None,
+            }
+        {
+            // Only handle KeyPress events, ignore KeyRelease and KeyRepeat
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            let mut queue_action: Option<QueueAction> = None;
+            match key.code {
+                event::KeyCode::Char('q') | event::KeyCode::Char('Q') => {
+                    if debug_mode {
+                        println!("[DEBUG] Quit pressed");
+                    }
+                    running = false;
+                },
+                event::KeyCode::Left | event::KeyCode::Right => {
+                    if let Some((ctrl, duration)) = seekable {
+                        let step = if key.modifiers.contains(event::KeyModifiers::SHIFT) { 30_000 } else { 5_000 };
+                        let position = ctrl.get_position();
+                        let target = if key.code == event::KeyCode::Left {
+                            position.saturating_sub(step)
+                        } else {
+                            (position + step).min(duration.saturating_sub(1000))
+                        };
+                        ctrl.seek(target);
+                    }
+                },
+                event::KeyCode::Char('v') | event::KeyCode::Char('V') => {
+                    panel_view = panel_view.next();
+                    if debug_mode {
+                        println!("[DEBUG] Now Playing panel shows {:?}", panel_view);
+                    }
+                },
+                event::KeyCode::Char('g') | event::KeyCode::Char('G') => {
+                    art_view.hide(&mut terminal)?;
+                    edit_equalizer(&mut terminal, &playback_settings.eq, &mut config.eq_presets, debug_mode)?;
+                    config.eq = playback_settings.eq.lock().unwrap().clone();
+                    if let Err(e) = config::save_values(config::CONFIG_FILE, &config::eq_values(&config.eq, &config.eq_presets))
+                        && debug_mode
+                    {
+                        println!("[DEBUG] Failed to save equaliser settings: {}", e);
+                    }
+                },
+                event::KeyCode::Char('u') | event::KeyCode::Char('U') => {
+                    // Marked tracks, or the whole queue, plus the rest of their albums so album gains see every track
+                    let files: Vec<&String> = if marked.is_empty() { mp3_files.iter().collect() } else { mp3_files.iter().filter(|f| marked.contains(*f)).collect() };
+                    let paths: Vec<String> = files.iter().map(|f| stats::stats_key(&get_track_path(f, &current_directory))).collect();
+                    let paths = with_album_tracks(&paths, &library.lock().unwrap());
+                    if !paths.is_empty() {
+                        art_view.hide(&mut terminal)?;
+                        let tagged = scan_loudness(&mut terminal, &paths, debug_mode)?;
+                        refresh_tags(&tagged, &metadata_cache, &library);
+                    }
+                },
+                event::KeyCode::Char('[') | event::KeyCode::Char(']') | event::KeyCode::Backspace => {
+                    let speed = match key.code {
+                        event::KeyCode::Char('[') => playback_settings.adjust_speed(-timestretch::SPEED_STEP),
+                        event::KeyCode::Char(']') => playback_settings.adjust_speed(timestretch::SPEED_STEP),
+                        _ => {
+                            playback_settings.reset_speed();
+                            1.0
                         }
-                        running = false;
-                    },
-                    event::KeyCode::Char('m') | event::KeyCode::Char('M') => {
-                        show_title = !show_title;
-                        if debug_mode {
-                            let mode = if show_title { "Title" } else { "Filename" };
-                            println!("[DEBUG] Display mode switched to: {}", mode);
+                    };
+                    // Re-seeking to where we are drops audio queued at the old speed
+                    if let Some(ctrl) = &symphonia_ctrl
+                        && current_playing_idx.is_some()
+                    {
+                        ctrl.seek(ctrl.get_position());
+                    }
+                    if debug_mode {
+                        println!("[DEBUG] Playback speed {:.1}x", speed);
+                    }
+                },
+                event::KeyCode::Char('a') | event::KeyCode::Char('A') => {
+                    if let Some(ctrl) = &symphonia_ctrl
+                        && current_playing_idx.is_some()
+                    {
+                        ctrl.set_loop_a(ctrl.get_position());
+                    }
+                },
+                event::KeyCode::Char('b') | event::KeyCode::Char('B') => {
+                    if let Some(ctrl) = &symphonia_ctrl
+                        && current_playing_idx.is_some()
+                    {
+                        if ctrl.get_loop().and_then(|l| l.section()).is_some() {
+                            ctrl.clear_loop();
+                        } else if ctrl.set_loop_b(ctrl.get_position()) {
+                            let a = ctrl.get_loop().map(|l| l.a_ms).unwrap_or(0);
+                            if !timestretch::is_normal_speed(config.ab_loop_speed) {
+                                speed_before_loop.get_or_insert(playback_settings.speed());
+                                *playback_settings.speed.lock().unwrap() = config.ab_loop_speed;
+                            }
+                            // Start practising straight away
+                            ctrl.seek(a);
                         }
-                    },
-                    event::KeyCode::Char('h') | event::KeyCode::Char('H') => {
-                        // Shuffle
-                        let mut rng = rand::thread_rng();
-                        mp3_files.shuffle(&mut rng);
-                        state.select(if !mp3_files.is_empty() { Some(0) } else { None });
-                        current_playing_idx = None;
-                        if debug_mode {
-                            println!("[DEBUG] Shuffled {} tracks", mp3_files.len());
+                    }
+                },
+                event::KeyCode::Char('d') | event::KeyCode::Char('D') => {
+                    art_view.hide(&mut terminal)?;
+                    if let Ok(timer) = choose_sleep_timer(&mut terminal, timers.sleep_timer(), debug_mode) {
+                        timers.set_sleep_timer(timer);
+                    }
+                },
+                event::KeyCode::Char('w') | event::KeyCode::Char('W') => {
+                    art_view.hide(&mut terminal)?;
+                    edit_alarms(&mut terminal, &mut config.alarms, debug_mode)?;
+                    timers.set_alarms(&config.alarms);
+                    if let Err(e) = config::save_values(config::CONFIG_FILE, &config::alarm_values(&config.alarms))
+                        && debug_mode
+                    {
+                        println!("[DEBUG] Failed to save alarms: {}", e);
+                    }
+                },
+                event::KeyCode::Char('m') | event::KeyCode::Char('M') => {
+                    show_title = !show_title;
+                    if debug_mode {
+                        let mode = if show_title { "Title" } else { "Filename" };
+                        println!("[DEBUG] Display mode switched to: {}", mode);
+                    }
+                },
+                event::KeyCode::Char('h') | event::KeyCode::Char('H') => {
+                    // Shuffle
+                    let mut rng = rand::thread_rng();
+                    mp3_files.shuffle(&mut rng);
+                    state.select(if !mp3_files.is_empty() { Some(0) } else { None });
+                    current_playing_idx = None;
+                    if debug_mode {
+                        println!("[DEBUG] Shuffled {} tracks", mp3_files.len());
+                    }
+                },
+                event::KeyCode::Char('o') | event::KeyCode::Char('O') => {
+                    // Restore original order
+                    mp3_files = original_mp3_files.clone();
+                    state.select(if !mp3_files.is_empty() { Some(0) } else { None });
+                    current_playing_idx = None;
+                    if debug_mode {
+                        println!("[DEBUG] Restored original track order");
+                    }
+                },
+                event::KeyCode::Char(c @ ('0'..='5' | '*')) => {
+                    // Rate the selected track, or the playing one when nothing is selected
+                    if let Some(file) = state.selected().or(current_playing_idx).and_then(|idx| mp3_files.get(idx)) {
+                        let track_path = get_track_path(file, &current_directory);
+                        let key = stats::stats_key(&track_path);
+                        if c == '*' {
+                            let favourite = play_stats.toggle_favourite(&key);
+                            if debug_mode {
+                                println!("[DEBUG] Favourite {} -> {}", key, favourite);
+                            }
+                        } else {
+                            let stars = c.to_digit(10).filter(|n| *n > 0).map(|n| n as u8);
+                            play_stats.set_rating(&key, stars);
+                            if config.write_ratings_to_tags {
+                                let play_count = play_stats.get(&key).map(|s| s.play_count).unwrap_or(0);
+                                let result = metadata::write_popularimeter(&track_path, stars, play_count);
+                                if debug_mode {
+                                    println!("[DEBUG] POPM write for {}: {:?}", track_path, result.err().map(|e| e.to_string()));
+                                }
+                            }
+                            if debug_mode {
+                                println!("[DEBUG] Rating {} -> {:?}", key, stars);
+                            }
                         }
-                    },
-                    event::KeyCode::Char('o') | event::KeyCode::Char('O') => {
-                        // Restore original order
-                        mp3_files = original_mp3_files.clone();
-                        state.select(if !mp3_files.is_empty() { Some(0) } else { None });
-                        current_playing_idx = None;
-                        if debug_mode {
-                            println!("[DEBUG] Restored original track order");
+                        let _ = play_stats.save_to(STATS_FILE);
+                    }
+                },
+                event::KeyCode::Char(' ') => {
+                    if let Some(file) = state.selected().and_then(|idx| mp3_files.get(idx))
+                        && !marked.remove(file)
+                    {
+                        marked.insert(file.clone());
+                    }
+                },
+                event::KeyCode::Char('e') | event::KeyCode::Char('E') => {
+                    let files = marked_or_selected(&mp3_files, &marked, state.selected());
+                    let paths: Vec<String> = files.iter().map(|f| get_track_path(f, &current_directory)).collect();
+                    if !paths.is_empty() {
+                        art_view.hide(&mut terminal)?;
+                        match edit_tags(&mut terminal, &paths, debug_mode) {
+                            Ok(written) => {
+                                refresh_tags(&paths, &metadata_cache, &library);
+                                marked.clear();
+                                if debug_mode {
+                                    println!("[DEBUG] Tags written to {} files", written);
+                                }
+                            },
+                            Err(_) => {
+                                if debug_mode {
+                                    println!("[DEBUG] Tag editor closed");
+                                }
+                            }
                         }
-                    },
-                    event::KeyCode::Char('f') | event::KeyCode::Char('F') => {
-                        if debug_mode {
-                            println!("[DEBUG] Folder browser requested");
-                        }
-                        match browse_folders(&mut terminal, debug_mode) {
-                            Ok(selected_folder) => {
-                                current_directory = selected_folder;
-                                if let Ok(new_files) = load_mp3_files(&current_directory) {
-                                    mp3_files = new_files;
-                                    state.select(if !mp3_files.is_empty() { Some(0) } else { None });
-                                    let _ = save_queue(&mp3_files, &current_directory);
-                                    if debug_mode {
-                                        println!("[DEBUG] Loaded {} files from {}", mp3_files.len(), current_directory);
+                    }
+                },
+                event::KeyCode::Char('i') | event::KeyCode::Char('I') => {
+                    let files = marked_or_selected(&mp3_files, &marked, state.selected());
+                    let paths: Vec<String> = files.iter().map(|f| get_track_path(f, &current_directory)).collect();
+                    if !paths.is_empty() {
+                        art_view.hide(&mut terminal)?;
+                        match tags_from_filenames(&mut terminal, &paths, &config.filename_pattern, debug_mode) {
+                            Ok(written) => {
+                                refresh_tags(&written, &metadata_cache, &library);
+                                marked.clear();
+                                if debug_mode {
+                                    println!("[DEBUG] Tagged {} files from their names", written.len());
+                                }
+                            },
+                            Err(_) => {
+                                if debug_mode {
+                                    println!("[DEBUG] Tags from file names cancelled");
+                                }
+                            }
+                        }
+                    }
+                },
+                event::KeyCode::Char('n') | event::KeyCode::Char('N') => {
+                    let files = marked_or_selected(&mp3_files, &marked, state.selected());
+                    let paths: Vec<String> = files.iter().map(|f| stats::stats_key(&get_track_path(f, &current_directory))).collect();
+                    let base = std::path::absolute(&current_directory).unwrap_or_else(|_| PathBuf::from(&current_directory));
+                    if !paths.is_empty() {
+                        art_view.hide(&mut terminal)?;
+                        match rename_files(&mut terminal, &paths, &base, &config.rename_template, debug_mode) {
+                            Ok(moved) => {
+                                let renamed: std::collections::HashMap<&String, &String> = moved.iter().map(|(from, to)| (from, to)).collect();
+                                // Queue entries become absolute paths to the new location
+                                for list in [&mut mp3_files, &mut original_mp3_files] {
+                                    for entry in list.iter_mut() {
+                                        if let Some(to) = renamed.get(&stats::stats_key(&get_track_path(entry, &current_directory))) {
+                                            *entry = (*to).clone();
+                                        }
+                                    }
+                                }
+                                let mut lib = library.lock().unwrap();
+                                for (from, to) in &moved {
+                                    metadata_cache.invalidate(from);
+                                    play_stats.rename(from, to);
+                                    if lib.remove_path(from) > 0 {
+                                        lib.upsert_file(to);
                                     }
                                 }
+                                marked.clear();
+                                let _ = play_stats.save_to(STATS_FILE);
+                                let _ = save_queue(&mp3_files, &current_directory);
+                                if debug_mode {
+                                    println!("[DEBUG] Renamed {} files", moved.len());
+                                }
                             },
                             Err(_) => {
                                 if debug_mode {
-                                    println!("[DEBUG] Folder selection cancelled");
+                                    println!("[DEBUG] Rename cancelled");
                                 }
                             }
                         }
-                    },
-                    event::KeyCode::Char('c') | event::KeyCode::Char('C') => {
+                    }
+                },
+                event::KeyCode::Char('t') | event::KeyCode::Char('T') => {
+                    // Sort by the next column, keeping the playing track marked
+                    queue_sort = queue_sort.next();
+                    let playing = current_playing_idx.and_then(|idx| mp3_files.get(idx)).cloned();
+                    mp3_files = original_mp3_files.clone();
+                    stats::sort_queue(&mut mp3_files, &current_directory, queue_sort, &play_stats, |path| {
+                        let tags = metadata_cache.get(path).unwrap_or_default();
+                        let title = tags.title.unwrap_or_else(|| metadata_cache.display_name(path, ".", false));
+                        (title, tags.artist.unwrap_or_default())
+                    });
+                    current_playing_idx = playing.and_then(|file| mp3_files.iter().position(|f| *f == file));
+                    state.select(if !mp3_files.is_empty() { Some(0) } else { None });
+                    if debug_mode {
+                        println!("[DEBUG] Queue sorted by {}", queue_sort.label());
+                    }
+                },
+                event::KeyCode::Char('f') | event::KeyCode::Char('F') => {
+                    if debug_mode {
+                        println!("[DEBUG] Folder browser requested");
+                    }
+                    art_view.hide(&mut terminal)?;
+                    match browse_folders(&mut terminal, debug_mode) {
+                        Ok(action) => queue_action = Some(action),
+                        Err(_) => {
+                            if debug_mode {
+                                println!("[DEBUG] Folder selection cancelled");
+                            }
+                        }
+                    }
+                },
+                event::KeyCode::Char('l') | event::KeyCode::Char('L') => {
+                    if debug_mode {
+                        println!("[DEBUG] Library requested");
+                    }
+                    let roots = config.library_roots_or_default();
+                    if library.lock().unwrap().tracks.is_empty() {
+                        library_scanner.start(roots.clone(), library.clone(), Some(LIBRARY_FILE.to_string()));
+                    }
+                    art_view.hide(&mut terminal)?;
+                    match browse_library(&mut terminal, &library, &library_scanner, &roots, debug_mode) {
+                        Ok(action) => queue_action = Some(action),
+                        Err(_) => {
+                            if debug_mode {
+                                println!("[DEBUG] Library closed");
+                            }
+                        }
+                    }
+                },
+                event::KeyCode::Char('/') => {
+                    if debug_mode {
+                        println!("[DEBUG] Library search requested");
+                    }
+                    if library.lock().unwrap().tracks.is_empty() {
+                        library_scanner.start(config.library_roots_or_default(), library.clone(), Some(LIBRARY_FILE.to_string()));
+                    }
+                    art_view.hide(&mut terminal)?;
+                    match search_library(&mut terminal, &library, debug_mode) {
+                        Ok(action) => queue_action = Some(action),
+                        Err(_) => {
+                            if debug_mode {
+                                println!("[DEBUG] Search closed");
+                            }
+                        }
+                    }
+                },
+                event::KeyCode::Char('r') | event::KeyCode::Char('R') => {
+                    if debug_mode {
+                        println!("[DEBUG] Listening history requested");
+                    }
+                    art_view.hide(&mut terminal)?;
+                    match browse_history(&mut terminal, debug_mode) {
+                        Ok(action) => queue_action = Some(action),
+                        Err(_) => {
+                            if debug_mode {
+                                println!("[DEBUG] History closed");
+                            }
+                        }
+                    }
+                },
+                event::KeyCode::Char('y') | event::KeyCode::Char('Y') => {
+                    if debug_mode {
+                        println!("[DEBUG] Smart playlists requested");
+                    }
+                    if library.lock().unwrap().tracks.is_empty() {
+                        library_scanner.start(config.library_roots_or_default(), library.clone(), Some(LIBRARY_FILE.to_string()));
+                    }
+                    art_view.hide(&mut terminal)?;
+                    match manage_playlists(&mut terminal, &library, &play_stats, debug_mode) {
+                        Ok(action) => queue_action = Some(action),
+                        Err(_) => {
+                            if debug_mode {
+                                println!("[DEBUG] Smart playlists closed");
+                            }
+                        }
+                    }
+                },
+                event::KeyCode::Char('c') | event::KeyCode::Char('C') => {
+                    if debug_mode {
+                        println!("[DEBUG] Clear queue pressed");
+                    }
+                    mp3_files.clear();
+                    marked.clear();
+                    state.select(None);
+                    current_playing_idx = None;
+                    let _ = save_queue(&mp3_files, &current_directory);
+                },
+                event::KeyCode::Down if !mp3_files.is_empty() => {
+                    let i = match state.selected() {
+                        Some(i) => {
+                            if i >= mp3_files.len() - 1 { 0 } else { i + 1 }
+                        },
+                        None => 0,
+                    };
+                    state.select(Some(i));
+                    if debug_mode {
+                        println!("[DEBUG] Down pressed, selected index: {}", i);
+                    }
+                },
+                event::KeyCode::Up if !mp3_files.is_empty() => {
+                    let i = match state.selected() {
+                        Some(i) => {
+                            if i == 0 { mp3_files.len() - 1 } else { i - 1 }
+                        },
+                        None => 0,
+                    };
+                    state.select(Some(i));
+                    if debug_mode {
+                        println!("[DEBUG] Up pressed, selected index: {}", i);
+                    }
+                },
+                event::KeyCode::Char('s') | event::KeyCode::Char('S') => {
+                    if let Some(ctrl) = &symphonia_ctrl {
                         if debug_mode {
-                            println!("[DEBUG] Clear queue pressed");
+                            println!("[DEBUG] Symphonia STOP");
                         }
-                        mp3_files.clear();
-                        state.select(None);
-                        current_playing_idx = None;
-                        let _ = save_queue(&mp3_files, &current_directory);
-                    },
-                    event::KeyCode::Down => {
-                        let i = match state.selected() {
+                        finish_listen(&mut play_stats, &mut listening, Some(ctrl), &metadata_cache, ListenEnd::Stopped, config.skip_threshold, debug_mode);
+                        ctrl.stop();
+                    }
+                },
+                event::KeyCode::Char('p') | event::KeyCode::Char('P') => {
+                    if let Some(idx) = state.selected()
+                        && let Some(file) = mp3_files.get(idx)
+                    {
+                        if debug_mode {
+                            println!("[DEBUG] Symphonia playback: {}", file);
+                        }
+                        finish_listen(&mut play_stats, &mut listening, symphonia_ctrl.as_ref(), &metadata_cache, ListenEnd::Skipped, config.skip_threshold, debug_mode);
+                        if let Some(ctrl) = &symphonia_ctrl {
+                            ctrl.stop();
+                        }
+                        let (ctrl, handle) = start_playback(file, &current_directory, &playback_settings, queue_album_context(&mp3_files, idx, &current_directory, &metadata_cache));
+                        listening = Some(ListenSession::start(&get_track_path(file, &current_directory), playback_settings.speed()));
+                        symphonia_ctrl = Some(ctrl);
+                        _symphonia_thread = Some(handle);
+                        current_playing_idx = Some(idx);
+                    }
+                },
+                // Pause/Resume for Symphonia
+                event::KeyCode::Char('z') | event::KeyCode::Char('Z') => {
+                    if let Some(ctrl) = &symphonia_ctrl {
+                        if ctrl.is_paused() {
+                            if debug_mode {
+                                println!("[DEBUG] Symphonia Resume (Z)");
+                            }
+                            ctrl.resume();
+                            if let Some(session) = listening.as_mut() {
+                                session.resume();
+                            }
+                        } else {
+                            if debug_mode {
+                                println!("[DEBUG] Symphonia Pause (Z)");
+                            }
+                            ctrl.pause();
+                            if let Some(session) = listening.as_mut() {
+                                session.pause();
+                            }
+                        }
+                    } else if debug_mode {
+                        println!("[DEBUG] No symphonia playback");
+                    }
+                },
+                event::KeyCode::PageDown if !mp3_files.is_empty() => {
+                // Play next track
+                    let next_idx = match current_playing_idx {
+                        Some(idx) => {
+                            if idx >= mp3_files.len() - 1 { 0 } else { idx + 1 }
+                        },
+                        None => match state.selected() {
                             Some(i) => {
                                 if i >= mp3_files.len() - 1 { 0 } else { i + 1 }
                             },
                             None => 0,
-                        };
-                        state.select(Some(i));
+                        }
+                    };
+                    
+                    if let Some(file) = mp3_files.get(next_idx) {
                         if debug_mode {
-                            println!("[DEBUG] Down pressed, selected index: {}", i);
+                            println!("[DEBUG] PageDown pressed - Play next track: {}", file);
                         }
-                    },
-                    event::KeyCode::Up => {
-                        let i = match state.selected() {
+                        state.select(Some(next_idx));
+                        
+                        finish_listen(&mut play_stats, &mut listening, symphonia_ctrl.as_ref(), &metadata_cache, ListenEnd::Skipped, config.skip_threshold, debug_mode);
+                        if let Some(ctrl) = &symphonia_ctrl {
+                            ctrl.stop();
+                        }
+                        
+                        let (ctrl, handle) = start_playback(file, &current_directory, &playback_settings, queue_album_context(&mp3_files, next_idx, &current_directory, &metadata_cache));
+                        listening = Some(ListenSession::start(&get_track_path(file, &current_directory), playback_settings.speed()));
+                        symphonia_ctrl = Some(ctrl);
+                        _symphonia_thread = Some(handle);
+                        current_playing_idx = Some(next_idx);
+                    }
+                },
+                event::KeyCode::PageUp if !mp3_files.is_empty() => {
+                // Play previous track
+                    let prev_idx = match current_playing_idx {
+                        Some(idx) => {
+                            if idx == 0 { mp3_files.len() - 1 } else { idx - 1 }
+                        },
+                        None => match state.selected() {
                             Some(i) => {
                                 if i == 0 { mp3_files.len() - 1 } else { i - 1 }
                             },
-                            None => 0,
-                        };
-                        state.select(Some(i));
+                            None => mp3_files.len() - 1,
+                        }
+                    };
+                    
+                    if let Some(file) = mp3_files.get(prev_idx) {
                         if debug_mode {
-                            println!("[DEBUG] Up pressed, selected index: {}", i);
+                            println!("[DEBUG] PageUp pressed - Play previous track: {}", file);
                         }
-                    },
-                    event::KeyCode::Char('s') | event::KeyCode::Char('S') => {
+                        state.select(Some(prev_idx));
+                        
+                        finish_listen(&mut play_stats, &mut listening, symphonia_ctrl.as_ref(), &metadata_cache, ListenEnd::Skipped, config.skip_threshold, debug_mode);
                         if let Some(ctrl) = &symphonia_ctrl {
-                            if debug_mode {
-                                println!("[DEBUG] Symphonia STOP");
-                            }
                             ctrl.stop();
                         }
-                    },
-                    event::KeyCode::Char('p') | event::KeyCode::Char('P') => {
-                        if let Some(idx) = state.selected() {
-                            if let Some(file) = mp3_files.get(idx) {
-                                if debug_mode {
-                                    println!("[DEBUG] Symphonia playback: {}", file);
-                                }
-                                if let Some(ctrl) = &symphonia_ctrl {
-                                    ctrl.stop();
-                                }
-                                let ctrl = PlaybackControl::new();
-                                let fname = if current_directory == "." {
-                                    file.clone()
-                                } else {
-                                    format!("{}\\{}", current_directory, file)
-                                };
-                                let handle = std::thread::spawn({
-                                    let ctrl = ctrl.clone();
-                                    move || {
-                                        let _ = play_mp3_with_symphonia(&fname, ctrl);
-                                    }
-                                });
-                                symphonia_ctrl = Some(ctrl);
-                                _symphonia_thread = Some(handle);
-                                current_playing_idx = Some(idx);
+                        
+                        let (ctrl, handle) = start_playback(file, &current_directory, &playback_settings, queue_album_context(&mp3_files, prev_idx, &current_directory, &metadata_cache));
+                        listening = Some(ListenSession::start(&get_track_path(file, &current_directory), playback_settings.speed()));
+                        symphonia_ctrl = Some(ctrl);
+                        _symphonia_thread = Some(handle);
+                        current_playing_idx = Some(prev_idx);
+                    }
+                },
+                _ => {}
+            }
+
+            // Queue changes requested by the folder browser or the library
+            if let Some(action) = queue_action {
+                match action {
+                    QueueAction::LoadDirectory(selected_folder) => {
+                        marked.clear();
+                        current_directory = selected_folder;
+                        if let Ok(new_files) = load_mp3_files(&current_directory) {
+                            mp3_files = new_files;
+                            original_mp3_files = mp3_files.clone();
+                            state.select(if !mp3_files.is_empty() { Some(0) } else { None });
+                            current_playing_idx = None;
+                            let _ = save_queue(&mp3_files, &current_directory);
+                            if debug_mode {
+                                println!("[DEBUG] Loaded {} files from {}", mp3_files.len(), current_directory);
                             }
                         }
                     },
-                    // Pause/Resume for Symphonia
-                    event::KeyCode::Char('z') | event::KeyCode::Char('Z') => {
-                        if let Some(ctrl) = &symphonia_ctrl {
-                            if ctrl.is_paused() {
-                                if debug_mode {
-                                    println!("[DEBUG] Symphonia Resume (Z)");
-                                }
-                                ctrl.resume();
-                            } else {
-                                if debug_mode {
-                                    println!("[DEBUG] Symphonia Pause (Z)");
-                                }
-                                ctrl.pause();
-                            }
-                        } else if debug_mode {
-                            println!("[DEBUG] No symphonia playback");
+                    QueueAction::Replace(tracks) => {
+                        marked.clear();
+                        mp3_files = tracks;
+                        original_mp3_files = mp3_files.clone();
+                        state.select(if !mp3_files.is_empty() { Some(0) } else { None });
+                        current_playing_idx = None;
+                        let _ = save_queue(&mp3_files, &current_directory);
+                        if debug_mode {
+                            println!("[DEBUG] Replaced queue with {} tracks", mp3_files.len());
                         }
                     },
-                    event::KeyCode::PageDown => {
-                        // Play next track
-                        if !mp3_files.is_empty() {
-                            let next_idx = match current_playing_idx {
-                                Some(idx) => {
-                                    if idx >= mp3_files.len() - 1 { 0 } else { idx + 1 }
-                                },
-                                None => match state.selected() {
-                                    Some(i) => {
-                                        if i >= mp3_files.len() - 1 { 0 } else { i + 1 }
-                                    },
-                                    None => 0,
-                                }
-                            };
-                            
-                            if let Some(file) = mp3_files.get(next_idx) {
-                                if debug_mode {
-                                    println!("[DEBUG] PageDown pressed - Play next track: {}", file);
-                                }
-                                state.select(Some(next_idx));
-                                
-                                if let Some(ctrl) = &symphonia_ctrl {
-                                    ctrl.stop();
-                                }
-                                
-                                let ctrl = PlaybackControl::new();
-                                let fname = if current_directory == "." {
-                                    file.clone()
-                                } else {
-                                    format!("{}\\{}", current_directory, file)
-                                };
-                                let handle = std::thread::spawn({
-                                    let ctrl = ctrl.clone();
-                                    move || {
-                                        let _ = play_mp3_with_symphonia(&fname, ctrl);
-                                    }
-                                });
-                                symphonia_ctrl = Some(ctrl);
-                                _symphonia_thread = Some(handle);
-                                current_playing_idx = Some(next_idx);
-                            }
+                    QueueAction::Append(tracks) => {
+                        if debug_mode {
+                            println!("[DEBUG] Appended {} tracks", tracks.len());
+                        }
+                        original_mp3_files.extend(tracks.iter().cloned());
+                        mp3_files.extend(tracks);
+                        if state.selected().is_none() && !mp3_files.is_empty() {
+                            state.select(Some(0));
                         }
+                        let _ = save_queue(&mp3_files, &current_directory);
                     },
-                    event::KeyCode::PageUp => {
-                        // Play previous track
-                        if !mp3_files.is_empty() {
-                            let prev_idx = match current_playing_idx {
-                                Some(idx) => {
-                                    if idx == 0 { mp3_files.len() - 1 } else { idx - 1 }
-                                },
-                                None => match state.selected() {
-                                    Some(i) => {
-                                        if i == 0 { mp3_files.len() - 1 } else { i - 1 }
-                                    },
-                                    None => mp3_files.len() - 1,
-                                }
-                            };
-                            
-                            if let Some(file) = mp3_files.get(prev_idx) {
-                                if debug_mode {
-                                    println!("[DEBUG] PageUp pressed - Play previous track: {}", file);
-                                }
-                                state.select(Some(prev_idx));
-                                
-                                if let Some(ctrl) = &symphonia_ctrl {
-                                    ctrl.stop();
-                                }
-                                
-                                let ctrl = PlaybackControl::new();
-                                let fname = if current_directory == "." {
-                                    file.clone()
-                                } else {
-                                    format!("{}\\{}", current_directory, file)
-                                };
-                                let handle = std::thread::spawn({
-                                    let ctrl = ctrl.clone();
-                                    move || {
-                                        let _ = play_mp3_with_symphonia(&fname, ctrl);
-                                    }
-                                });
-                                symphonia_ctrl = Some(ctrl);
-                                _symphonia_thread = Some(handle);
-                                current_playing_idx = Some(prev_idx);
+                    QueueAction::PlayNow(tracks) => {
+                        let first_idx = mp3_files.len();
+                        original_mp3_files.extend(tracks.iter().cloned());
+                        mp3_files.extend(tracks);
+                        let _ = save_queue(&mp3_files, &current_directory);
+                        if let Some(file) = mp3_files.get(first_idx) {
+                            if debug_mode {
+                                println!("[DEBUG] Play now: {}", file);
+                            }
+                            finish_listen(&mut play_stats, &mut listening, symphonia_ctrl.as_ref(), &metadata_cache, ListenEnd::Skipped, config.skip_threshold, debug_mode);
+                            if let Some(ctrl) = &symphonia_ctrl {
+                                ctrl.stop();
                             }
+                            let (ctrl, handle) = start_playback(file, &current_directory, &playback_settings, queue_album_context(&mp3_files, first_idx, &current_directory, &metadata_cache));
+                            listening = Some(ListenSession::start(&get_track_path(file, &current_directory), playback_settings.speed()));
+                            symphonia_ctrl = Some(ctrl);
+                            _symphonia_thread = Some(handle);
+                            current_playing_idx = Some(first_idx);
+                            state.select(Some(first_idx));
                         }
                     },
-                    _ => {}
                 }
             }
         }
     }
 
+    finish_listen(&mut play_stats, &mut listening, symphonia_ctrl.as_ref(), &metadata_cache, ListenEnd::Stopped, config.skip_threshold, debug_mode);
+    metadata_cache.save();
+    {
+        let library = library.lock().unwrap();
+        if library.generation != library_generation && !library_scanner.is_running() {
+            let _ = library.save_to(LIBRARY_FILE);
+        }
+    }
+
     // Restore terminal
+    art_view.hide(&mut terminal)?;
     disable_raw_mode()?;
-    execute!(io::stdout(), LeaveAlternateScreen)?;
+    execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
     Ok(())
 }
@@ -0,0 +1,243 @@
+use id3::TagLike;
+
+/// Tag fields read from a track's ID3 frames.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i32>,
+    pub track: Option<u32>,
+    pub total_tracks: Option<u32>,
+    pub disc: Option<u32>,
+    pub total_discs: Option<u32>,
+}
+
+impl TrackTags {
+    /// Reads the ID3 tag of `file_path`. Untagged or unreadable files give empty tags.
+    pub fn read(file_path: &str) -> Self {
+        match id3::Tag::read_from_path(file_path) {
+            Ok(tag) => Self::from_tag(&tag),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn from_tag(tag: &id3::Tag) -> Self {
+        let text = |s: Option<&str>| s.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+        Self {
+            title: text(tag.title()),
+            artist: text(tag.artist()),
+            album: text(tag.album()),
+            album_artist: text(tag.album_artist()),
+            composer: text(tag.get("TCOM").and_then(|f| f.content().text())),
+            comment: text(tag.comments().next().map(|c| c.text.as_str())),
+            genre: text(tag.genre_parsed().as_deref()),
+            year: tag.year().or_else(|| tag.date_recorded().map(|d| d.year)),
+            track: tag.track(),
+            total_tracks: tag.total_tracks(),
+            disc: tag.disc(),
+            total_discs: tag.total_discs(),
+        }
+    }
+
+    /// Number of tab-separated fields produced by `to_fields`.
+    pub const FIELD_COUNT: usize = 12;
+
+    /// Serialises the tags as escaped, tab-separable fields for the on-disk caches.
+    pub fn to_fields(&self) -> Vec<String> {
+        let text = |v: &Option<String>| v.as_deref().map(escape_field).unwrap_or_default();
+        let number = |v: Option<u32>| v.map(|n| n.to_string()).unwrap_or_default();
+        vec![
+            text(&self.title),
+            text(&self.artist),
+            text(&self.album),
+            text(&self.album_artist),
+            text(&self.composer),
+            text(&self.comment),
+            text(&self.genre),
+            self.year.map(|y| y.to_string()).unwrap_or_default(),
+            number(self.track),
+            number(self.total_tracks),
+            number(self.disc),
+            number(self.total_discs),
+        ]
+    }
+
+    /// Inverse of `to_fields`. Returns `None` when the field count does not match.
+    pub fn from_fields(fields: &[&str]) -> Option<Self> {
+        if fields.len() != Self::FIELD_COUNT {
+            return None;
+        }
+        let text = |s: &str| if s.is_empty() { None } else { Some(unescape_field(s)) };
+        let number = |s: &str| s.parse::<u32>().ok();
+        Some(Self {
+            title: text(fields[0]),
+            artist: text(fields[1]),
+            album: text(fields[2]),
+            album_artist: text(fields[3]),
+            composer: text(fields[4]),
+            comment: text(fields[5]),
+            genre: text(fields[6]),
+            year: fields[7].parse().ok(),
+            track: number(fields[8]),
+            total_tracks: number(fields[9]),
+            disc: number(fields[10]),
+            total_discs: number(fields[11]),
+        })
+    }
+}
+
+/// `POPM` user that rdaio writes ratings under.
+pub const POPM_USER: &str = "rdaio";
+
+/// Maps 1-5 stars onto the 1-255 `POPM` scale the way most players read it.
+pub fn stars_to_popm(stars: u8) -> u8 {
+    match stars {
+        0 => 0,
+        1 => 1,
+        2 => 64,
+        3 => 128,
+        4 => 196,
+        _ => 255,
+    }
+}
+
+/// Writes (or with `None` removes) rdaio's `POPM` frame, carrying the star
+/// rating and play count. Other users' `POPM` frames are left alone.
+pub fn write_popularimeter(file_path: &str, stars: Option<u8>, play_count: u32) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut tag, version) = match id3::Tag::read_from_path(file_path) {
+        Ok(tag) => {
+            let version = tag.version();
+            (tag, version)
+        }
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => (id3::Tag::new(), id3::Version::Id3v24),
+        Err(e) => return Err(e.into()),
+    };
+    let others: Vec<id3::Frame> = tag.remove("POPM")
+        .into_iter()
+        .filter(|f| f.content().popularimeter().is_some_and(|p| p.user != POPM_USER))
+        .collect();
+    for frame in others {
+        tag.add_frame(frame);
+    }
+    if let Some(stars) = stars {
+        tag.add_frame(id3::frame::Popularimeter {
+            user: POPM_USER.to_string(),
+            rating: stars_to_popm(stars),
+            counter: u64::from(play_count),
+        });
+    }
+    tag.write_to_path(file_path, version)?;
+    Ok(())
+}
+
+/// Escapes backslashes, tabs and newlines so a value fits in one tab-separated field.
+pub fn escape_field(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "")
+}
+
+pub fn unescape_field(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('t') => out.push('\t'),
+                Some('n') => out.push('\n'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Technical details of the stream being decoded, published by the playback thread.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamInfo {
+    pub codec: String,
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub duration_ms: Option<u64>,
+    /// ReplayGain applied to playback.
+    pub replay_gain: Option<crate::replaygain::AppliedGain>,
+}
+
+/// Formats "n/total", "n" or "-" for track and disc numbers.
+pub fn format_number_pair(number: Option<u32>, total: Option<u32>) -> String {
+    match (number, total) {
+        (Some(n), Some(t)) => format!("{}/{}", n, t),
+        (Some(n), None) => n.to_string(),
+        _ => String::from("-"),
+    }
+}
+
+/// Formats milliseconds as m:ss.
+pub fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Builds the lines shown in the now-playing panel.
+pub fn now_playing_lines(tags: &TrackTags, info: Option<&StreamInfo>, fallback_title: &str) -> Vec<String> {
+    let field = |v: &Option<String>| v.clone().unwrap_or_else(|| String::from("-"));
+    let mut lines = vec![
+        format!("Title:    {}", tags.title.clone().unwrap_or_else(|| fallback_title.to_string())),
+        format!("Artist:   {}", field(&tags.artist)),
+        format!("Album:    {}", field(&tags.album)),
+        format!("Track:    {}   Disc: {}",
+            format_number_pair(tags.track, tags.total_tracks),
+            format_number_pair(tags.disc, tags.total_discs)),
+        format!("Year:     {}", tags.year.map(|y| y.to_string()).unwrap_or_else(|| String::from("-"))),
+        format!("Genre:    {}", field(&tags.genre)),
+    ];
+    match info {
+        Some(info) => {
+            let bitrate = info.bitrate_kbps.map(|b| format!("{} kbps", b)).unwrap_or_else(|| String::from("-"));
+            lines.push(format!("Codec:    {}   Bitrate: {}", info.codec, bitrate));
+            lines.push(format!("Format:   {} Hz, {} ch", info.sample_rate, info.channels));
+            if let Some(ms) = info.duration_ms {
+                lines.push(format!("Length:   {}", format_duration(ms)));
+            }
+            if let Some(gain) = info.replay_gain {
+                lines.push(format!("Gain:     {:+.2} dB ({})", gain.db, gain.source.label()));
+            }
+        }
+        None => lines.push(String::from("Codec:    -")),
+    }
+    lines
+}
+
+/// A one-line progress bar, e.g. `1:23 ━━━━●──────── 4:56`, `width` columns wide.
+/// A–B loop points are drawn as `[` and `]`.
+pub fn progress_line(position_ms: u64, duration_ms: Option<u64>, width: usize, ab_loop: Option<(u64, Option<u64>)>) -> String {
+    let Some(duration) = duration_ms.filter(|d| *d > 0) else {
+        return format_duration(position_ms);
+    };
+    let (elapsed, total) = (format_duration(position_ms), format_duration(duration));
+    let bar_width = width.saturating_sub(elapsed.len() + total.len() + 2);
+    if bar_width < 3 {
+        return format!("{} / {}", elapsed, total);
+    }
+    let column = |ms: u64| ((ms.min(duration) as f64 / duration as f64) * (bar_width - 1) as f64).round() as usize;
+    let head = column(position_ms);
+    let loop_a = ab_loop.map(|(a, _)| column(a));
+    let loop_b = ab_loop.and_then(|(_, b)| b).map(column);
+    let bar: String = (0..bar_width)
+        .map(|i| match i.cmp(&head) {
+            std::cmp::Ordering::Equal => '●',
+            _ if Some(i) == loop_a => '[',
+            _ if Some(i) == loop_b => ']',
+            std::cmp::Ordering::Less => '━',
+            std::cmp::Ordering::Greater => '─',
+        })
+        .collect();
+    format!("{} {} {}", elapsed, bar, total)
+}
@@ -0,0 +1,184 @@
+use crate::metadata::TrackTags;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+pub const META_CACHE_FILE: &str = ".rdaio_meta_cache";
+
+/// A disk cache entry, valid while the file's mtime and size are unchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CachedTags {
+    pub mtime: u64,
+    pub size: u64,
+    pub tags: TrackTags,
+}
+
+/// Returns (mtime in seconds, size in bytes) for a file.
+pub fn file_stamp(path: &str) -> Option<(u64, u64)> {
+    let meta = fs::metadata(path).ok()?;
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    Some((mtime, meta.len()))
+}
+
+/// Encodes one cache entry as a tab-separated line (without the newline).
+pub fn encode_cache_line(track_path: &str, entry: &CachedTags) -> String {
+    let mut line = crate::metadata::escape_field(track_path);
+    line.push('\t');
+    line.push_str(&entry.mtime.to_string());
+    line.push('\t');
+    line.push_str(&entry.size.to_string());
+    for field in entry.tags.to_fields() {
+        line.push('\t');
+        line.push_str(&field);
+    }
+    line
+}
+
+pub fn decode_cache_line(line: &str) -> Option<(String, CachedTags)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 3 + TrackTags::FIELD_COUNT {
+        return None;
+    }
+    let mtime = fields[1].parse().ok()?;
+    let size = fields[2].parse().ok()?;
+    let tags = TrackTags::from_fields(&fields[3..])?;
+    Some((crate::metadata::unescape_field(fields[0]), CachedTags { mtime, size, tags }))
+}
+
+pub fn load_disk_cache(path: &str) -> HashMap<String, CachedTags> {
+    match fs::read_to_string(path) {
+        Ok(content) => content.lines().filter_map(decode_cache_line).collect(),
+        Err(_) => HashMap::new(),
+    }
+}
+
+pub fn save_disk_cache(path: &str, entries: &HashMap<String, CachedTags>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = String::new();
+    for (track_path, entry) in entries {
+        data.push_str(&encode_cache_line(track_path, entry));
+        data.push('\n');
+    }
+    fs::write(path, data)?;
+    Ok(())
+}
+
+/// In-memory track metadata cache filled by a background thread.
+///
+/// The UI only ever looks up the in-memory map; misses are queued for the
+/// worker, which consults the on-disk cache (keyed by path + mtime + size)
+/// before falling back to reading the ID3 tag.
+pub struct MetadataCache {
+    entries: Arc<Mutex<HashMap<String, TrackTags>>>,
+    pending: Arc<Mutex<HashSet<String>>>,
+    disk: Arc<Mutex<HashMap<String, CachedTags>>>,
+    disk_path: Option<String>,
+    requests: Sender<String>,
+}
+
+impl MetadataCache {
+    /// Starts the worker. Pass `None` to keep the cache in memory only.
+    pub fn new(disk_path: Option<&str>) -> Self {
+        let entries = Arc::new(Mutex::new(HashMap::new()));
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+        let disk = Arc::new(Mutex::new(HashMap::new()));
+        let disk_path = disk_path.map(|p| p.to_string());
+        let (requests, rx) = mpsc::channel::<String>();
+
+        std::thread::spawn({
+            let entries = entries.clone();
+            let pending = pending.clone();
+            let disk = disk.clone();
+            let disk_path = disk_path.clone();
+            move || {
+                if let Some(path) = &disk_path {
+                    *disk.lock().unwrap() = load_disk_cache(path);
+                }
+                let mut dirty = false;
+                loop {
+                    match rx.recv_timeout(Duration::from_millis(500)) {
+                        Ok(track_path) => {
+                            let stamp = file_stamp(&track_path);
+                            let cached = disk.lock().unwrap().get(&track_path).cloned();
+                            let tags = match (cached, stamp) {
+                                (Some(c), Some((mtime, size))) if c.mtime == mtime && c.size == size => c.tags,
+                                _ => {
+                                    let tags = TrackTags::read(&track_path);
+                                    if let Some((mtime, size)) = stamp {
+                                        disk.lock().unwrap().insert(
+                                            track_path.clone(),
+                                            CachedTags { mtime, size, tags: tags.clone() },
+                                        );
+                                        dirty = true;
+                                    }
+                                    tags
+                                }
+                            };
+                            entries.lock().unwrap().insert(track_path.clone(), tags);
+                            pending.lock().unwrap().remove(&track_path);
+                        }
+                        Err(RecvTimeoutError::Timeout) => {
+                            if dirty && let Some(path) = &disk_path {
+                                let _ = save_disk_cache(path, &disk.lock().unwrap());
+                                dirty = false;
+                            }
+                        }
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            }
+        });
+
+        Self { entries, pending, disk, disk_path, requests }
+    }
+
+    /// Returns cached tags, or `None` after scheduling a background read.
+    pub fn get(&self, track_path: &str) -> Option<TrackTags> {
+        if let Some(tags) = self.entries.lock().unwrap().get(track_path) {
+            return Some(tags.clone());
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.insert(track_path.to_string()) {
+            let _ = self.requests.send(track_path.to_string());
+        }
+        None
+    }
+
+    /// Drops a track so the next lookup reads its tags again (after editing them).
+    pub fn invalidate(&self, track_path: &str) {
+        self.entries.lock().unwrap().remove(track_path);
+    }
+
+    /// Whether any lookups are still waiting for the worker.
+    pub fn is_loading(&self) -> bool {
+        !self.pending.lock().unwrap().is_empty()
+    }
+
+    /// Writes the on-disk cache immediately (used on exit).
+    pub fn save(&self) {
+        if let Some(path) = &self.disk_path {
+            let _ = save_disk_cache(path, &self.disk.lock().unwrap());
+        }
+    }
+
+    /// Title for a queue entry: the cached ID3 title, or the file name while loading.
+    pub fn display_name(&self, file_name: &str, directory: &str, show_title: bool) -> String {
+        let short_name = Path::new(file_name)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| file_name.to_string());
+        if !show_title {
+            return short_name;
+        }
+        self.get(&crate::get_track_path(file_name, directory))
+            .and_then(|tags| tags.title)
+            .unwrap_or(short_name)
+    }
+}
@@ -0,0 +1,293 @@
+use crate::library::{UNKNOWN_ALBUM, UNKNOWN_ARTIST, UNKNOWN_GENRE};
+use crate::metadata::TrackTags;
+use crossterm::event;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, Paragraph, ListState, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Color}};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::{fs, io, error::Error};
+
+pub const DEFAULT_RENAME_TEMPLATE: &str = "{artist}/{album}/{track:02} - {title}.{ext}";
+
+/// Characters that are not allowed in file names on at least one common platform.
+const ILLEGAL_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Makes one path component safe. Returns the cleaned name and whether anything was replaced.
+pub fn sanitize_component(name: &str) -> (String, bool) {
+    let cleaned: String = name.chars()
+        .map(|c| if ILLEGAL_CHARS.contains(&c) || c.is_control() { '_' } else { c })
+        .collect();
+    // Windows drops trailing dots and spaces
+    let trimmed = cleaned.trim().trim_end_matches('.').trim_end().to_string();
+    let result = if trimmed.is_empty() || trimmed == ".." { String::from("_") } else { trimmed };
+    let changed = result != name;
+    (result, changed)
+}
+
+fn value_for(name: &str, tags: &TrackTags, source: &Path) -> Option<String> {
+    let stem = source.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let value = match name {
+        "artist" => tags.artist.clone().or_else(|| tags.album_artist.clone()).unwrap_or_else(|| UNKNOWN_ARTIST.to_string()),
+        "albumartist" | "album_artist" => tags.album_artist.clone().or_else(|| tags.artist.clone()).unwrap_or_else(|| UNKNOWN_ARTIST.to_string()),
+        "album" => tags.album.clone().unwrap_or_else(|| UNKNOWN_ALBUM.to_string()),
+        "title" => tags.title.clone().unwrap_or(stem),
+        "genre" => tags.genre.clone().unwrap_or_else(|| UNKNOWN_GENRE.to_string()),
+        "composer" => tags.composer.clone().unwrap_or_default(),
+        "year" => tags.year.map(|y| y.to_string()).unwrap_or_default(),
+        "track" => tags.track.map(|n| n.to_string()).unwrap_or_default(),
+        "disc" => tags.disc.map(|n| n.to_string()).unwrap_or_default(),
+        "ext" => source.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_else(|| String::from("mp3")),
+        "filename" => stem,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// Expands a template such as `{artist}/{album}/{track:02} - {title}.{ext}`.
+///
+/// `/` separates directories. `{name:0N}` zero-pads numbers to N digits.
+/// Placeholder values are sanitised; the flag reports whether any were changed.
+pub fn render_template(template: &str, tags: &TrackTags, source: &Path) -> Result<(PathBuf, bool), String> {
+    let mut components: Vec<String> = vec![String::new()];
+    let mut sanitized = false;
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        match c {
+            '{' => {
+                let end = rest.find('}').ok_or("unclosed '{' in template")?;
+                let spec = &rest[1..end];
+                let (name, format) = spec.split_once(':').unwrap_or((spec, ""));
+                let mut value = value_for(&name.to_ascii_lowercase(), tags, source)
+                    .ok_or_else(|| format!("unknown placeholder {{{}}}", name))?;
+                if let Some(width) = format.strip_prefix('0').and_then(|w| w.parse::<usize>().ok())
+                    && !value.is_empty()
+                {
+                    value = format!("{:0>width$}", value, width = width);
+                }
+                let (clean, changed) = sanitize_component(&value);
+                sanitized |= changed && !value.is_empty();
+                if !value.is_empty() {
+                    components.last_mut().unwrap().push_str(&clean);
+                }
+                rest = &rest[end + 1..];
+            }
+            '/' | '\\' => {
+                components.push(String::new());
+                rest = &rest[1..];
+            }
+            c => {
+                components.last_mut().unwrap().push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    let mut path = PathBuf::new();
+    // Empty components come from a leading or doubled '/' or a directory placeholder without a value
+    for component in components.iter().filter(|c| !c.trim().is_empty()) {
+        let (clean, changed) = sanitize_component(component);
+        sanitized |= changed;
+        path.push(clean);
+    }
+    Ok((path, sanitized))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenameStatus {
+    Ready,
+    /// Target equals the current path.
+    Unchanged,
+    /// Another file already has, or would get, this name.
+    Collision,
+    Error(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenamePlan {
+    pub from: String,
+    pub to: String,
+    pub status: RenameStatus,
+    /// Illegal characters were replaced with `_`.
+    pub sanitized: bool,
+}
+
+/// Previews where each file would go, given each path with its tags. Targets
+/// are resolved against `base` unless the template is absolute. Nothing is
+/// touched on disk.
+pub fn plan_renames(tracks: &[(String, TrackTags)], template: &str, base: &Path) -> Vec<RenamePlan> {
+    let mut plans: Vec<RenamePlan> = tracks.iter()
+        .map(|(from, tags)| {
+            let source = Path::new(from);
+            match render_template(template, tags, source) {
+                Ok((relative, sanitized)) => {
+                    let target = if Path::new(template).is_absolute() {
+                        Path::new("/").join(relative)
+                    } else {
+                        base.join(relative)
+                    };
+                    let to = target.to_string_lossy().to_string();
+                    let status = if to == *from { RenameStatus::Unchanged } else { RenameStatus::Ready };
+                    RenamePlan { from: from.clone(), to, status, sanitized }
+                }
+                Err(e) => RenamePlan { from: from.clone(), to: String::new(), status: RenameStatus::Error(e), sanitized: false },
+            }
+        })
+        .collect();
+
+    // Compare case-insensitively so plans stay safe on case-insensitive filesystems
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for plan in plans.iter().filter(|p| p.status == RenameStatus::Ready || p.status == RenameStatus::Unchanged) {
+        *counts.entry(plan.to.to_lowercase()).or_default() += 1;
+    }
+    for plan in plans.iter_mut().filter(|p| p.status == RenameStatus::Ready) {
+        let key = plan.to.to_lowercase();
+        let duplicate = counts.get(&key).copied().unwrap_or(0) > 1;
+        // An existing file is only fine when it is this same file under a different case
+        let occupied = Path::new(&plan.to).exists() && key != plan.from.to_lowercase();
+        if duplicate || occupied {
+            plan.status = RenameStatus::Collision;
+        }
+    }
+    plans
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        // Different filesystems: copy, then remove the original
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Moves every `Ready` file. Returns the moves that succeeded and the errors.
+pub fn apply_renames(plans: &[RenamePlan]) -> (Vec<(String, String)>, Vec<String>) {
+    let mut moved = Vec::new();
+    let mut errors = Vec::new();
+    for plan in plans.iter().filter(|p| p.status == RenameStatus::Ready) {
+        match move_file(Path::new(&plan.from), Path::new(&plan.to)) {
+            Ok(()) => moved.push((plan.from.clone(), plan.to.clone())),
+            Err(e) => errors.push(format!("{}: {}", plan.from, e)),
+        }
+    }
+    (moved, errors)
+}
+
+/// Rename screen: edit the template, check the preview, then apply.
+/// Returns the (old, new) paths of the files that were moved.
+pub fn rename_files(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    paths: &[String],
+    base: &Path,
+    template: &str,
+    debug_mode: bool,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut template = template.to_string();
+    // Tags are read once; only the template changes while the screen is open
+    let mut remaining: Vec<(String, TrackTags)> = paths.iter().map(|p| (p.clone(), TrackTags::read(p))).collect();
+    let mut moved_so_far: Vec<(String, String)> = Vec::new();
+    let mut plans = plan_renames(&remaining, &template, base);
+    let mut list_state = ListState::default();
+    list_state.select(if plans.is_empty() { None } else { Some(0) });
+    let mut message = String::new();
+    let mut dirty = false;
+
+    loop {
+        if dirty {
+            plans = plan_renames(&remaining, &template, base);
+            dirty = false;
+        }
+        let ready = plans.iter().filter(|p| p.status == RenameStatus::Ready).count();
+        let problems = plans.iter().filter(|p| matches!(p.status, RenameStatus::Collision | RenameStatus::Error(_))).count();
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Min(5),
+                    Constraint::Length(4),
+                ].as_ref())
+                .split(f.size());
+
+            let input = Paragraph::new(format!("{}_", template))
+                .block(Block::default().borders(Borders::ALL).title(format!("Template ({{artist}} {{albumartist}} {{album}} {{title}} {{track:02}} {{disc}} {{year}} {{genre}} {{ext}}) - relative to {}", base.display())));
+            f.render_widget(input, chunks[0]);
+
+            let items: Vec<ListItem> = plans.iter()
+                .map(|plan| {
+                    let (tag, style) = match &plan.status {
+                        RenameStatus::Ready if plan.sanitized => (String::from("chars replaced"), Style::default().fg(Color::Yellow)),
+                        RenameStatus::Ready => (String::from("ok"), Style::default().fg(Color::Green)),
+                        RenameStatus::Unchanged => (String::from("unchanged"), Style::default().fg(Color::DarkGray)),
+                        RenameStatus::Collision => (String::from("COLLISION"), Style::default().fg(Color::Red)),
+                        RenameStatus::Error(e) => (e.clone(), Style::default().fg(Color::Red)),
+                    };
+                    ListItem::new(format!("[{}] {}  ->  {}", tag, plan.from, plan.to)).style(style)
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(format!("Preview - {} to move, {} with problems {}", ready, problems, message)))
+                .highlight_symbol(">> ");
+            f.render_stateful_widget(list, chunks[1], &mut list_state);
+
+            let help = Paragraph::new("[Type] Template  [Up/Down] Scroll  [Enter] Move files marked ok (others are skipped)  [ESC] Cancel")
+                .block(Block::default().borders(Borders::ALL).title("Rename by Pattern"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[2]);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(200))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                event::KeyCode::Esc => {
+                    // Files moved before a partial failure still need the queue updated
+                    if moved_so_far.is_empty() {
+                        return Err("Cancelled".into());
+                    }
+                    return Ok(moved_so_far);
+                },
+                event::KeyCode::Down if !plans.is_empty() => {
+                    let i = list_state.selected().map(|i| if i >= plans.len() - 1 { 0 } else { i + 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Up if !plans.is_empty() => {
+                    let i = list_state.selected().map(|i| if i == 0 { plans.len() - 1 } else { i - 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Backspace => {
+                    template.pop();
+                    dirty = true;
+                },
+                event::KeyCode::Char(c) => {
+                    template.push(c);
+                    dirty = true;
+                },
+                event::KeyCode::Enter if ready > 0 => {
+                    let (moved, errors) = apply_renames(&plans);
+                    if debug_mode {
+                        println!("[DEBUG] Renamed {} files, {} errors", moved.len(), errors.len());
+                    }
+                    let done: HashSet<&String> = moved.iter().map(|(from, _)| from).collect();
+                    remaining.retain(|(p, _)| !done.contains(p));
+                    moved_so_far.extend(moved);
+                    if errors.is_empty() {
+                        return Ok(moved_so_far);
+                    }
+                    // Report the failures and keep the rest on screen
+                    message = format!("- {} moved, failed: {}", moved_so_far.len(), errors[0]);
+                    dirty = true;
+                },
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use symphonia::core::meta::MetadataRevision;
+
+/// EBU R128 tags are relative to -23 LUFS, ReplayGain 2 to -18 LUFS.
+const R128_TO_REPLAYGAIN_DB: f32 = 5.0;
+
+/// Which ReplayGain value playback uses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReplayGainMode {
+    Off,
+    Track,
+    Album,
+    /// Album gain while neighbouring queue entries are from the same album, track gain otherwise.
+    #[default]
+    Auto,
+}
+
+impl ReplayGainMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "off" | "none" | "false" => Some(Self::Off),
+            "track" => Some(Self::Track),
+            "album" => Some(Self::Album),
+            "auto" => Some(Self::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Track => "track",
+            Self::Album => "album",
+            Self::Auto => "auto",
+        }
+    }
+
+    /// Resolves `Auto` to track or album gain.
+    pub fn resolve(self, album_context: bool) -> Self {
+        match self {
+            Self::Auto if album_context => Self::Album,
+            Self::Auto => Self::Track,
+            mode => mode,
+        }
+    }
+}
+
+/// ReplayGain options from the config file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayGainSettings {
+    pub mode: ReplayGainMode,
+    /// Added to every ReplayGain value, in dB.
+    pub preamp_db: f32,
+    /// Lower the gain so the tagged peak does not exceed full scale.
+    pub prevent_clipping: bool,
+}
+
+impl Default for ReplayGainSettings {
+    fn default() -> Self {
+        Self { mode: ReplayGainMode::Auto, preamp_db: 0.0, prevent_clipping: true }
+    }
+}
+
+/// ReplayGain values of a track, in dB and linear peak amplitude.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track_gain_db: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain_db: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+/// Parses "-6.54 dB", "+2.1 dB" or "-6.54".
+fn parse_gain(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let number = value.strip_suffix("dB").or_else(|| value.strip_suffix("db")).unwrap_or(value);
+    number.trim().trim_start_matches('+').parse().ok().filter(|g: &f32| g.is_finite())
+}
+
+fn parse_peak(value: &str) -> Option<f32> {
+    value.trim().parse().ok().filter(|p: &f32| p.is_finite() && *p > 0.0)
+}
+
+/// Parses an R128 gain: a Q7.8 fixed-point integer relative to -23 LUFS.
+fn parse_r128(value: &str) -> Option<f32> {
+    value.trim().parse::<i16>().ok().map(|q| q as f32 / 256.0 + R128_TO_REPLAYGAIN_DB)
+}
+
+impl ReplayGain {
+    /// Reads ReplayGain from tag key/value pairs: ID3 `TXXX:REPLAYGAIN_*` frames,
+    /// `REPLAYGAIN_*` Vorbis comments or Opus `R128_*_GAIN` comments. Keys are
+    /// case-insensitive; ReplayGain values win over R128 ones.
+    pub fn from_tags<'a>(tags: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut gain = Self::default();
+        let mut r128 = Self::default();
+        for (key, value) in tags {
+            let key = key.strip_prefix("TXXX:").unwrap_or(key).to_ascii_uppercase();
+            match key.as_str() {
+                "REPLAYGAIN_TRACK_GAIN" => gain.track_gain_db = parse_gain(value).or(gain.track_gain_db),
+                "REPLAYGAIN_TRACK_PEAK" => gain.track_peak = parse_peak(value).or(gain.track_peak),
+                "REPLAYGAIN_ALBUM_GAIN" => gain.album_gain_db = parse_gain(value).or(gain.album_gain_db),
+                "REPLAYGAIN_ALBUM_PEAK" => gain.album_peak = parse_peak(value).or(gain.album_peak),
+                "R128_TRACK_GAIN" => r128.track_gain_db = parse_r128(value),
+                "R128_ALBUM_GAIN" => r128.album_gain_db = parse_r128(value),
+                _ => {}
+            }
+        }
+        gain.track_gain_db = gain.track_gain_db.or(r128.track_gain_db);
+        gain.album_gain_db = gain.album_gain_db.or(r128.album_gain_db);
+        gain
+    }
+
+    /// Reads ReplayGain from the metadata Symphonia found while probing.
+    pub fn from_metadata<'a>(revisions: impl IntoIterator<Item = &'a MetadataRevision>) -> Self {
+        let tags: Vec<(&str, String)> = revisions.into_iter()
+            .flat_map(|revision| revision.tags())
+            .map(|tag| (tag.key.as_str(), tag.value.to_string()))
+            .collect();
+        Self::from_tags(tags.iter().map(|(key, value)| (*key, value.as_str())))
+    }
+
+    /// Gain and peak for a resolved mode, and which of the two they are; album
+    /// mode falls back to track values and the other way round, so partly
+    /// tagged files still get levelled.
+    pub fn select(&self, mode: ReplayGainMode) -> Option<(ReplayGainMode, f32, Option<f32>)> {
+        let track = self.track_gain_db.map(|g| (ReplayGainMode::Track, g, self.track_peak));
+        let album = self.album_gain_db.map(|g| (ReplayGainMode::Album, g, self.album_peak));
+        match mode {
+            ReplayGainMode::Off => None,
+            ReplayGainMode::Album => album.or(track),
+            ReplayGainMode::Track | ReplayGainMode::Auto => track.or(album),
+        }
+    }
+
+    /// The gain playback should apply, or `None` when it should be left alone.
+    pub fn applied(&self, settings: &ReplayGainSettings, album_context: bool) -> Option<AppliedGain> {
+        let (source, gain_db, peak) = self.select(settings.mode.resolve(album_context))?;
+        let mut factor = 10f32.powf((gain_db + settings.preamp_db) / 20.0);
+        if settings.prevent_clipping
+            && let Some(peak) = peak
+            && factor * peak > 1.0
+        {
+            factor = 1.0 / peak;
+        }
+        Some(AppliedGain { factor, db: 20.0 * factor.log10(), source })
+    }
+}
+
+/// Gain applied to a track: the linear factor, the same in dB, and whether
+/// the track or album value was used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AppliedGain {
+    pub factor: f32,
+    pub db: f32,
+    pub source: ReplayGainMode,
+}
+
+/// Scales samples by a ReplayGain factor, hard-limiting anything the tags did not foresee.
+pub fn apply_gain(samples: &mut [f32], factor: f32) {
+    for sample in samples {
+        *sample = (*sample * factor).clamp(-1.0, 1.0);
+    }
+}
+
+/// Whether the queue entry at `index` is played as part of an album: the
+/// entry before or after it has the same album tag.
+pub fn album_context(albums: &[Option<String>], index: usize) -> bool {
+    let Some(Some(album)) = albums.get(index) else {
+        return false;
+    };
+    let same = |i: usize| albums.get(i).and_then(|a| a.as_ref()).is_some_and(|a| a == album);
+    (index > 0 && same(index - 1)) || same(index + 1)
+}
@@ -0,0 +1,289 @@
+use crate::library::{Library, LibraryTrack};
+use crate::QueueAction;
+use crossterm::event;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, Paragraph, ListState, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Modifier, Color}};
+use std::{io, error::Error};
+use std::sync::{Arc, Mutex};
+
+/// Tag fields a query term can be restricted to with `field:value`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Composer,
+    Comment,
+    Genre,
+    Path,
+}
+
+impl SearchField {
+    /// Fields searched by unqualified terms, with their ranking weight.
+    const FREE_TEXT: [(SearchField, u32); 7] = [
+        (SearchField::Title, 10),
+        (SearchField::Artist, 8),
+        (SearchField::AlbumArtist, 6),
+        (SearchField::Album, 6),
+        (SearchField::Composer, 4),
+        (SearchField::Comment, 2),
+        (SearchField::Path, 1),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "title" => Some(SearchField::Title),
+            "artist" => Some(SearchField::Artist),
+            "album" => Some(SearchField::Album),
+            "albumartist" | "album_artist" => Some(SearchField::AlbumArtist),
+            "composer" => Some(SearchField::Composer),
+            "comment" => Some(SearchField::Comment),
+            "genre" => Some(SearchField::Genre),
+            "path" | "file" => Some(SearchField::Path),
+            _ => None,
+        }
+    }
+
+    fn weight(self) -> u32 {
+        SearchField::FREE_TEXT.iter()
+            .find(|(f, _)| *f == self)
+            .map(|(_, w)| *w)
+            .unwrap_or(5)
+    }
+
+    fn value(self, track: &LibraryTrack) -> Option<&str> {
+        let tags = &track.tags;
+        match self {
+            SearchField::Title => tags.title.as_deref(),
+            SearchField::Artist => tags.artist.as_deref(),
+            SearchField::Album => tags.album.as_deref(),
+            SearchField::AlbumArtist => tags.album_artist.as_deref(),
+            SearchField::Composer => tags.composer.as_deref(),
+            SearchField::Comment => tags.comment.as_deref(),
+            SearchField::Genre => tags.genre.as_deref(),
+            SearchField::Path => Some(track.path.as_str()),
+        }
+    }
+}
+
+/// One query term. All terms of a query must match.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    /// Matches any free-text field.
+    Any(String),
+    Field(SearchField, String),
+    /// Inclusive year range; open ends are `None`.
+    Year(Option<i32>, Option<i32>),
+}
+
+/// Splits on whitespace while keeping "quoted phrases" together.
+fn tokenize(query: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+fn parse_year_range(value: &str) -> Option<Term> {
+    match value.split_once("..") {
+        Some((from, to)) => {
+            let from = if from.is_empty() { None } else { Some(from.parse().ok()?) };
+            let to = if to.is_empty() { None } else { Some(to.parse().ok()?) };
+            Some(Term::Year(from, to))
+        }
+        None => {
+            let year = value.parse().ok()?;
+            Some(Term::Year(Some(year), Some(year)))
+        }
+    }
+}
+
+/// Parses queries like `artist:bowie year:1977..1980 "low"`.
+/// Unknown `field:` prefixes are treated as plain text.
+pub fn parse_query(query: &str) -> Vec<Term> {
+    tokenize(query)
+        .into_iter()
+        .filter_map(|token| {
+            if let Some((name, value)) = token.split_once(':')
+                && !value.is_empty()
+            {
+                if name.eq_ignore_ascii_case("year") {
+                    return parse_year_range(value);
+                }
+                if let Some(field) = SearchField::from_name(name) {
+                    return Some(Term::Field(field, value.to_lowercase()));
+                }
+            }
+            Some(Term::Any(token.to_lowercase()))
+        })
+        .collect()
+}
+
+/// Score of `needle` in one field: exact > word prefix > substring.
+fn field_score(value: &str, needle: &str, weight: u32) -> u32 {
+    let value = value.to_lowercase();
+    if value == needle {
+        weight * 4
+    } else if value.match_indices(needle).any(|(i, _)| !value[..i].ends_with(char::is_alphanumeric)) {
+        weight * 2
+    } else if value.contains(needle) {
+        weight
+    } else {
+        0
+    }
+}
+
+/// Returns a rank for the track, or `None` when any term fails to match.
+pub fn score_track(track: &LibraryTrack, terms: &[Term]) -> Option<u32> {
+    let mut total = 0;
+    for term in terms {
+        let score = match term {
+            Term::Any(needle) => SearchField::FREE_TEXT.iter()
+                .filter_map(|(field, weight)| field.value(track).map(|v| field_score(v, needle, *weight)))
+                .sum(),
+            Term::Field(field, needle) => field.value(track)
+                .map(|v| field_score(v, needle, field.weight()))
+                .unwrap_or(0),
+            Term::Year(from, to) => match track.tags.year {
+                Some(year) if from.is_none_or(|f| year >= f) && to.is_none_or(|t| year <= t) => 1,
+                _ => 0,
+            },
+        };
+        if score == 0 {
+            return None;
+        }
+        total += score;
+    }
+    Some(total)
+}
+
+/// Ranked library search; returns indices into `library.tracks`, best first.
+pub fn search(library: &Library, query: &str, limit: usize) -> Vec<usize> {
+    let terms = parse_query(query);
+    if terms.is_empty() {
+        return Vec::new();
+    }
+    let mut hits: Vec<(u32, usize)> = library.tracks.iter()
+        .enumerate()
+        .filter_map(|(idx, track)| score_track(track, &terms).map(|score| (score, idx)))
+        .collect();
+    hits.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    hits.into_iter().take(limit).map(|(_, idx)| idx).collect()
+}
+
+/// Search screen: type a query, pick results to play or enqueue.
+pub fn search_library(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    library: &Arc<Mutex<Library>>,
+    debug_mode: bool,
+) -> Result<QueueAction, Box<dyn Error>> {
+    let mut query = String::new();
+    let mut results: Vec<(String, String)> = Vec::new();
+    let mut result_state = ListState::default();
+    let mut dirty = false;
+
+    loop {
+        if dirty {
+            let lib = library.lock().unwrap();
+            results = search(&lib, &query, 500).into_iter()
+                .map(|idx| {
+                    let track = &lib.tracks[idx];
+                    let label = format!("{} - {} [{}]", track.artist_name(), track.title_or_file_name(), track.album_name());
+                    (track.path.clone(), label)
+                })
+                .collect();
+            result_state.select(if results.is_empty() { None } else { Some(0) });
+            dirty = false;
+        }
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Min(5),
+                    Constraint::Length(4),
+                ].as_ref())
+                .split(f.size());
+
+            let input = Paragraph::new(format!("{}_", query))
+                .block(Block::default().borders(Borders::ALL).title("Search (e.g. artist:bowie year:1977..1980)"));
+            f.render_widget(input, chunks[0]);
+
+            let items: Vec<ListItem> = results.iter().map(|(_, label)| ListItem::new(label.clone())).collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(format!("Results ({})", results.len())))
+                .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+                .highlight_symbol(">> ");
+            f.render_stateful_widget(list, chunks[1], &mut result_state);
+
+            let help = Paragraph::new("[Type] Query  [Up/Down] Select  [Enter] Play Now  [Ctrl+A] Append  [Ctrl+R] Replace Queue With All  [ESC] Back")
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[2]);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(200))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            let ctrl = key.modifiers.contains(event::KeyModifiers::CONTROL);
+            let selected = result_state.selected().and_then(|i| results.get(i)).map(|(path, _)| path.clone());
+            match key.code {
+                event::KeyCode::Esc => {
+                    return Err("Cancelled".into());
+                },
+                event::KeyCode::Down if !results.is_empty() => {
+                    let i = result_state.selected().map(|i| if i >= results.len() - 1 { 0 } else { i + 1 }).unwrap_or(0);
+                    result_state.select(Some(i));
+                },
+                event::KeyCode::Up if !results.is_empty() => {
+                    let i = result_state.selected().map(|i| if i == 0 { results.len() - 1 } else { i - 1 }).unwrap_or(0);
+                    result_state.select(Some(i));
+                },
+                event::KeyCode::Enter => {
+                    if let Some(path) = selected {
+                        if debug_mode {
+                            println!("[DEBUG] Search '{}' -> play {}", query, path);
+                        }
+                        return Ok(QueueAction::PlayNow(vec![path]));
+                    }
+                },
+                event::KeyCode::Char('a') if ctrl => {
+                    if let Some(path) = selected {
+                        return Ok(QueueAction::Append(vec![path]));
+                    }
+                },
+                event::KeyCode::Char('r') if ctrl && !results.is_empty() => {
+                    return Ok(QueueAction::Replace(results.iter().map(|(path, _)| path.clone()).collect()));
+                },
+                event::KeyCode::Backspace => {
+                    query.pop();
+                    dirty = true;
+                },
+                event::KeyCode::Char(c) if !ctrl => {
+                    query.push(c);
+                    dirty = true;
+                },
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,207 @@
+/// Shortest silence the filters act on; shorter runs are just quiet passages
+/// and zero crossings.
+pub const MIN_SILENCE_MS: u64 = 50;
+/// Silence the trim filter holds back while it cannot tell whether it is trailing.
+/// Longer runs are played, so only up to this much trailing silence is trimmed.
+const MAX_HELD_MS: u64 = 60_000;
+
+/// Threshold and minimum length of the silence one option removes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SilenceRule {
+    pub enabled: bool,
+    /// Frames whose every sample is below this level (dBFS) are silent.
+    pub threshold_db: f32,
+    /// Runs shorter than this are left alone.
+    pub min_ms: u64,
+}
+
+/// Silence options from the config file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SilenceSettings {
+    /// Skip silence anywhere in a track (`skip_silence*`).
+    pub skip: SilenceRule,
+    /// Trim silence at the start and end of a track (`trim_silence*`).
+    pub trim: SilenceRule,
+}
+
+impl Default for SilenceSettings {
+    fn default() -> Self {
+        Self {
+            skip: SilenceRule { enabled: false, threshold_db: -50.0, min_ms: 2000 },
+            trim: SilenceRule { enabled: false, threshold_db: -60.0, min_ms: 500 },
+        }
+    }
+}
+
+/// Parses a duration in seconds, e.g. "2", "0.5" or "1.5s", into milliseconds.
+pub fn parse_seconds(value: &str) -> Option<u64> {
+    let secs: f32 = value.trim().trim_end_matches('s').trim().parse().ok()?;
+    (secs.is_finite() && secs >= 0.0).then(|| ((secs * 1000.0).round() as u64).max(MIN_SILENCE_MS))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SilenceMode {
+    /// Drop every silent run of the minimum length.
+    Skip,
+    /// Drop such runs only at the start and end of the track.
+    Trim,
+}
+
+/// Decoded audio as `(track frame of the first frame, interleaved samples)` segments.
+pub type Segments = Vec<(u64, Vec<f32>)>;
+
+/// Removes silent runs from a stream of interleaved audio. Silent frames are
+/// held back until the run ends and it is known whether it is dropped.
+struct SilenceFilter {
+    mode: SilenceMode,
+    channels: usize,
+    threshold: f32,
+    min_frames: u64,
+    max_held_frames: u64,
+    /// Nothing audible has been passed on since the start of the track.
+    leading: bool,
+    run_start: u64,
+    run_frames: u64,
+    /// Held frames of the current run; empty once the run is known to be dropped.
+    held: Vec<f32>,
+    /// The run was too long to hold and is being played.
+    released: bool,
+    dropped_frames: u64,
+}
+
+/// Appends a frame starting at track frame `frame` to the output, joining it
+/// to the last segment when it follows on.
+fn push_frames(out: &mut Segments, frame: u64, samples: &[f32], channels: usize) {
+    if let Some((start, last)) = out.last_mut()
+        && *start + (last.len() / channels) as u64 == frame
+    {
+        last.extend_from_slice(samples);
+        return;
+    }
+    out.push((frame, samples.to_vec()));
+}
+
+impl SilenceFilter {
+    fn new(mode: SilenceMode, rule: &SilenceRule, channels: usize, sample_rate: u32, at_track_start: bool) -> Self {
+        let frames = |ms: u64| ms * sample_rate as u64 / 1000;
+        Self {
+            mode,
+            channels: channels.max(1),
+            threshold: 10f32.powf(rule.threshold_db / 20.0),
+            min_frames: frames(rule.min_ms.max(MIN_SILENCE_MS)).max(1),
+            max_held_frames: frames(MAX_HELD_MS),
+            leading: at_track_start,
+            run_start: 0,
+            run_frames: 0,
+            held: Vec::new(),
+            released: false,
+            dropped_frames: 0,
+        }
+    }
+
+    /// Whether the current run is dropped however it ends.
+    fn surely_dropped(&self) -> bool {
+        self.run_frames >= self.min_frames && (self.mode == SilenceMode::Skip || self.leading)
+    }
+
+    fn end_run(&mut self, trailing: bool, out: &mut Segments) {
+        if self.run_frames == 0 {
+            return;
+        }
+        let dropped = !self.released && self.run_frames >= self.min_frames && match self.mode {
+            SilenceMode::Skip => true,
+            SilenceMode::Trim => self.leading || trailing,
+        };
+        if dropped {
+            self.dropped_frames += self.run_frames;
+        } else if !self.released {
+            push_frames(out, self.run_start, &self.held, self.channels);
+        }
+        self.held.clear();
+        self.run_frames = 0;
+        self.released = false;
+    }
+
+    fn process(&mut self, start_frame: u64, samples: &[f32], out: &mut Segments) {
+        // Audio that does not follow on (a loop jump) ends the run
+        if self.run_frames > 0 && start_frame != self.run_start + self.run_frames {
+            self.end_run(false, out);
+        }
+        for (i, frame) in samples.chunks_exact(self.channels).enumerate() {
+            let index = start_frame + i as u64;
+            if frame.iter().all(|s| s.abs() < self.threshold) {
+                if self.run_frames == 0 {
+                    self.run_start = index;
+                }
+                self.run_frames += 1;
+                if self.released {
+                    push_frames(out, index, frame, self.channels);
+                } else if self.surely_dropped() {
+                    self.held.clear();
+                } else {
+                    self.held.extend_from_slice(frame);
+                    if self.run_frames > self.max_held_frames {
+                        push_frames(out, self.run_start, &self.held, self.channels);
+                        self.held.clear();
+                        self.released = true;
+                    }
+                }
+            } else {
+                self.end_run(false, out);
+                self.leading = false;
+                push_frames(out, index, frame, self.channels);
+            }
+        }
+    }
+}
+
+/// The skip and trim filters a track's audio goes through.
+pub struct SilenceRemover {
+    filters: Vec<SilenceFilter>,
+}
+
+impl SilenceRemover {
+    /// `at_track_start` is false after a seek, when there is no leading silence to trim.
+    pub fn new(settings: &SilenceSettings, channels: usize, sample_rate: u32, at_track_start: bool) -> Self {
+        let mut filters = Vec::new();
+        if settings.trim.enabled {
+            filters.push(SilenceFilter::new(SilenceMode::Trim, &settings.trim, channels, sample_rate, at_track_start));
+        }
+        if settings.skip.enabled {
+            filters.push(SilenceFilter::new(SilenceMode::Skip, &settings.skip, channels, sample_rate, at_track_start));
+        }
+        Self { filters }
+    }
+
+    /// Passes on the audio that is not (or not yet known to be) silence to drop.
+    pub fn process(&mut self, start_frame: u64, samples: Vec<f32>) -> Segments {
+        let mut segments = vec![(start_frame, samples)];
+        for filter in &mut self.filters {
+            let mut out = Vec::new();
+            for (start, samples) in &segments {
+                filter.process(*start, samples, &mut out);
+            }
+            segments = out;
+        }
+        segments
+    }
+
+    /// Ends the track: returns the held audio that is not trailing silence.
+    pub fn finish(&mut self) -> Segments {
+        let mut segments: Segments = Vec::new();
+        for filter in &mut self.filters {
+            let mut out = Vec::new();
+            for (start, samples) in &segments {
+                filter.process(*start, samples, &mut out);
+            }
+            filter.end_run(true, &mut out);
+            segments = out;
+        }
+        segments
+    }
+
+    /// Track frames dropped so far.
+    pub fn dropped_frames(&self) -> u64 {
+        self.filters.iter().map(|f| f.dropped_frames).sum()
+    }
+}
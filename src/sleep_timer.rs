@@ -0,0 +1,147 @@
+use crossterm::event;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Modifier, Color}};
+use std::time::{Duration, Instant};
+use std::{io, error::Error};
+
+/// Playback fades out over this long before the timer stops it.
+pub const FADE_MS: u64 = 30_000;
+
+/// When the sleep timer stops playback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SleepTimer {
+    At(Instant),
+    EndOfTrack,
+    EndOfQueue,
+}
+
+impl SleepTimer {
+    pub fn after_minutes(minutes: u64) -> Self {
+        SleepTimer::At(Instant::now() + Duration::from_secs(minutes * 60))
+    }
+
+    /// Milliseconds of playback left before the timer fires. `remaining_in_track`
+    /// is what is left of the playing track, `last_in_queue` whether it is the last one.
+    pub fn remaining_ms(&self, now: Instant, remaining_in_track: Option<u64>, last_in_queue: bool) -> Option<u64> {
+        match self {
+            SleepTimer::At(deadline) => Some(deadline.saturating_duration_since(now).as_millis() as u64),
+            SleepTimer::EndOfTrack => remaining_in_track,
+            SleepTimer::EndOfQueue if last_in_queue => remaining_in_track,
+            SleepTimer::EndOfQueue => None,
+        }
+    }
+
+    pub fn label(&self, now: Instant) -> String {
+        match self {
+            SleepTimer::At(deadline) => {
+                let secs = deadline.saturating_duration_since(now).as_secs();
+                format!("Sleep {}:{:02}", secs / 60, secs % 60)
+            }
+            SleepTimer::EndOfTrack => String::from("Sleep after track"),
+            SleepTimer::EndOfQueue => String::from("Sleep after queue"),
+        }
+    }
+}
+
+/// Volume for `remaining_ms` of playback left: full until the last 30
+/// seconds, then an equal-power fall to silence.
+pub fn fade_gain(remaining_ms: Option<u64>) -> f32 {
+    match remaining_ms {
+        Some(ms) if ms < FADE_MS => {
+            let left = ms as f32 / FADE_MS as f32;
+            (left * std::f32::consts::FRAC_PI_2).sin()
+        }
+        _ => 1.0,
+    }
+}
+
+const PRESET_MINUTES: [u64; 6] = [15, 30, 45, 60, 90, 120];
+
+/// Sleep timer chooser. Returns the new timer (`None` turns it off), or
+/// `Err("Cancelled")` when the screen is left without a choice.
+pub fn choose_sleep_timer(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    current: Option<SleepTimer>,
+    debug_mode: bool,
+) -> Result<Option<SleepTimer>, Box<dyn Error>> {
+    let mut options: Vec<(String, Option<SleepTimer>)> = vec![(String::from("Off"), None)];
+    let preset_count = PRESET_MINUTES.len();
+    options.extend(PRESET_MINUTES.iter().map(|m| (format!("{} minutes", m), None)));
+    options.push((String::from("At the end of this track"), Some(SleepTimer::EndOfTrack)));
+    options.push((String::from("At the end of the queue"), Some(SleepTimer::EndOfQueue)));
+    let mut list_state = ListState::default();
+    list_state.select(Some(0));
+    let mut minutes = String::new();
+
+    loop {
+        let now = Instant::now();
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Min(5),
+                    Constraint::Length(3),
+                    Constraint::Length(3),
+                ].as_ref())
+                .split(f.size());
+
+            let title = match &current {
+                Some(timer) => format!("Sleep Timer - {} (fades out over the last 30 s)", timer.label(now)),
+                None => String::from("Sleep Timer - off (fades out over the last 30 s)"),
+            };
+            let items: Vec<ListItem> = options.iter().map(|(label, _)| ListItem::new(label.clone())).collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .highlight_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
+                .highlight_symbol(">> ");
+            f.render_stateful_widget(list, chunks[0], &mut list_state);
+
+            let input = Paragraph::new(format!("{}_", minutes))
+                .block(Block::default().borders(Borders::ALL).title("Or type minutes"));
+            f.render_widget(input, chunks[1]);
+
+            let help = Paragraph::new("[Up/Down] Select  [0-9] Minutes  [Enter] Set  [ESC] Cancel")
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[2]);
+        })?;
+
+        // Polled so the countdown in the title keeps moving
+        if event::poll(Duration::from_millis(500))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                event::KeyCode::Esc => return Err("Cancelled".into()),
+                event::KeyCode::Up => {
+                    let i = list_state.selected().unwrap_or(0);
+                    list_state.select(Some(i.saturating_sub(1)));
+                },
+                event::KeyCode::Down => {
+                    let i = list_state.selected().unwrap_or(0);
+                    list_state.select(Some((i + 1).min(options.len() - 1)));
+                },
+                event::KeyCode::Char(c) if c.is_ascii_digit() && minutes.len() < 4 => minutes.push(c),
+                event::KeyCode::Backspace => {
+                    minutes.pop();
+                },
+                event::KeyCode::Enter => {
+                    let timer = match minutes.parse::<u64>() {
+                        Ok(m) if m > 0 => Some(SleepTimer::after_minutes(m)),
+                        _ => match list_state.selected().unwrap_or(0) {
+                            i if (1..=preset_count).contains(&i) => Some(SleepTimer::after_minutes(PRESET_MINUTES[i - 1])),
+                            i => options[i].1,
+                        },
+                    };
+                    if debug_mode {
+                        println!("[DEBUG] Sleep timer set to {:?}", timer);
+                    }
+                    return Ok(timer);
+                },
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,578 @@
+use crate::library::{Library, LibraryTrack};
+use crate::stats::PlayStats;
+use crate::QueueAction;
+use crossterm::event;
+use rand::seq::SliceRandom;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, Paragraph, ListState, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Modifier, Color}};
+use std::cmp::Ordering;
+use std::{fs, io, error::Error};
+use std::sync::{Arc, Mutex};
+
+pub const PLAYLISTS_FILE: &str = ".rdaio_playlists";
+
+/// Track properties usable in rules, `order` and comparisons.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Composer,
+    Comment,
+    Genre,
+    Path,
+    Year,
+    Track,
+    Disc,
+    Plays,
+    Skips,
+    /// 1-5 stars; unrated tracks have no value.
+    Rating,
+    /// 1 for favourites, 0 otherwise; compares against `yes`/`no` too.
+    Favourite,
+    /// Days since the last counted play; never-played tracks are infinitely old.
+    DaysSincePlayed,
+}
+
+/// Data besides the tags that rules can refer to.
+pub struct RuleContext<'a> {
+    pub stats: &'a PlayStats,
+    /// Unix time the rules are evaluated at.
+    pub now: u64,
+}
+
+/// A field's value for one track.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    Text(String),
+    Number(f64),
+    Missing,
+}
+
+impl RuleField {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "title" => Some(RuleField::Title),
+            "artist" => Some(RuleField::Artist),
+            "album" => Some(RuleField::Album),
+            "albumartist" | "album_artist" => Some(RuleField::AlbumArtist),
+            "composer" => Some(RuleField::Composer),
+            "comment" => Some(RuleField::Comment),
+            "genre" => Some(RuleField::Genre),
+            "path" | "file" => Some(RuleField::Path),
+            "year" => Some(RuleField::Year),
+            "track" => Some(RuleField::Track),
+            "disc" => Some(RuleField::Disc),
+            "plays" | "playcount" => Some(RuleField::Plays),
+            "skips" | "skipcount" => Some(RuleField::Skips),
+            "rating" | "stars" => Some(RuleField::Rating),
+            "favourite" | "favorite" | "loved" => Some(RuleField::Favourite),
+            "days_since_played" | "lastplayed" | "last_played" => Some(RuleField::DaysSincePlayed),
+            _ => None,
+        }
+    }
+
+    pub fn value(self, track: &LibraryTrack, ctx: &RuleContext) -> FieldValue {
+        let text = |v: &Option<String>| v.clone().map(FieldValue::Text).unwrap_or(FieldValue::Missing);
+        let number = |v: Option<f64>| v.map(FieldValue::Number).unwrap_or(FieldValue::Missing);
+        let tags = &track.tags;
+        match self {
+            RuleField::Title => text(&tags.title),
+            RuleField::Artist => text(&tags.artist),
+            RuleField::Album => text(&tags.album),
+            RuleField::AlbumArtist => text(&tags.album_artist),
+            RuleField::Composer => text(&tags.composer),
+            RuleField::Comment => text(&tags.comment),
+            RuleField::Genre => text(&tags.genre),
+            RuleField::Path => FieldValue::Text(track.path.clone()),
+            RuleField::Year => number(tags.year.map(f64::from)),
+            RuleField::Track => number(tags.track.map(f64::from)),
+            RuleField::Disc => number(tags.disc.map(f64::from)),
+            RuleField::Plays => FieldValue::Number(ctx.stats.get(&track.path).map(|s| s.play_count).unwrap_or(0).into()),
+            RuleField::Skips => FieldValue::Number(ctx.stats.get(&track.path).map(|s| s.skip_count).unwrap_or(0).into()),
+            RuleField::Rating => number(ctx.stats.get(&track.path).and_then(|s| s.rating).map(f64::from)),
+            RuleField::Favourite => FieldValue::Number(if ctx.stats.get(&track.path).is_some_and(|s| s.favourite) { 1.0 } else { 0.0 }),
+            RuleField::DaysSincePlayed => FieldValue::Number(
+                ctx.stats.get(&track.path)
+                    .and_then(|s| s.last_played)
+                    .map(|t| ctx.now.saturating_sub(t) as f64 / 86400.0)
+                    .unwrap_or(f64::INFINITY),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Is,
+    IsNot,
+    Contains,
+    NotContains,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// `field op value`, e.g. `genre is Jazz` or `year >= 1970`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub field: RuleField,
+    pub op: Op,
+    pub value: String,
+}
+
+impl Condition {
+    pub fn matches(&self, track: &LibraryTrack, ctx: &RuleContext) -> bool {
+        let actual = self.field.value(track, ctx);
+        match (&actual, self.op) {
+            (FieldValue::Missing, Op::IsNot | Op::NotContains) => true,
+            (FieldValue::Missing, _) => false,
+            (FieldValue::Text(text), op) => {
+                let text = text.to_lowercase();
+                let wanted = self.value.to_lowercase();
+                match op {
+                    Op::Is => text == wanted,
+                    Op::IsNot => text != wanted,
+                    Op::Contains => text.contains(&wanted),
+                    Op::NotContains => !text.contains(&wanted),
+                    Op::Less => text < wanted,
+                    Op::LessOrEqual => text <= wanted,
+                    Op::Greater => text > wanted,
+                    Op::GreaterOrEqual => text >= wanted,
+                }
+            }
+            (FieldValue::Number(n), op) => {
+                let wanted = match self.value.to_ascii_lowercase().as_str() {
+                    "yes" | "true" => 1.0,
+                    "no" | "false" => 0.0,
+                    value => match value.parse::<f64>() {
+                        Ok(wanted) => wanted,
+                        Err(_) => return false,
+                    },
+                };
+                match op {
+                    Op::Is | Op::Contains => *n == wanted,
+                    Op::IsNot | Op::NotContains => *n != wanted,
+                    Op::Less => *n < wanted,
+                    Op::LessOrEqual => *n <= wanted,
+                    Op::Greater => *n > wanted,
+                    Op::GreaterOrEqual => *n >= wanted,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PlaylistOrder {
+    /// Library (path) order.
+    Default,
+    Random,
+    Field(RuleField, bool),
+}
+
+/// A saved rule set that is re-evaluated against the library every time it is loaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmartPlaylist {
+    pub name: String,
+    /// Alternatives joined by `or`; each is a list of conditions joined by `and`.
+    pub any_of: Vec<Vec<Condition>>,
+    pub limit: Option<usize>,
+    pub order: PlaylistOrder,
+    /// The rule text as the user wrote it.
+    pub source: String,
+}
+
+fn parse_op(words: &[&str]) -> Option<(Op, usize)> {
+    let first = words.first()?.to_ascii_lowercase();
+    let second = words.get(1).map(|w| w.to_ascii_lowercase());
+    let op = match (first.as_str(), second.as_deref()) {
+        ("is", Some("not")) => return Some((Op::IsNot, 2)),
+        ("does", Some("not")) if words.get(2).is_some_and(|w| w.eq_ignore_ascii_case("contain")) => return Some((Op::NotContains, 3)),
+        ("is" | "=" | "==", _) => Op::Is,
+        ("!=" | "isnt", _) => Op::IsNot,
+        ("contains" | "has" | "~", _) => Op::Contains,
+        ("<", _) => Op::Less,
+        ("<=" | "≤", _) => Op::LessOrEqual,
+        (">", _) => Op::Greater,
+        (">=" | "≥", _) => Op::GreaterOrEqual,
+        _ => return None,
+    };
+    Some((op, 1))
+}
+
+const KEYWORDS: [&str; 4] = ["and", "or", "limit", "order"];
+
+fn is_keyword(word: &str) -> bool {
+    KEYWORDS.contains(&word.to_ascii_lowercase().as_str())
+}
+
+/// Splits a rule into words. Text in double quotes is one word that is never
+/// taken for a keyword; the flag marks such words.
+fn tokenize(rule: &str) -> Result<Vec<(String, bool)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = rule.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(ch) => text.push(ch),
+                    None => return Err(String::from("unterminated quote")),
+                }
+            }
+            // A comma after the closing quote only separates clauses
+            if chars.peek() == Some(&',') {
+                chars.next();
+            }
+            tokens.push((text, true));
+        } else {
+            let mut text = String::new();
+            while let Some(&ch) = chars.peek().filter(|ch| !ch.is_whitespace() && **ch != '"') {
+                text.push(ch);
+                chars.next();
+            }
+            tokens.push((text, false));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses `genre is Jazz and year >= 1960 or artist contains miles limit 50 order random`.
+/// Values containing a keyword are quoted: `artist is "Simon and Garfunkel"`.
+///
+/// `not played in N days` and `played in N days` are shorthands for
+/// `days_since_played > N` and `days_since_played <= N`.
+pub fn parse_rule(name: &str, rule: &str) -> Result<SmartPlaylist, String> {
+    // Saved as `name = rule` lines, so the name cannot hold the separator
+    if name.contains('=') {
+        return Err(String::from("playlist names cannot contain '='"));
+    }
+    let tokens = tokenize(rule)?;
+    let quoted: Vec<bool> = tokens.iter().map(|(_, quoted)| *quoted).collect();
+    let keyword_at = |i: usize| !quoted[i] && is_keyword(&tokens[i].0);
+    // "limit 50, order random": drop commas that only separate clauses
+    let words: Vec<&str> = tokens.iter().enumerate()
+        .map(|(i, (w, quoted))| {
+            if *quoted || (i + 1 < tokens.len() && !keyword_at(i + 1)) {
+                w.as_str()
+            } else {
+                w.strip_suffix(',').unwrap_or(w)
+            }
+        })
+        .collect();
+    let mut any_of: Vec<Vec<Condition>> = vec![Vec::new()];
+    let mut limit = None;
+    let mut order = PlaylistOrder::Default;
+    let mut i = 0;
+
+    while i < words.len() {
+        let word = if quoted[i] { String::new() } else { words[i].to_ascii_lowercase() };
+        match word.as_str() {
+            "and" => {
+                i += 1;
+                continue;
+            }
+            "or" => {
+                any_of.push(Vec::new());
+                i += 1;
+                continue;
+            }
+            "limit" => {
+                let n = words.get(i + 1).and_then(|w| w.parse().ok()).ok_or("limit needs a number")?;
+                limit = Some(n);
+                i += 2;
+                continue;
+            }
+            "order" => {
+                let target = words.get(i + 1).ok_or("order needs a field or 'random'")?;
+                if target.eq_ignore_ascii_case("random") {
+                    order = PlaylistOrder::Random;
+                    i += 2;
+                } else {
+                    let field = RuleField::from_name(target).ok_or_else(|| format!("unknown field '{}'", target))?;
+                    let descending = words.get(i + 2).is_some_and(|w| w.eq_ignore_ascii_case("desc"));
+                    let has_direction = words.get(i + 2).is_some_and(|w| w.eq_ignore_ascii_case("desc") || w.eq_ignore_ascii_case("asc"));
+                    order = PlaylistOrder::Field(field, descending);
+                    i += if has_direction { 3 } else { 2 };
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some((condition, used)) = parse_played_in(&words[i..]) {
+            any_of.last_mut().unwrap().push(condition);
+            i += used;
+            continue;
+        }
+
+        let field = RuleField::from_name(words[i]).ok_or_else(|| format!("unknown field '{}'", words[i]))?;
+        let (op, used) = parse_op(&words[i + 1..]).ok_or_else(|| format!("missing operator after '{}'", words[i]))?;
+        let value_start = i + 1 + used;
+        // The value runs until the next keyword
+        let mut value_end = value_start;
+        while value_end < words.len() && !keyword_at(value_end) {
+            value_end += 1;
+        }
+        if value_end == value_start {
+            return Err(format!("missing value for '{}'", words[i]));
+        }
+        let value = words[value_start..value_end].join(" ");
+        any_of.last_mut().unwrap().push(Condition { field, op, value });
+        i = value_end;
+    }
+
+    any_of.retain(|group| !group.is_empty());
+    Ok(SmartPlaylist {
+        name: name.to_string(),
+        any_of,
+        limit,
+        order,
+        source: rule.trim().to_string(),
+    })
+}
+
+/// `[not] played in N days`, returning the condition and the words used.
+fn parse_played_in(words: &[&str]) -> Option<(Condition, usize)> {
+    let negated = words.first()?.eq_ignore_ascii_case("not");
+    let rest = if negated { &words[1..] } else { words };
+    if rest.len() < 4
+        || !rest[0].eq_ignore_ascii_case("played")
+        || !rest[1].eq_ignore_ascii_case("in")
+        || !rest[3].to_ascii_lowercase().starts_with("day")
+    {
+        return None;
+    }
+    rest[2].parse::<f64>().ok()?;
+    let op = if negated { Op::Greater } else { Op::LessOrEqual };
+    let condition = Condition { field: RuleField::DaysSincePlayed, op, value: rest[2].to_string() };
+    Some((condition, if negated { 5 } else { 4 }))
+}
+
+impl SmartPlaylist {
+    pub fn matches(&self, track: &LibraryTrack, ctx: &RuleContext) -> bool {
+        // No conditions selects everything (useful with order/limit alone)
+        self.any_of.is_empty() || self.any_of.iter().any(|group| group.iter().all(|c| c.matches(track, ctx)))
+    }
+
+    /// Evaluates the rules now and returns the matching track paths.
+    pub fn evaluate(&self, library: &Library, ctx: &RuleContext) -> Vec<String> {
+        let mut tracks: Vec<&LibraryTrack> = library.tracks.iter().filter(|t| self.matches(t, ctx)).collect();
+        match &self.order {
+            PlaylistOrder::Default => {}
+            PlaylistOrder::Random => tracks.shuffle(&mut rand::thread_rng()),
+            PlaylistOrder::Field(field, descending) => {
+                tracks.sort_by(|a, b| {
+                    let ordering = compare_values(&field.value(a, ctx), &field.value(b, ctx));
+                    if *descending { ordering.reverse() } else { ordering }
+                });
+            }
+        }
+        if let Some(limit) = self.limit {
+            tracks.truncate(limit);
+        }
+        tracks.into_iter().map(|t| t.path.clone()).collect()
+    }
+}
+
+/// Orders values with missing ones last.
+fn compare_values(a: &FieldValue, b: &FieldValue) -> Ordering {
+    match (a, b) {
+        (FieldValue::Number(x), FieldValue::Number(y)) => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+        (FieldValue::Text(x), FieldValue::Text(y)) => x.to_lowercase().cmp(&y.to_lowercase()),
+        (FieldValue::Missing, FieldValue::Missing) => Ordering::Equal,
+        (FieldValue::Missing, _) => Ordering::Greater,
+        (_, FieldValue::Missing) => Ordering::Less,
+        (FieldValue::Number(_), FieldValue::Text(_)) => Ordering::Less,
+        (FieldValue::Text(_), FieldValue::Number(_)) => Ordering::Greater,
+    }
+}
+
+/// Reads `name = rule` lines. Lines that fail to parse are skipped.
+pub fn load_playlists(path: &str) -> Vec<SmartPlaylist> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    content.lines()
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            let (name, rule) = line.split_once('=')?;
+            parse_rule(name.trim(), rule).ok()
+        })
+        .collect()
+}
+
+pub fn save_playlists(path: &str, playlists: &[SmartPlaylist]) -> Result<(), Box<dyn Error>> {
+    let mut data = String::new();
+    for playlist in playlists {
+        data.push_str(&format!("{} = {}\n", playlist.name, playlist.source));
+    }
+    fs::write(path, data)?;
+    Ok(())
+}
+
+/// Lists saved smart playlists; lets the user load, add, edit and delete them.
+pub fn manage_playlists(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    library: &Arc<Mutex<Library>>,
+    stats: &PlayStats,
+    debug_mode: bool,
+) -> Result<QueueAction, Box<dyn Error>> {
+    let mut playlists = load_playlists(PLAYLISTS_FILE);
+    let mut list_state = ListState::default();
+    list_state.select(if playlists.is_empty() { None } else { Some(0) });
+    // Text being edited as "Name = rule"; `editing` holds the index being replaced
+    let mut input: Option<String> = None;
+    let mut editing: Option<usize> = None;
+    let mut message = String::new();
+
+    // Matching every rule against the library is slow and holds the library lock,
+    // so the counts are only worked out again when a playlist changes
+    let count_matches = |playlists: &[SmartPlaylist]| -> Vec<usize> {
+        let ctx = RuleContext { stats, now: crate::stats::now_secs() };
+        let lib = library.lock().unwrap();
+        playlists.iter().map(|p| lib.tracks.iter().filter(|t| p.matches(t, &ctx)).count()).collect()
+    };
+    let mut counts = count_matches(&playlists);
+
+    loop {
+        let ctx = RuleContext { stats, now: crate::stats::now_secs() };
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Min(5),
+                    Constraint::Length(3),
+                    Constraint::Length(4),
+                ].as_ref())
+                .split(f.size());
+
+            let items: Vec<ListItem> = playlists.iter().zip(counts.iter())
+                .map(|(p, count)| {
+                    let limit = p.limit.map(|l| format!(", limit {}", l)).unwrap_or_default();
+                    ListItem::new(format!("{} ({} matches{})  -  {}", p.name, count, limit, p.source))
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title("Smart Playlists"))
+                .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+                .highlight_symbol(">> ");
+            f.render_stateful_widget(list, chunks[0], &mut list_state);
+
+            let (editor_title, editor_text) = match &input {
+                Some(text) => ("Name = rule  ([Enter] Save  [ESC] Cancel)", format!("{}_", text)),
+                None => ("Rule", message.clone()),
+            };
+            let editor = Paragraph::new(editor_text)
+                .block(Block::default().borders(Borders::ALL).title(editor_title));
+            f.render_widget(editor, chunks[1]);
+
+            let help = Paragraph::new("[Enter] Load (replace queue)  [A] Append  [N] New  [E] Edit  [D] Delete  [ESC] Back   Rules: genre is Jazz and plays >= 3 and not played in 30 days limit 50 order random")
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[2]);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(200))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+
+            if let Some(text) = input.as_mut() {
+                match key.code {
+                    event::KeyCode::Esc => {
+                        input = None;
+                        editing = None;
+                    },
+                    event::KeyCode::Backspace => {
+                        text.pop();
+                    },
+                    event::KeyCode::Char(c) => text.push(c),
+                    event::KeyCode::Enter => {
+                        let parsed = text.split_once('=')
+                            .ok_or_else(|| String::from("expected 'Name = rule'"))
+                            .and_then(|(name, rule)| parse_rule(name.trim(), rule));
+                        match parsed {
+                            Ok(playlist) if !playlist.name.is_empty() => {
+                                match editing.take() {
+                                    Some(idx) if idx < playlists.len() => playlists[idx] = playlist,
+                                    _ => playlists.push(playlist),
+                                }
+                                let _ = save_playlists(PLAYLISTS_FILE, &playlists);
+                                counts = count_matches(&playlists);
+                                message = String::from("Saved");
+                                input = None;
+                                if list_state.selected().is_none() {
+                                    list_state.select(Some(0));
+                                }
+                            }
+                            Ok(_) => message = String::from("Error: playlist needs a name"),
+                            Err(e) => message = format!("Error: {}", e),
+                        }
+                    },
+                    _ => {}
+                }
+                continue;
+            }
+
+            let selected = list_state.selected().filter(|i| *i < playlists.len());
+            match key.code {
+                event::KeyCode::Esc => {
+                    return Err("Cancelled".into());
+                },
+                event::KeyCode::Down if !playlists.is_empty() => {
+                    let i = selected.map(|i| if i >= playlists.len() - 1 { 0 } else { i + 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Up if !playlists.is_empty() => {
+                    let i = selected.map(|i| if i == 0 { playlists.len() - 1 } else { i - 1 }).unwrap_or(0);
+                    list_state.select(Some(i));
+                },
+                event::KeyCode::Enter | event::KeyCode::Char('a') | event::KeyCode::Char('A') => {
+                    if let Some(i) = selected {
+                        let tracks = playlists[i].evaluate(&library.lock().unwrap(), &ctx);
+                        if debug_mode {
+                            println!("[DEBUG] Smart playlist '{}' -> {} tracks", playlists[i].name, tracks.len());
+                        }
+                        if tracks.is_empty() {
+                            message = format!("'{}' matches no tracks", playlists[i].name);
+                        } else if key.code == event::KeyCode::Enter {
+                            return Ok(QueueAction::Replace(tracks));
+                        } else {
+                            return Ok(QueueAction::Append(tracks));
+                        }
+                    }
+                },
+                event::KeyCode::Char('n') | event::KeyCode::Char('N') => {
+                    input = Some(String::new());
+                    editing = None;
+                },
+                event::KeyCode::Char('e') | event::KeyCode::Char('E') => {
+                    if let Some(i) = selected {
+                        input = Some(format!("{} = {}", playlists[i].name, playlists[i].source));
+                        editing = Some(i);
+                    }
+                },
+                event::KeyCode::Char('d') | event::KeyCode::Char('D') => {
+                    if let Some(i) = selected {
+                        playlists.remove(i);
+                        let _ = save_playlists(PLAYLISTS_FILE, &playlists);
+                        counts = count_matches(&playlists);
+                        list_state.select(if playlists.is_empty() { None } else { Some(i.min(playlists.len() - 1)) });
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
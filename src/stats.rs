@@ -0,0 +1,457 @@
+use crate::metadata::{escape_field, unescape_field};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const STATS_FILE: &str = ".rdaio_stats";
+
+/// Listening statistics of one track.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackStats {
+    pub play_count: u32,
+    pub skip_count: u32,
+    /// Unix time (seconds) of the last counted play.
+    pub last_played: Option<u64>,
+    /// Hash of the audio frames, used to follow renamed files.
+    pub audio_hash: Option<String>,
+    /// 1-5 stars, `None` when unrated.
+    pub rating: Option<u8>,
+    pub favourite: bool,
+}
+
+/// How a listen came to an end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListenEnd {
+    /// The track played to the end.
+    Finished,
+    /// The user moved on with PageDown or by playing another track.
+    Skipped,
+    /// Playback was stopped or the player quit.
+    Stopped,
+}
+
+/// What a listen counted as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListenOutcome {
+    Played,
+    Skipped,
+    Ignored,
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Stats are keyed by absolute path so relative queue entries and library paths agree.
+pub fn stats_key(path: &str) -> String {
+    std::path::absolute(path)
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// Decides whether a listen counts as a play, a skip or neither.
+///
+/// A listen shorter than `skip_threshold` percent of the track is a skip when
+/// the user moved on, and is ignored when playback was merely stopped.
+pub fn classify_listen(listened_ms: u64, duration_ms: Option<u64>, end: ListenEnd, skip_threshold: u32) -> ListenOutcome {
+    let reached_threshold = match duration_ms {
+        Some(total) if total > 0 => listened_ms * 100 >= total * u64::from(skip_threshold),
+        _ => false,
+    };
+    match end {
+        // Tracks that end within a second failed to decode
+        ListenEnd::Finished if listened_ms >= 1000 || reached_threshold => ListenOutcome::Played,
+        ListenEnd::Finished => ListenOutcome::Ignored,
+        _ if reached_threshold => ListenOutcome::Played,
+        ListenEnd::Skipped => ListenOutcome::Skipped,
+        ListenEnd::Stopped => ListenOutcome::Ignored,
+    }
+}
+
+/// FNV-1a hash of a file's audio data, ignoring ID3v2 and ID3v1 tags so
+/// that retagging a file keeps its hash.
+pub fn audio_hash(path: &str) -> Option<String> {
+    let data = fs::read(path).ok()?;
+    let mut start = 0;
+    if data.len() >= 10 && &data[..3] == b"ID3" {
+        let size = data[6..10].iter().fold(0usize, |acc, b| (acc << 7) | usize::from(b & 0x7f));
+        let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+        start = (10 + size + footer).min(data.len());
+    }
+    let mut end = data.len();
+    if end >= start + 128 && &data[end - 128..end - 125] == b"TAG" {
+        end -= 128;
+    }
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in &data[start..end] {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    Some(format!("{:016x}", hash))
+}
+
+/// Audio hashes worked out by a background thread, as reading whole files
+/// is too slow for the UI thread.
+pub struct AudioHashCache {
+    entries: Arc<Mutex<HashMap<String, Option<String>>>>,
+    pending: Arc<Mutex<HashSet<String>>>,
+    requests: Sender<String>,
+}
+
+impl AudioHashCache {
+    pub fn new() -> Self {
+        let entries = Arc::new(Mutex::new(HashMap::new()));
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+        let (requests, rx) = mpsc::channel::<String>();
+
+        std::thread::spawn({
+            let entries = entries.clone();
+            let pending = pending.clone();
+            move || {
+                for track_path in rx {
+                    let hash = audio_hash(&track_path);
+                    entries.lock().unwrap().insert(track_path.clone(), hash);
+                    pending.lock().unwrap().remove(&track_path);
+                }
+            }
+        });
+
+        Self { entries, pending, requests }
+    }
+
+    /// `Some(None)` when the file could not be read, `None` while it is being hashed.
+    pub fn get(&self, track_path: &str) -> Option<Option<String>> {
+        if let Some(hash) = self.entries.lock().unwrap().get(track_path) {
+            return Some(hash.clone());
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.insert(track_path.to_string()) {
+            let _ = self.requests.send(track_path.to_string());
+        }
+        None
+    }
+}
+
+/// Stats keys of the queue entries, worked out again only when the queue changes.
+#[derive(Debug, Default)]
+pub struct QueueKeys {
+    files: Vec<String>,
+    directory: String,
+    keys: Vec<String>,
+}
+
+impl QueueKeys {
+    pub fn update(&mut self, files: &[String], directory: &str) -> &[String] {
+        if self.files != files || self.directory != directory {
+            self.keys = files.iter().map(|f| stats_key(&crate::get_track_path(f, directory))).collect();
+            self.files = files.to_vec();
+            self.directory = directory.to_string();
+        }
+        &self.keys
+    }
+}
+
+/// Time spent listening to the current track, excluding pauses. It is
+/// measured in track time, so a track played at 2x is fully heard in half its length.
+#[derive(Clone, Debug)]
+pub struct ListenSession {
+    /// Stats key of the track.
+    pub path: String,
+    /// The track's path as queued, which is what the metadata cache is keyed by.
+    pub track_path: String,
+    /// Unix time the track started, for the listening history.
+    pub started_at: u64,
+    /// Track time heard before the current stretch.
+    listened: Duration,
+    /// Start of the current stretch at `speed`; `None` while paused.
+    resumed_at: Option<Instant>,
+    speed: f32,
+}
+
+impl ListenSession {
+    pub fn start(path: &str, speed: f32) -> Self {
+        Self {
+            path: stats_key(path),
+            track_path: path.to_string(),
+            started_at: now_secs(),
+            listened: Duration::ZERO,
+            resumed_at: Some(Instant::now()),
+            speed,
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.listened = self.heard(Instant::now());
+        self.resumed_at = None;
+    }
+
+    pub fn resume(&mut self) {
+        if self.resumed_at.is_none() {
+            self.resumed_at = Some(Instant::now());
+        }
+    }
+
+    /// Follows a change of playback speed; time from here on counts at `speed`.
+    pub fn set_speed(&mut self, speed: f32) {
+        if speed != self.speed {
+            let now = Instant::now();
+            self.listened = self.heard(now);
+            if self.resumed_at.is_some() {
+                self.resumed_at = Some(now);
+            }
+            self.speed = speed;
+        }
+    }
+
+    fn heard(&self, now: Instant) -> Duration {
+        let stretch = self.resumed_at.map(|at| now.saturating_duration_since(at)).unwrap_or_default();
+        self.listened + stretch.mul_f32(self.speed)
+    }
+
+    pub fn listened_ms(&self) -> u64 {
+        self.heard(Instant::now()).as_millis() as u64
+    }
+}
+
+/// Per-track play counts, skips, last-played times, ratings and favourites,
+/// persisted to `.rdaio_stats`.
+#[derive(Clone, Debug, Default)]
+pub struct PlayStats {
+    pub tracks: HashMap<String, TrackStats>,
+    /// Store audio hashes so history survives renames (`stats_audio_hash`).
+    pub use_audio_hash: bool,
+    /// Entries waiting for the background hasher.
+    unhashed: Vec<String>,
+}
+
+impl PlayStats {
+    pub fn load_from(path: &str) -> Self {
+        let tracks = match fs::read_to_string(path) {
+            Ok(content) => content.lines().filter_map(decode_stats_line).collect(),
+            Err(_) => HashMap::new(),
+        };
+        Self { tracks, use_audio_hash: false, unhashed: Vec::new() }
+    }
+
+    pub fn save_to(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let mut keys: Vec<&String> = self.tracks.keys().collect();
+        keys.sort();
+        let mut data = String::new();
+        for key in keys {
+            data.push_str(&encode_stats_line(key, &self.tracks[key]));
+            data.push('\n');
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn get(&self, path: &str) -> Option<&TrackStats> {
+        self.tracks.get(path)
+    }
+
+    /// Entry for a path. With audio hashing, entries without a hash are queued
+    /// for `apply_audio_hashes`.
+    fn entry_for(&mut self, path: &str) -> &mut TrackStats {
+        let stats = self.tracks.entry(path.to_string()).or_default();
+        if self.use_audio_hash && stats.audio_hash.is_none() && !self.unhashed.iter().any(|p| p == path) {
+            self.unhashed.push(path.to_string());
+        }
+        stats
+    }
+
+    /// Fills in the hashes the background hasher has finished; an entry whose
+    /// file has gone missing but has the same audio is merged into the new
+    /// path. Returns whether anything changed.
+    pub fn apply_audio_hashes(&mut self, hashes: &AudioHashCache) -> bool {
+        let mut changed = false;
+        for path in std::mem::take(&mut self.unhashed) {
+            match hashes.get(&path) {
+                Some(hash) => {
+                    changed = true;
+                    if let Some(hash) = hash {
+                        self.set_audio_hash(&path, hash);
+                    }
+                }
+                None => self.unhashed.push(path),
+            }
+        }
+        changed
+    }
+
+    fn set_audio_hash(&mut self, path: &str, hash: String) {
+        let moved = self.tracks.iter()
+            .find(|(old, s)| old.as_str() != path && s.audio_hash.as_ref() == Some(&hash) && !Path::new(old).exists())
+            .map(|(old, _)| old.clone());
+        let old = moved.and_then(|old| self.tracks.remove(&old));
+        let Some(stats) = self.tracks.get_mut(path) else {
+            return;
+        };
+        if let Some(old) = old {
+            stats.play_count += old.play_count;
+            stats.skip_count += old.skip_count;
+            stats.last_played = stats.last_played.max(old.last_played);
+            stats.rating = stats.rating.or(old.rating);
+            stats.favourite |= old.favourite;
+        }
+        stats.audio_hash = Some(hash);
+    }
+
+    /// Moves a track's statistics to its new path after a rename.
+    pub fn rename(&mut self, old_path: &str, new_path: &str) {
+        if let Some(stats) = self.tracks.remove(old_path) {
+            self.tracks.insert(new_path.to_string(), stats);
+        }
+    }
+
+    /// Sets (or with `None` clears) a track's star rating.
+    pub fn set_rating(&mut self, path: &str, stars: Option<u8>) {
+        self.entry_for(path).rating = stars.map(|s| s.clamp(1, 5));
+    }
+
+    /// Flips the favourite flag and returns the new state.
+    pub fn toggle_favourite(&mut self, path: &str) -> bool {
+        let stats = self.entry_for(path);
+        stats.favourite = !stats.favourite;
+        stats.favourite
+    }
+
+    /// Applies a finished listen and returns what it counted as.
+    pub fn record(&mut self, path: &str, listened_ms: u64, duration_ms: Option<u64>, end: ListenEnd, skip_threshold: u32, now: u64) -> ListenOutcome {
+        let outcome = classify_listen(listened_ms, duration_ms, end, skip_threshold);
+        match outcome {
+            ListenOutcome::Played => {
+                let stats = self.entry_for(path);
+                stats.play_count += 1;
+                stats.last_played = Some(now);
+            }
+            ListenOutcome::Skipped => self.entry_for(path).skip_count += 1,
+            ListenOutcome::Ignored => {}
+        }
+        outcome
+    }
+}
+
+fn encode_stats_line(path: &str, stats: &TrackStats) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        escape_field(path),
+        stats.play_count,
+        stats.skip_count,
+        stats.last_played.map(|t| t.to_string()).unwrap_or_default(),
+        stats.audio_hash.as_deref().unwrap_or(""),
+        stats.rating.map(|r| r.to_string()).unwrap_or_default(),
+        if stats.favourite { "1" } else { "" },
+    )
+}
+
+fn decode_stats_line(line: &str) -> Option<(String, TrackStats)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    // Files written before ratings existed have five fields
+    if fields.len() != 5 && fields.len() != 7 {
+        return None;
+    }
+    let stats = TrackStats {
+        play_count: fields[1].parse().ok()?,
+        skip_count: fields[2].parse().ok()?,
+        last_played: fields[3].parse().ok(),
+        audio_hash: (!fields[4].is_empty()).then(|| fields[4].to_string()),
+        rating: fields.get(5).and_then(|r| r.parse().ok()),
+        favourite: fields.get(6).is_some_and(|f| *f == "1"),
+    };
+    Some((unescape_field(fields[0]), stats))
+}
+
+/// Short relative time for the queue column, e.g. "5m ago" or "3d ago".
+pub fn format_age(then: u64, now: u64) -> String {
+    let secs = now.saturating_sub(then);
+    match secs {
+        0..=59 => String::from("just now"),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// Queue columns the T key sorts by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueSort {
+    Original,
+    Title,
+    Artist,
+    Rating,
+    Plays,
+    Skips,
+    LastPlayed,
+}
+
+impl QueueSort {
+    pub fn next(self) -> Self {
+        match self {
+            QueueSort::Original => QueueSort::Title,
+            QueueSort::Title => QueueSort::Artist,
+            QueueSort::Artist => QueueSort::Rating,
+            QueueSort::Rating => QueueSort::Plays,
+            QueueSort::Plays => QueueSort::Skips,
+            QueueSort::Skips => QueueSort::LastPlayed,
+            QueueSort::LastPlayed => QueueSort::Original,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            QueueSort::Original => "Queue order",
+            QueueSort::Title => "Title",
+            QueueSort::Artist => "Artist",
+            QueueSort::Rating => "Rating",
+            QueueSort::Plays => "Plays",
+            QueueSort::Skips => "Skips",
+            QueueSort::LastPlayed => "Last played",
+        }
+    }
+}
+
+/// Sorts queue entries by a column. `names` gives (title, artist) for a track
+/// path; numeric columns put the highest (or most recent) first. Original
+/// order is restored by the caller from its saved copy.
+pub fn sort_queue(files: &mut [String], directory: &str, sort: QueueSort, stats: &PlayStats, names: impl Fn(&str) -> (String, String)) {
+    let track_stats = |file: &String| stats.get(&stats_key(&crate::get_track_path(file, directory))).cloned().unwrap_or_default();
+    let track_names = |file: &String| {
+        let (title, artist) = names(&crate::get_track_path(file, directory));
+        (title.to_lowercase(), artist.to_lowercase())
+    };
+    match sort {
+        QueueSort::Original => {}
+        QueueSort::Title => files.sort_by_cached_key(|f| track_names(f).0),
+        QueueSort::Artist => files.sort_by_cached_key(|f| {
+            let (title, artist) = track_names(f);
+            (artist, title)
+        }),
+        // Favourites first within the same rating
+        QueueSort::Rating => files.sort_by_cached_key(|f| {
+            let stats = track_stats(f);
+            std::cmp::Reverse((stats.rating, stats.favourite))
+        }),
+        QueueSort::Plays => files.sort_by_cached_key(|f| std::cmp::Reverse(track_stats(f).play_count)),
+        QueueSort::Skips => files.sort_by_cached_key(|f| std::cmp::Reverse(track_stats(f).skip_count)),
+        QueueSort::LastPlayed => files.sort_by_cached_key(|f| std::cmp::Reverse(track_stats(f).last_played)),
+    }
+}
+
+/// Stars for the rating column, e.g. `★★★☆☆`; blank when unrated.
+pub fn format_rating(rating: Option<u8>) -> String {
+    match rating {
+        Some(stars) => (1..=5).map(|i| if i <= stars { '★' } else { '☆' }).collect(),
+        None => String::from("     "),
+    }
+}
+
+/// Fixed-width rating / plays / skips / last-played columns for a queue row.
+pub fn stats_columns(stats: Option<&TrackStats>, now: u64) -> String {
+    let stats = stats.cloned().unwrap_or_default();
+    let last = stats.last_played.map(|t| format_age(t, now)).unwrap_or_else(|| String::from("never"));
+    let heart = if stats.favourite { '♥' } else { ' ' };
+    format!(" {}{} {:>5} {:>5} {:>9}", format_rating(stats.rating), heart, stats.play_count, stats.skip_count, last)
+}
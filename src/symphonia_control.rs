@@ -1,21 +1,112 @@
+use crate::equalizer::EqSettings;
+use crate::metadata::StreamInfo;
+use crate::replaygain::ReplayGainSettings;
+use crate::silence::SilenceSettings;
+use crate::visualiser::SampleTap;
 use std::env;
-use std::sync::{Arc, Mutex, atomic::AtomicBool};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU32, AtomicU64}};
 use std::sync::atomic::Ordering;
 
+/// Audio settings that outlive a single track; every playback thread shares them.
+#[derive(Clone)]
+pub struct PlaybackSettings {
+    pub eq: Arc<Mutex<EqSettings>>,
+    pub replay_gain: ReplayGainSettings,
+    pub silence: SilenceSettings,
+    /// Playback speed; pitch is kept by time-stretching.
+    pub speed: Arc<Mutex<f32>>,
+    /// Output volume (f32 bits), read by the output stream; the sleep timer fades it.
+    pub volume: Arc<AtomicU32>,
+}
+
+impl PlaybackSettings {
+    pub fn new(eq: EqSettings, replay_gain: ReplayGainSettings) -> Self {
+        Self {
+            eq: Arc::new(Mutex::new(eq)),
+            replay_gain,
+            silence: SilenceSettings::default(),
+            speed: Arc::new(Mutex::new(1.0)),
+            volume: Arc::new(AtomicU32::new(1.0f32.to_bits())),
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.volume.store(volume.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn speed(&self) -> f32 {
+        *self.speed.lock().unwrap()
+    }
+
+    /// Changes the speed by `delta`, within the supported range. Returns the new speed.
+    pub fn adjust_speed(&self, delta: f32) -> f32 {
+        let mut speed = self.speed.lock().unwrap();
+        *speed = crate::timestretch::clamp_speed(*speed + delta);
+        *speed
+    }
+
+    pub fn reset_speed(&self) {
+        *self.speed.lock().unwrap() = 1.0;
+    }
+}
+
+impl Default for PlaybackSettings {
+    fn default() -> Self {
+        Self::new(EqSettings::default(), ReplayGainSettings::default())
+    }
+}
+
+/// Loop points in the playing track; the section loops once `b_ms` is set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AbLoop {
+    pub a_ms: u64,
+    pub b_ms: Option<u64>,
+}
+
+impl AbLoop {
+    /// Start and end of the section when both points are set.
+    pub fn section(&self) -> Option<(u64, u64)> {
+        self.b_ms.map(|b| (self.a_ms, b))
+    }
+}
+
 #[derive(Clone)]
 pub struct PlaybackControl {
     pub paused: Arc<AtomicBool>,
     pub stopped: Arc<AtomicBool>,
-    #[allow(dead_code)]
-    pub position_ms: Arc<Mutex<u64>>,
+    /// Position of the sample being played, updated by the output stream.
+    pub position_ms: Arc<AtomicU64>,
+    pub stream_info: Arc<Mutex<Option<StreamInfo>>>,
+    /// Recent output samples for the spectrum analyser.
+    pub tap: SampleTap,
+    /// Position (ms) the playback thread should jump to next.
+    pub seek_request: Arc<Mutex<Option<u64>>>,
+    pub settings: PlaybackSettings,
+    /// The track is played as part of an album, for ReplayGain's auto mode.
+    pub album_context: bool,
+    pub ab_loop: Arc<Mutex<Option<AbLoop>>>,
 }
 
 impl PlaybackControl {
     pub fn new() -> Self {
+        Self::with_settings(PlaybackSettings::default())
+    }
+
+    pub fn with_settings(settings: PlaybackSettings) -> Self {
         Self {
             paused: Arc::new(AtomicBool::new(false)),
             stopped: Arc::new(AtomicBool::new(false)),
-            position_ms: Arc::new(Mutex::new(0)),
+            position_ms: Arc::new(AtomicU64::new(0)),
+            stream_info: Arc::new(Mutex::new(None)),
+            tap: SampleTap::new(),
+            seek_request: Arc::new(Mutex::new(None)),
+            settings,
+            album_context: false,
+            ab_loop: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -50,15 +141,68 @@ impl PlaybackControl {
 
     #[allow(dead_code)]
     pub fn set_position(&self, ms: u64) {
-        *self.position_ms.lock().unwrap() = ms;
+        self.position_ms.store(ms, Ordering::SeqCst);
         if env::args().any(|arg| arg == "--debug") {
             println!("[Control] Set position={}", ms);
         }
     }
 
-    #[allow(dead_code)]
     pub fn get_position(&self) -> u64 {
-        *self.position_ms.lock().unwrap()
+        self.position_ms.load(Ordering::SeqCst)
+    }
+
+    /// Asks the playback thread to continue from `ms`. The reported position
+    /// moves at once so the UI does not jump back while the seek happens.
+    pub fn seek(&self, ms: u64) {
+        *self.seek_request.lock().unwrap() = Some(ms);
+        self.position_ms.store(ms, Ordering::SeqCst);
+        if env::args().any(|arg| arg == "--debug") {
+            println!("[Control] Seek to {}", ms);
+        }
+    }
+
+    /// Takes the pending seek, if any.
+    pub fn take_seek(&self) -> Option<u64> {
+        self.seek_request.lock().unwrap().take()
+    }
+
+    /// Marks loop point A, dropping any earlier loop.
+    pub fn set_loop_a(&self, ms: u64) {
+        *self.ab_loop.lock().unwrap() = Some(AbLoop { a_ms: ms, b_ms: None });
+        if env::args().any(|arg| arg == "--debug") {
+            println!("[Control] Loop A at {}", ms);
+        }
+    }
+
+    /// Marks loop point B, which must come after A. Returns whether the loop is now active.
+    pub fn set_loop_b(&self, ms: u64) -> bool {
+        let mut ab_loop = self.ab_loop.lock().unwrap();
+        match ab_loop.as_mut() {
+            Some(points) if points.b_ms.is_none() && ms > points.a_ms => {
+                points.b_ms = Some(ms);
+                if env::args().any(|arg| arg == "--debug") {
+                    println!("[Control] Loop B at {}", ms);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn clear_loop(&self) {
+        *self.ab_loop.lock().unwrap() = None;
+    }
+
+    pub fn get_loop(&self) -> Option<AbLoop> {
+        *self.ab_loop.lock().unwrap()
+    }
+
+    pub fn set_stream_info(&self, info: StreamInfo) {
+        *self.stream_info.lock().unwrap() = Some(info);
+    }
+
+    pub fn get_stream_info(&self) -> Option<StreamInfo> {
+        self.stream_info.lock().unwrap().clone()
     }
 }
 
@@ -1,16 +1,195 @@
+use crate::equalizer::Equalizer;
+use crate::metadata::StreamInfo;
+use crate::replaygain::{apply_gain, ReplayGain};
+use crate::silence::SilenceRemover;
+use crate::timestretch::{is_normal_speed, TimeStretch};
 use crate::symphonia_control::PlaybackControl;
+use crate::visualiser::{SampleTap, TAP_SIZE};
 use std::fs::File;
 use std::env;
-use rodio::{OutputStream, Sink};
-use rodio::buffer::SamplesBuffer;
+use rodio::{OutputStream, Sink, Source};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
 use symphonia::core::audio::SampleBuffer;
 use symphonia::core::codecs::DecoderOptions;
-use symphonia::core::formats::FormatOptions;
+use symphonia::core::formats::{FormatOptions, SeekMode, SeekTo};
 use symphonia::core::probe::Hint;
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
+use symphonia::core::units::{Time, TimeBase};
 use symphonia::default::{get_codecs, get_probe};
 
+/// Decoded chunks kept queued in the sink, about a second of MP3 frames.
+/// Decoding waits beyond that, so pausing is instant and the reported
+/// position follows what is actually heard.
+const MAX_QUEUED_CHUNKS: usize = 40;
+
+/// Mono frames collected before they are handed to the visualiser tap.
+const TAP_BATCH: usize = 256;
+
+/// A decoded chunk that publishes the playback position, and copies what is
+/// heard to the visualiser tap, as it is played.
+pub struct TrackedSource {
+    samples: std::vec::IntoIter<f32>,
+    channels: u16,
+    sample_rate: u32,
+    /// Frame (one sample per channel) of the track this chunk starts at.
+    start_frame: u64,
+    /// Track frames each output frame stands for: the playback speed.
+    speed: f64,
+    emitted: u64,
+    position_ms: Arc<AtomicU64>,
+    volume: Arc<AtomicU32>,
+    gain: f32,
+    tap: SampleTap,
+    frame_sum: f32,
+    mono: Vec<f32>,
+}
+
+impl TrackedSource {
+    pub fn new(samples: Vec<f32>, channels: u16, sample_rate: u32, start_frame: u64, ctrl: &PlaybackControl) -> Self {
+        Self {
+            samples: samples.into_iter(),
+            channels: channels.max(1),
+            sample_rate: sample_rate.max(1),
+            start_frame,
+            speed: 1.0,
+            emitted: 0,
+            position_ms: ctrl.position_ms.clone(),
+            volume: ctrl.settings.volume.clone(),
+            gain: ctrl.settings.volume(),
+            tap: ctrl.tap.clone(),
+            frame_sum: 0.0,
+            mono: Vec::with_capacity(TAP_BATCH),
+        }
+    }
+
+    /// Marks the chunk as time-stretched audio played at `speed`.
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed as f64;
+        self
+    }
+
+    fn feed_tap(&mut self, sample: f32) {
+        self.frame_sum += sample;
+        if (self.emitted + 1).is_multiple_of(self.channels as u64) {
+            self.mono.push(self.frame_sum / self.channels as f32);
+            self.frame_sum = 0.0;
+            let last = self.samples.len() == 0;
+            let delivered = (self.mono.len() >= TAP_BATCH || last) && self.tap.try_push(&self.mono, self.sample_rate);
+            // The UI may be reading the tap; keep the batch for the next try unless it piles up
+            if delivered || self.mono.len() > TAP_SIZE {
+                self.mono.clear();
+            }
+        }
+    }
+}
+
+impl Iterator for TrackedSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.samples.next()?;
+        // Every 256 frames is precise enough and keeps the atomic out of the hot path
+        if self.emitted.is_multiple_of(self.channels as u64 * 256) {
+            let frame = self.start_frame + ((self.emitted / self.channels as u64) as f64 * self.speed) as u64;
+            self.position_ms.store(frame * 1000 / self.sample_rate as u64, Ordering::Relaxed);
+            self.gain = f32::from_bits(self.volume.load(Ordering::Relaxed));
+        }
+        let sample = sample * self.gain;
+        self.feed_tap(sample);
+        self.emitted += 1;
+        Some(sample)
+    }
+}
+
+impl Source for TrackedSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.samples.len())
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Mirrors the pause flag onto the sink. Returns whether playback is paused.
+fn sync_pause(ctrl: &PlaybackControl, sink: &Sink, was_paused: &mut bool, debug_mode: bool) -> bool {
+    if ctrl.is_paused() {
+        if !*was_paused {
+            if debug_mode {
+                println!("[Symphonia] Paused at {:.1}s", ctrl.get_position() as f32 / 1000.0);
+            }
+            sink.pause();
+            *was_paused = true;
+        }
+        true
+    } else {
+        if *was_paused {
+            if debug_mode {
+                println!("[Symphonia] Resumed from {:.1}s", ctrl.get_position() as f32 / 1000.0);
+            }
+            sink.play();
+            *was_paused = false;
+        }
+        false
+    }
+}
+
+/// Queues the audio still held by the time stretcher, at normal speed, and drops the stretcher.
+fn flush_stretch(stretch: &mut Option<TimeStretch>, sink: &Sink, channels: u16, sample_rate: u32, ctrl: &PlaybackControl) {
+    if let Some(mut stretcher) = stretch.take() {
+        let (start, rest) = stretcher.flush();
+        if !rest.is_empty() {
+            sink.append(TrackedSource::new(rest, channels, sample_rate, start, ctrl));
+        }
+    }
+}
+
+/// Queues processed audio starting at track frame `start`, time-stretching it
+/// when playing at another speed.
+fn queue_audio(samples: Vec<f32>, start: u64, sink: &Sink, stretch: &mut Option<TimeStretch>, channels: u16, sample_rate: u32, ctrl: &PlaybackControl) {
+    let speed = ctrl.settings.speed();
+    if is_normal_speed(speed) {
+        flush_stretch(stretch, sink, channels, sample_rate, ctrl);
+        sink.append(TrackedSource::new(samples, channels, sample_rate, start, ctrl));
+        return;
+    }
+    // The stretcher needs contiguous input; skipped silence starts it afresh
+    if stretch.as_ref().is_some_and(|s| s.input_end() != start) {
+        flush_stretch(stretch, sink, channels, sample_rate, ctrl);
+    }
+    let stretcher = stretch.get_or_insert_with(|| {
+        let mut stretcher = TimeStretch::new(channels as usize, sample_rate);
+        stretcher.reset(start);
+        stretcher
+    });
+    let (start, stretched) = stretcher.process(&samples, speed);
+    if !stretched.is_empty() {
+        sink.append(TrackedSource::new(stretched, channels, sample_rate, start, ctrl).with_speed(speed));
+    }
+}
+
+/// Converts a timestamp in the track's time base to a frame number.
+fn ts_to_frame(ts: u64, time_base: Option<TimeBase>, sample_rate: u32) -> u64 {
+    match time_base {
+        Some(tb) => {
+            let time = tb.calc_time(ts);
+            time.seconds * sample_rate as u64 + (time.frac * sample_rate as f64) as u64
+        }
+        None => ts,
+    }
+}
+
 pub fn play_mp3_with_symphonia(
     filename: &str,
     ctrl: PlaybackControl,
@@ -20,17 +199,50 @@ pub fn play_mp3_with_symphonia(
     let mut hint = Hint::new();
     hint.with_extension("mp3");
 
-    let probed = get_probe().format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut probed = get_probe().format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+    // Tags may sit before the stream (ID3v2) or inside the container (Vorbis comments)
+    let mut revisions: Vec<_> = probed.metadata.get().and_then(|m| m.current().cloned()).into_iter().collect();
     let mut format = probed.format;
+    revisions.extend(format.metadata().current().cloned());
+    let replay_gain = ReplayGain::from_metadata(&revisions);
+    let gain = replay_gain.applied(&ctrl.settings.replay_gain, ctrl.album_context);
     let track = format.default_track().ok_or("No default track found")?;
+    let track_id = track.id;
+    let time_base = track.codec_params.time_base;
     let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
 
+    // Publish what we know about the stream before the first packet is decoded
+    let params = &track.codec_params;
+    let codec = get_codecs()
+        .get_codec(params.codec)
+        .map(|d| d.short_name.to_uppercase())
+        .unwrap_or_else(|| String::from("?"));
+    let duration_ms = match (params.n_frames, params.time_base) {
+        (Some(frames), Some(tb)) => {
+            let time = tb.calc_time(frames);
+            Some(time.seconds * 1000 + (time.frac * 1000.0) as u64)
+        }
+        (Some(frames), None) => params.sample_rate.map(|rate| frames * 1000 / rate as u64),
+        _ => None,
+    };
+    let file_bytes = std::fs::metadata(filename).map(|m| m.len()).unwrap_or(0);
+    let mut stream_info = StreamInfo {
+        codec,
+        bitrate_kbps: duration_ms.filter(|ms| *ms > 0).map(|ms| (file_bytes * 8 / ms) as u32),
+        sample_rate: params.sample_rate.unwrap_or(0),
+        channels: params.channels.map(|c| c.count() as u16).unwrap_or(0),
+        duration_ms,
+        replay_gain: gain,
+    };
+    ctrl.set_stream_info(stream_info.clone());
+
     // Set up rodio output
     let (_stream, stream_handle) = OutputStream::try_default()?;
     let debug_mode = env::args().any(|arg| arg == "--debug");
 
     if debug_mode {
         println!("[Symphonia] Starting playback of: {}", filename);
+        println!("[Symphonia] ReplayGain {:?}, applying {:?}", replay_gain, gain);
     }
 
     // Get format info before starting
@@ -39,21 +251,97 @@ pub fn play_mp3_with_symphonia(
     let mut first_packet = true;
 
     // Create sink and start immediately
-    let sink = Sink::try_new(&stream_handle)?;
+    let mut sink = Sink::try_new(&stream_handle)?;
     sink.play();
     
     let mut was_paused = false;
-    let mut sample_count = 0u64;
+    let mut frame_count = 0u64;
+    // After a seek, decoded audio before this frame is dropped
+    let mut skip_to_frame: Option<u64> = None;
+    let mut end_of_stream = false;
+    let mut equalizer = Equalizer::new(ctrl.settings.eq.clone());
+    let mut stretch: Option<TimeStretch> = None;
+    // Set when the A-B loop reached B: jump back to A without dropping queued audio
+    let mut loop_back: Option<u64> = None;
+    let silence_settings = ctrl.settings.silence;
+    let remove_silence = silence_settings.skip.enabled || silence_settings.trim.enabled;
+    let mut silence: Option<SilenceRemover> = None;
+    // Leading silence is only trimmed when playback starts at the beginning
+    let mut silence_at_start = true;
 
-    // Stream decode and play directly
-    while let Ok(packet) = format.next_packet() {
+    // Stream decode and play, keeping a short queue ahead of the output
+    loop {
         if ctrl.is_stopped() {
             if debug_mode {
-                println!("[Symphonia] Stopped at {:.1}s", sample_count as f32 / sample_rate as f32);
+                println!("[Symphonia] Stopped at {:.1}s", ctrl.get_position() as f32 / 1000.0);
             }
             sink.stop();
             break;
         }
+        // A seek asked for by the user takes over from a pending loop jump
+        let looped = loop_back.take().map(|ms| (ms, false));
+        if let Some((ms, user_seek)) = ctrl.take_seek().map(|ms| (ms, true)).or(looped) {
+            let to = SeekTo::Time { time: Time::from(ms as f64 / 1000.0), track_id: Some(track_id) };
+            match format.seek(SeekMode::Accurate, to) {
+                Ok(seeked) => {
+                    decoder.reset();
+                    if user_seek {
+                        // A fresh sink drops the audio queued from the old position
+                        sink = Sink::try_new(&stream_handle)?;
+                        if was_paused {
+                            sink.pause();
+                        }
+                        stretch = None;
+                        silence = None;
+                        silence_at_start = false;
+                    } else {
+                        flush_stretch(&mut stretch, &sink, channels, sample_rate, &ctrl);
+                    }
+                    frame_count = ms * sample_rate as u64 / 1000;
+                    skip_to_frame = Some(ts_to_frame(seeked.required_ts, time_base, sample_rate));
+                    end_of_stream = false;
+                    if debug_mode {
+                        println!("[Symphonia] Seeked to {:.1}s", ms as f32 / 1000.0);
+                    }
+                }
+                Err(e) => {
+                    if debug_mode {
+                        println!("[Symphonia] Seek to {:.1}s failed: {}", ms as f32 / 1000.0, e);
+                    }
+                }
+            }
+        }
+        if sync_pause(&ctrl, &sink, &mut was_paused, debug_mode) {
+            std::thread::sleep(Duration::from_millis(50));
+            continue;
+        }
+        // Let the queued audio play out; a seek can still restart decoding
+        if end_of_stream {
+            if sink.empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+            continue;
+        }
+        if sink.len() >= MAX_QUEUED_CHUNKS {
+            std::thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+        let Ok(packet) = format.next_packet() else {
+            for (start, samples) in silence.as_mut().map(|s| s.finish()).unwrap_or_default() {
+                queue_audio(samples, start, &sink, &mut stretch, channels, sample_rate, &ctrl);
+            }
+            if debug_mode && let Some(remover) = &silence {
+                println!("[Symphonia] Removed {:.1}s of silence", remover.dropped_frames() as f32 / sample_rate as f32);
+            }
+            silence = None;
+            flush_stretch(&mut stretch, &sink, channels, sample_rate, &ctrl);
+            end_of_stream = true;
+            continue;
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
 
         match decoder.decode(&packet) {
             Ok(decoded) => {
@@ -63,48 +351,66 @@ pub fn play_mp3_with_symphonia(
                     if debug_mode {
                         println!("[Symphonia] Decoded @ {}Hz, {} channels", sample_rate, channels);
                     }
+                    stream_info.sample_rate = sample_rate;
+                    stream_info.channels = channels;
+                    ctrl.set_stream_info(stream_info.clone());
                     first_packet = false;
                 }
 
                 let mut sample_buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
                 sample_buf.copy_interleaved_ref(decoded);
-                let samples = sample_buf.samples();
+                let mut samples = sample_buf.samples();
+
+                // Sample-accurate seek: drop whatever precedes the target
+                if let Some(target) = skip_to_frame {
+                    let start = ts_to_frame(packet.ts(), time_base, sample_rate);
+                    let frames = (samples.len() / channels as usize) as u64;
+                    if start + frames <= target {
+                        continue;
+                    }
+                    let skip = target.saturating_sub(start) as usize * channels as usize;
+                    samples = &samples[skip.min(samples.len())..];
+                    skip_to_frame = None;
+                }
                 
                 if samples.is_empty() {
                     continue;
                 }
 
-                // Handle pause/resume
-                if ctrl.is_paused() {
-                    if !was_paused {
-                        if debug_mode {
-                            println!("[Symphonia] Paused at {:.1}s", sample_count as f32 / sample_rate as f32);
-                        }
-                        sink.stop();
-                        was_paused = true;
+                let mut samples = samples.to_vec();
+                // Stop at loop point B and continue from A
+                if let Some((a, b)) = ctrl.get_loop().and_then(|l| l.section()) {
+                    let end = b * sample_rate as u64 / 1000;
+                    let frames = samples.len() as u64 / channels as u64;
+                    if frame_count + frames >= end {
+                        samples.truncate(end.saturating_sub(frame_count) as usize * channels as usize);
+                        loop_back = Some(a);
                     }
-                    std::thread::sleep(std::time::Duration::from_millis(100));
+                }
+                if samples.is_empty() {
                     continue;
-                } else if was_paused {
-                    if debug_mode {
-                        println!("[Symphonia] Resumed from {:.1}s", sample_count as f32 / sample_rate as f32);
-                    }
-                    was_paused = false;
                 }
-
-                // Append decoded chunk directly to sink
-                let source = SamplesBuffer::new(channels, sample_rate, samples.to_vec());
-                sink.append(source);
-                sample_count += samples.len() as u64 / channels as u64;
-                
-                // Small sleep to prevent busy waiting
-                std::thread::sleep(std::time::Duration::from_millis(10));
+                if let Some(gain) = gain {
+                    apply_gain(&mut samples, gain.factor);
+                }
+                equalizer.process(&mut samples, channels as usize, sample_rate);
+                let frames = samples.len() as u64 / channels as u64;
+                let segments = match silence.as_mut() {
+                    Some(remover) => remover.process(frame_count, samples),
+                    None if remove_silence => silence
+                        .insert(SilenceRemover::new(&silence_settings, channels as usize, sample_rate, silence_at_start))
+                        .process(frame_count, samples),
+                    None => vec![(frame_count, samples)],
+                };
+                for (start, samples) in segments {
+                    queue_audio(samples, start, &sink, &mut stretch, channels, sample_rate, &ctrl);
+                }
+                frame_count += frames;
             }
             Err(_) => continue,
         }
     }
 
-    sink.sleep_until_end();
     if debug_mode {
         println!("[Symphonia] Playback complete");
     }
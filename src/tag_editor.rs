@@ -0,0 +1,385 @@
+use crate::metadata::{format_number_pair, TrackTags};
+use crossterm::event;
+use id3::TagLike;
+use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders, List, ListItem, Paragraph, ListState, Wrap}, layout::{Layout, Constraint, Direction}, style::{Style, Modifier, Color}};
+use std::path::Path;
+use std::{fs, io, error::Error};
+
+/// Tag fields the editor can change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagField {
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    Track,
+    Disc,
+    Year,
+    Genre,
+    Comment,
+}
+
+impl TagField {
+    pub const ALL: [TagField; 9] = [
+        TagField::Title,
+        TagField::Artist,
+        TagField::Album,
+        TagField::AlbumArtist,
+        TagField::Track,
+        TagField::Disc,
+        TagField::Year,
+        TagField::Genre,
+        TagField::Comment,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TagField::Title => "Title",
+            TagField::Artist => "Artist",
+            TagField::Album => "Album",
+            TagField::AlbumArtist => "Album artist",
+            TagField::Track => "Track (n/total)",
+            TagField::Disc => "Disc (n/total)",
+            TagField::Year => "Year",
+            TagField::Genre => "Genre",
+            TagField::Comment => "Comment",
+        }
+    }
+
+    /// The field as the editor shows it; empty when unset.
+    pub fn current(self, tags: &TrackTags) -> String {
+        let text = |v: &Option<String>| v.clone().unwrap_or_default();
+        let pair = |n: Option<u32>, t: Option<u32>| if n.is_some() { format_number_pair(n, t) } else { String::new() };
+        match self {
+            TagField::Title => text(&tags.title),
+            TagField::Artist => text(&tags.artist),
+            TagField::Album => text(&tags.album),
+            TagField::AlbumArtist => text(&tags.album_artist),
+            TagField::Track => pair(tags.track, tags.total_tracks),
+            TagField::Disc => pair(tags.disc, tags.total_discs),
+            TagField::Year => tags.year.map(|y| y.to_string()).unwrap_or_default(),
+            TagField::Genre => text(&tags.genre),
+            TagField::Comment => text(&tags.comment),
+        }
+    }
+
+    /// Checks a new value before anything is written.
+    pub fn validate(self, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match self {
+            TagField::Track | TagField::Disc if !value.is_empty() && parse_number_pair(value).is_none() => {
+                Err(format!("{}: expected a number like 3 or 3/12", self.label()))
+            }
+            TagField::Year if !value.is_empty() && value.parse::<i32>().is_err() => {
+                Err(String::from("Year: expected a number"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses `3` or `3/12`.
+pub fn parse_number_pair(value: &str) -> Option<(u32, Option<u32>)> {
+    match value.split_once('/') {
+        Some((n, total)) => Some((n.trim().parse().ok()?, Some(total.trim().parse().ok()?))),
+        None => Some((value.trim().parse().ok()?, None)),
+    }
+}
+
+/// ID3v2 version to write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagVersion {
+    /// Whatever the file already has (ID3v2.4 for untagged files and v2.2 tags).
+    Keep,
+    V23,
+    V24,
+}
+
+impl TagVersion {
+    pub fn next(self) -> Self {
+        match self {
+            TagVersion::Keep => TagVersion::V23,
+            TagVersion::V23 => TagVersion::V24,
+            TagVersion::V24 => TagVersion::Keep,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TagVersion::Keep => "keep file's version",
+            TagVersion::V23 => "ID3v2.3",
+            TagVersion::V24 => "ID3v2.4",
+        }
+    }
+
+    fn resolve(self, existing: Option<id3::Version>) -> id3::Version {
+        match (self, existing) {
+            (TagVersion::V23, _) => id3::Version::Id3v23,
+            (TagVersion::Keep, Some(id3::Version::Id3v23)) => id3::Version::Id3v23,
+            _ => id3::Version::Id3v24,
+        }
+    }
+}
+
+/// Applies changed fields to a tag; an empty value removes the field.
+/// Frames the editor does not know about are left untouched.
+pub fn apply_changes(tag: &mut id3::Tag, changes: &[(TagField, String)], version: id3::Version) {
+    for (field, value) in changes {
+        let value = value.trim();
+        match field {
+            TagField::Title if value.is_empty() => tag.remove_title(),
+            TagField::Title => tag.set_title(value),
+            TagField::Artist if value.is_empty() => tag.remove_artist(),
+            TagField::Artist => tag.set_artist(value),
+            TagField::Album if value.is_empty() => tag.remove_album(),
+            TagField::Album => tag.set_album(value),
+            TagField::AlbumArtist if value.is_empty() => tag.remove_album_artist(),
+            TagField::AlbumArtist => tag.set_album_artist(value),
+            TagField::Genre if value.is_empty() => tag.remove_genre(),
+            TagField::Genre => tag.set_genre(value),
+            TagField::Track => {
+                tag.remove_track();
+                tag.remove_total_tracks();
+                if let Some((n, total)) = parse_number_pair(value) {
+                    tag.set_track(n);
+                    if let Some(total) = total {
+                        tag.set_total_tracks(total);
+                    }
+                }
+            }
+            TagField::Disc => {
+                tag.remove_disc();
+                tag.remove_total_discs();
+                if let Some((n, total)) = parse_number_pair(value) {
+                    tag.set_disc(n);
+                    if let Some(total) = total {
+                        tag.set_total_discs(total);
+                    }
+                }
+            }
+            TagField::Year => {
+                // v2.3 keeps the year in TYER, v2.4 in TDRC
+                tag.remove_year();
+                tag.remove_date_recorded();
+                if let Ok(year) = value.parse::<i32>() {
+                    if version == id3::Version::Id3v24 {
+                        tag.set_date_recorded(id3::Timestamp { year, month: None, day: None, hour: None, minute: None, second: None });
+                    } else {
+                        tag.set_year(year);
+                    }
+                }
+            }
+            TagField::Comment => {
+                tag.remove_comment(Some(""), None);
+                if !value.is_empty() {
+                    tag.add_frame(id3::frame::Comment {
+                        lang: String::from("eng"),
+                        description: String::new(),
+                        text: value.to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Writes changed fields to a file. The tag is written to a copy next to the
+/// file which then replaces it, so a failed write never leaves a half-written MP3.
+/// Where the file cannot be replaced (Windows, while it is playing) the tag is
+/// written in place instead.
+pub fn write_tags(file_path: &str, changes: &[(TagField, String)], version: TagVersion) -> Result<(), Box<dyn Error>> {
+    for (field, value) in changes {
+        field.validate(value)?;
+    }
+    let (mut tag, existing) = match id3::Tag::read_from_path(file_path) {
+        Ok(tag) => {
+            let existing = tag.version();
+            (tag, Some(existing))
+        }
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => (id3::Tag::new(), None),
+        // Refuse to overwrite a tag we could not parse
+        Err(e) => return Err(e.into()),
+    };
+    let version = version.resolve(existing);
+    apply_changes(&mut tag, changes, version);
+    write_tag_safely(file_path, &tag, version)
+}
+
+/// Writes `tag` through a temporary copy that replaces the file, falling back
+/// to an in-place write when the file cannot be replaced.
+pub fn write_tag_safely(file_path: &str, tag: &id3::Tag, version: id3::Version) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(file_path);
+    let file_name = path.file_name().ok_or("not a file")?.to_string_lossy().to_string();
+    let temp_path = path.with_file_name(format!(".{}.rdaio-tmp", file_name));
+    fs::copy(path, &temp_path)?;
+    if let Err(e) = tag.write_to_path(&temp_path, version) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    if fs::rename(&temp_path, path).is_err() {
+        let _ = fs::remove_file(&temp_path);
+        tag.write_to_path(path, version)?;
+    }
+    Ok(())
+}
+
+/// One row of the edit form.
+struct FormRow {
+    field: TagField,
+    /// Value shared by all selected files; `None` when they differ.
+    common: Option<String>,
+    value: String,
+    changed: bool,
+}
+
+/// Edit form for one or more tracks. Only fields the user touches are written,
+/// so a batch edit of the album leaves each file's title alone.
+/// Returns how many files were written.
+pub fn edit_tags(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    paths: &[String],
+    debug_mode: bool,
+) -> Result<usize, Box<dyn Error>> {
+    let current: Vec<TrackTags> = paths.iter().map(|p| TrackTags::read(p)).collect();
+    let mut rows: Vec<FormRow> = TagField::ALL.iter()
+        .map(|&field| {
+            let first = current.first().map(|t| field.current(t)).unwrap_or_default();
+            let common = current.iter().all(|t| field.current(t) == first).then_some(first);
+            FormRow { field, value: common.clone().unwrap_or_default(), common, changed: false }
+        })
+        .collect();
+    let mut list_state = ListState::default();
+    list_state.select(Some(0));
+    let mut version = TagVersion::Keep;
+    let mut auto_number = false;
+    let mut message = String::new();
+
+    loop {
+        let title = if paths.len() == 1 {
+            format!("Edit Tags - {}", Path::new(&paths[0]).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default())
+        } else {
+            format!("Edit Tags - {} files", paths.len())
+        };
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .margin(2)
+                .constraints([
+                    Constraint::Min(11),
+                    Constraint::Length(3),
+                    Constraint::Length(4),
+                ].as_ref())
+                .split(f.size());
+
+            let items: Vec<ListItem> = rows.iter()
+                .map(|row| {
+                    let shown = if row.field == TagField::Track && auto_number {
+                        format!("1..{} by queue order", paths.len())
+                    } else if row.changed || row.common.is_some() {
+                        row.value.clone()
+                    } else {
+                        String::from("<mixed - keep>")
+                    };
+                    let style = if row.changed || (row.field == TagField::Track && auto_number) {
+                        Style::default().fg(Color::Yellow)
+                    } else {
+                        Style::default()
+                    };
+                    ListItem::new(format!("{:<16} {}", row.field.label(), shown)).style(style)
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(title.clone()))
+                .highlight_style(Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))
+                .highlight_symbol(">> ");
+            f.render_stateful_widget(list, chunks[0], &mut list_state);
+
+            let status = Paragraph::new(message.clone())
+                .block(Block::default().borders(Borders::ALL).title(format!("Write as: {}", version.label())));
+            f.render_widget(status, chunks[1]);
+
+            let help = Paragraph::new("[Up/Down] Field  [Type] Edit  [Ctrl+U] Clear field  [Ctrl+N] Number tracks by queue order  [Ctrl+V] ID3 version  [Enter] Save  [ESC] Cancel")
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .wrap(Wrap { trim: true });
+            f.render_widget(help, chunks[2]);
+        })?;
+
+        if event::poll(std::time::Duration::from_millis(200))?
+            && let event::Event::Key(key) = event::read()?
+        {
+            if key.kind != event::KeyEventKind::Press {
+                continue;
+            }
+            let ctrl = key.modifiers.contains(event::KeyModifiers::CONTROL);
+            let selected = list_state.selected().unwrap_or(0);
+            match key.code {
+                event::KeyCode::Esc => {
+                    return Err("Cancelled".into());
+                },
+                event::KeyCode::Down | event::KeyCode::Tab => {
+                    list_state.select(Some((selected + 1) % rows.len()));
+                },
+                event::KeyCode::Up | event::KeyCode::BackTab => {
+                    list_state.select(Some((selected + rows.len() - 1) % rows.len()));
+                },
+                event::KeyCode::Char('n') if ctrl => {
+                    auto_number = !auto_number;
+                },
+                event::KeyCode::Char('v') if ctrl => {
+                    version = version.next();
+                },
+                event::KeyCode::Char('u') if ctrl => {
+                    rows[selected].value.clear();
+                    rows[selected].changed = true;
+                },
+                event::KeyCode::Backspace => {
+                    let row = &mut rows[selected];
+                    if !row.changed && row.common.is_none() {
+                        row.value.clear();
+                    }
+                    row.value.pop();
+                    row.changed = true;
+                },
+                event::KeyCode::Char(c) if !ctrl => {
+                    let row = &mut rows[selected];
+                    row.value.push(c);
+                    row.changed = true;
+                },
+                event::KeyCode::Enter => {
+                    let changes: Vec<(TagField, String)> = rows.iter()
+                        .filter(|row| row.changed && !(row.field == TagField::Track && auto_number))
+                        .map(|row| (row.field, row.value.clone()))
+                        .collect();
+                    if let Some(error) = changes.iter().find_map(|(field, value)| field.validate(value).err()) {
+                        message = error;
+                        continue;
+                    }
+                    if changes.is_empty() && !auto_number {
+                        return Ok(0);
+                    }
+                    let mut written = 0;
+                    let mut failures = Vec::new();
+                    for (idx, path) in paths.iter().enumerate() {
+                        let mut file_changes = changes.clone();
+                        if auto_number {
+                            file_changes.push((TagField::Track, format!("{}/{}", idx + 1, paths.len())));
+                        }
+                        match write_tags(path, &file_changes, version) {
+                            Ok(()) => written += 1,
+                            Err(e) => failures.push(format!("{}: {}", path, e)),
+                        }
+                    }
+                    if debug_mode {
+                        println!("[DEBUG] Tag editor wrote {} files, {} failed", written, failures.len());
+                    }
+                    if failures.is_empty() {
+                        return Ok(written);
+                    }
+                    message = format!("{} written, {} failed: {}", written, failures.len(), failures[0]);
+                },
+                _ => {}
+            }
+        }
+    }
+}
@@ -1,9 +1,14 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use std::fs;
     use std::io::Write;
+    use std::sync::Mutex;
     use tempfile::TempDir;
 
+    // Tests touching the shared .rdaio_queue file must not run concurrently
+    static QUEUE_FILE_LOCK: Mutex<()> = Mutex::new(());
+
     // Helper function to create a test directory with MP3 files
     fn create_test_dir_with_mp3s() -> (TempDir, Vec<String>) {
         let temp_dir = TempDir::new().expect("Failed to create temp dir");
@@ -87,6 +92,7 @@ mod tests {
     // Tests for utility functions
     #[test]
     fn test_save_and_load_queue() {
+        let _guard = QUEUE_FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
         // Clean up any existing queue file
         let _ = fs::remove_file(".rdaio_queue");
         
@@ -118,6 +124,7 @@ mod tests {
 
     #[test]
     fn test_load_queue_empty() {
+        let _guard = QUEUE_FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
         // Clean up any existing queue file
         let _ = fs::remove_file(".rdaio_queue");
         
@@ -127,6 +134,7 @@ mod tests {
 
     #[test]
     fn test_save_queue_empty_list() {
+        let _guard = QUEUE_FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
         let _ = fs::remove_file(".rdaio_queue");
         
         let files: Vec<String> = vec![];
@@ -346,6 +354,7 @@ mod tests {
 
     #[test]
     fn test_save_queue_multiline_filenames() {
+        let _guard = QUEUE_FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
         let _ = fs::remove_file(".rdaio_queue");
         
         let files = vec![
@@ -377,4 +386,1884 @@ mod tests {
         let display = crate::get_display_name(file_name, directory, false);
         assert_eq!(display, file_name);
     }
+
+    // Tests for folder browser selection
+    #[test]
+    fn test_get_track_path_joins_directory() {
+        let path = crate::get_track_path("song.mp3", "music");
+        assert_eq!(std::path::PathBuf::from(path), std::path::PathBuf::from("music").join("song.mp3"));
+        assert_eq!(crate::get_track_path("song.mp3", "."), "song.mp3");
+    }
+
+    #[test]
+    fn test_get_track_path_keeps_absolute_entries() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let absolute = temp_dir.path().join("song.mp3").to_string_lossy().to_string();
+
+        assert_eq!(crate::get_track_path(&absolute, "./elsewhere"), absolute);
+    }
+
+    #[test]
+    fn test_get_display_name_strips_directories() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let absolute = temp_dir.path().join("picked.mp3").to_string_lossy().to_string();
+
+        assert_eq!(crate::get_display_name(&absolute, ".", false), "picked.mp3");
+        assert_eq!(crate::get_display_name(&absolute, ".", true), "picked.mp3");
+    }
+
+    #[test]
+    fn test_collect_selection_expands_directories() {
+        let (temp_dir, _) = create_test_dir_with_mp3s();
+        let other_dir = TempDir::new().expect("Failed to create temp dir");
+        let single = other_dir.path().join("single.mp3");
+        fs::write(&single, b"dummy content").expect("Failed to write test file");
+
+        let tracks = crate::collect_selection(&[single.clone(), temp_dir.path().to_path_buf()]);
+
+        assert_eq!(tracks.len(), 4);
+        assert_eq!(tracks[0], single.to_string_lossy());
+        assert!(tracks[1].ends_with("song1.mp3"));
+        assert!(tracks[3].ends_with("song3.mp3"));
+        assert!(tracks.iter().all(|t| std::path::Path::new(t).is_absolute()));
+    }
+
+    #[test]
+    fn test_collect_selection_empty() {
+        assert!(crate::collect_selection(&[]).is_empty());
+    }
+
+    // Tests for track metadata
+    #[test]
+    fn test_track_tags_read_full_tag() {
+        use id3::TagLike;
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let path = temp_dir.path().join("tagged.mp3");
+        fs::write(&path, b"dummy content").expect("Failed to write test file");
+
+        let mut tag = id3::Tag::new();
+        tag.set_title("Heroes");
+        tag.set_artist("David Bowie");
+        tag.set_album("Heroes");
+        tag.set_genre("Rock");
+        tag.set_year(1977);
+        tag.set_track(3);
+        tag.set_total_tracks(10);
+        tag.set_disc(1);
+        tag.write_to_path(&path, id3::Version::Id3v24).expect("Failed to write tag");
+
+        let tags = crate::metadata::TrackTags::read(path.to_string_lossy().as_ref());
+        assert_eq!(tags.title.as_deref(), Some("Heroes"));
+        assert_eq!(tags.artist.as_deref(), Some("David Bowie"));
+        assert_eq!(tags.genre.as_deref(), Some("Rock"));
+        assert_eq!(tags.year, Some(1977));
+        assert_eq!(tags.track, Some(3));
+        assert_eq!(tags.total_tracks, Some(10));
+        assert_eq!(tags.disc, Some(1));
+    }
+
+    #[test]
+    fn test_track_tags_read_untagged_file() {
+        let (temp_dir, _) = create_test_dir_with_mp3s();
+        let path = temp_dir.path().join("song1.mp3");
+
+        let tags = crate::metadata::TrackTags::read(path.to_string_lossy().as_ref());
+        assert_eq!(tags, crate::metadata::TrackTags::default());
+    }
+
+    #[test]
+    fn test_format_number_pair() {
+        assert_eq!(crate::metadata::format_number_pair(Some(3), Some(12)), "3/12");
+        assert_eq!(crate::metadata::format_number_pair(Some(3), None), "3");
+        assert_eq!(crate::metadata::format_number_pair(None, Some(12)), "-");
+    }
+
+    #[test]
+    fn test_now_playing_lines_fallback_title() {
+        let tags = crate::metadata::TrackTags::default();
+        let info = crate::metadata::StreamInfo {
+            codec: "MP3".to_string(),
+            bitrate_kbps: Some(320),
+            sample_rate: 44100,
+            channels: 2,
+            duration_ms: Some(185_000),
+            replay_gain: None,
+        };
+
+        let lines = crate::metadata::now_playing_lines(&tags, Some(&info), "track01.mp3");
+        assert!(lines[0].ends_with("track01.mp3"));
+        assert!(lines.iter().any(|l| l.contains("320 kbps")));
+        assert!(lines.iter().any(|l| l.contains("44100 Hz, 2 ch")));
+        assert!(lines.iter().any(|l| l.ends_with("3:05")));
+    }
+
+    // Tests for the metadata cache
+    #[test]
+    fn test_track_tags_fields_roundtrip() {
+        let tags = crate::metadata::TrackTags {
+            title: Some("Tab\there".to_string()),
+            artist: Some("Line\nbreak \\ slash".to_string()),
+            year: Some(1999),
+            track: Some(7),
+            ..Default::default()
+        };
+
+        let fields = tags.to_fields();
+        assert!(fields.iter().all(|f| !f.contains('\t') && !f.contains('\n')));
+        let refs: Vec<&str> = fields.iter().map(|f| f.as_str()).collect();
+        assert_eq!(crate::metadata::TrackTags::from_fields(&refs), Some(tags));
+        assert_eq!(crate::metadata::TrackTags::from_fields(&refs[1..]), None);
+    }
+
+    #[test]
+    fn test_disk_cache_roundtrip() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let cache_file = temp_dir.path().join("cache").to_string_lossy().to_string();
+        let mut entries = std::collections::HashMap::new();
+        entries.insert("music/a.mp3".to_string(), crate::metadata_cache::CachedTags {
+            mtime: 1_700_000_000,
+            size: 4096,
+            tags: crate::metadata::TrackTags { title: Some("A".to_string()), ..Default::default() },
+        });
+
+        crate::metadata_cache::save_disk_cache(&cache_file, &entries).expect("Failed to save cache");
+        assert_eq!(crate::metadata_cache::load_disk_cache(&cache_file), entries);
+    }
+
+    #[test]
+    fn test_metadata_cache_loads_in_background() {
+        let (temp_dir, _) = create_test_dir_with_mp3s();
+        let file = temp_dir.path().join("song1.mp3").to_string_lossy().to_string();
+        let cache = crate::metadata_cache::MetadataCache::new(None);
+
+        // First lookup only schedules the read
+        let mut tags = cache.get(&file);
+        for _ in 0..100 {
+            if tags.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            tags = cache.get(&file);
+        }
+        assert_eq!(tags, Some(crate::metadata::TrackTags::default()));
+        assert!(!cache.is_loading());
+        assert_eq!(cache.display_name(&file, ".", true), "song1.mp3");
+    }
+
+    #[test]
+    fn test_config_parse() {
+        let config = crate::config::Config::parse("# comment\nmetadata_cache = off\nunknown = 1\n");
+        assert!(!config.metadata_cache);
+        assert_eq!(crate::config::Config::parse(""), crate::config::Config::default());
+    }
+
+    // Tests for the music library
+    fn library_track(path: &str, artist: &str, album: &str, track: u32, genre: &str, year: i32) -> crate::library::LibraryTrack {
+        crate::library::LibraryTrack {
+            path: path.to_string(),
+            mtime: 1,
+            size: 2,
+            tags: crate::metadata::TrackTags {
+                title: Some(format!("Title {}", track)),
+                artist: Some(artist.to_string()),
+                album: Some(album.to_string()),
+                genre: Some(genre.to_string()),
+                year: Some(year),
+                track: Some(track),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn sample_library() -> crate::library::Library {
+        let mut library = crate::library::Library::default();
+        library.replace_tracks(vec![
+            library_track("/m/b2.mp3", "Bowie", "Low", 2, "Rock", 1977),
+            library_track("/m/b1.mp3", "Bowie", "Low", 1, "Rock", 1977),
+            library_track("/m/e1.mp3", "Eno", "Apollo", 1, "Ambient", 1983),
+            library_track("/m/x.mp3", "Bowie", "Heroes", 1, "Rock", 1977),
+        ]);
+        library
+    }
+
+    #[test]
+    fn test_library_groups_by_mode() {
+        use crate::library::BrowseMode;
+        let library = sample_library();
+
+        assert_eq!(library.categories(BrowseMode::Artist), vec!["Bowie", "Eno"]);
+        assert_eq!(library.categories(BrowseMode::Genre), vec!["Ambient", "Rock"]);
+        assert_eq!(library.categories(BrowseMode::Year), vec!["1983", "1977"]);
+
+        let albums = library.albums(BrowseMode::Artist, "Bowie");
+        let names: Vec<&str> = albums.iter().map(|a| a.album.as_str()).collect();
+        assert_eq!(names, vec!["Heroes", "Low"]);
+    }
+
+    #[test]
+    fn test_library_album_tracks_in_track_order() {
+        use crate::library::{AlbumKey, BrowseMode};
+        let library = sample_library();
+        let low = AlbumKey { artist: "Bowie".to_string(), album: "Low".to_string() };
+
+        let tracks = library.album_tracks(BrowseMode::Genre, "Rock", &low);
+        let paths: Vec<&str> = tracks.iter().map(|t| t.path.as_str()).collect();
+        assert_eq!(paths, vec!["/m/b1.mp3", "/m/b2.mp3"]);
+    }
+
+    #[test]
+    fn test_library_save_and_load() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file = temp_dir.path().join("library").to_string_lossy().to_string();
+        let library = sample_library();
+
+        library.save_to(&file).expect("Failed to save library");
+        let loaded = crate::library::Library::load_from(&file);
+        assert_eq!(loaded.tracks, library.tracks);
+    }
+
+    #[test]
+    fn test_library_scan_is_recursive() {
+        let (temp_dir, _) = create_test_dir_with_mp3s();
+        let nested = temp_dir.path().join("disc2");
+        fs::create_dir(&nested).expect("Failed to create nested dir");
+        fs::write(nested.join("deep.mp3"), b"dummy content").expect("Failed to write test file");
+
+        let scanned = std::sync::atomic::AtomicUsize::new(0);
+        let reread = std::sync::atomic::AtomicUsize::new(0);
+        let root = temp_dir.path().to_string_lossy().to_string();
+        let tracks = crate::library::scan_roots(&[root], &[], &scanned, &reread);
+
+        assert_eq!(tracks.len(), 4);
+        assert_eq!(scanned.load(std::sync::atomic::Ordering::Relaxed), 4);
+        assert!(tracks.iter().any(|t| t.path.ends_with("deep.mp3")));
+        assert!(tracks.iter().all(|t| t.artist_name() == crate::library::UNKNOWN_ARTIST));
+    }
+
+    #[test]
+    fn test_config_library_roots() {
+        let config = crate::config::Config::parse("library_root = /music\nlibrary_root = /more\n");
+        assert_eq!(config.library_roots, vec!["/music", "/more"]);
+        assert_eq!(crate::config::Config::default().library_roots_or_default(), vec!["."]);
+    }
+
+    // Tests for incremental rescans and filesystem watching
+    #[test]
+    fn test_library_rescan_only_rereads_changed_files() {
+        let (temp_dir, _) = create_test_dir_with_mp3s();
+        let root = temp_dir.path().to_string_lossy().to_string();
+        let scanned = std::sync::atomic::AtomicUsize::new(0);
+        let reread = std::sync::atomic::AtomicUsize::new(0);
+        let mut previous = crate::library::scan_roots(std::slice::from_ref(&root), &[], &scanned, &reread);
+        assert_eq!(reread.load(std::sync::atomic::Ordering::Relaxed), 3);
+
+        // Pretend song1 was tagged earlier; unchanged stamp means the old tags are kept
+        previous[0].tags.title = Some("Kept".to_string());
+        // A deleted file must drop out of the library
+        previous.push(library_track("/gone/away.mp3", "X", "Y", 1, "Z", 2000));
+        fs::remove_file(temp_dir.path().join("song3.mp3")).expect("Failed to remove test file");
+
+        let reread = std::sync::atomic::AtomicUsize::new(0);
+        let tracks = crate::library::scan_roots(&[root], &previous, &scanned, &reread);
+        assert_eq!(reread.load(std::sync::atomic::Ordering::Relaxed), 0);
+        assert_eq!(tracks.len(), 2);
+        assert_eq!(tracks[0].tags.title.as_deref(), Some("Kept"));
+        assert!(!tracks.iter().any(|t| t.path.contains("gone")));
+    }
+
+    #[test]
+    fn test_library_upsert_and_remove() {
+        let (temp_dir, _) = create_test_dir_with_mp3s();
+        let mut library = crate::library::Library::default();
+        let song = temp_dir.path().join("song2.mp3").to_string_lossy().to_string();
+
+        assert!(library.upsert_file(&song));
+        assert!(!library.upsert_file(&song), "Unchanged stamp should not re-read");
+        assert!(!library.upsert_file("/nonexistent/file.mp3"));
+        assert_eq!(library.tracks.len(), 1);
+
+        let dir = temp_dir.path().to_string_lossy().to_string();
+        assert_eq!(library.remove_path(&dir), 1);
+        assert!(library.tracks.is_empty());
+    }
+
+    #[test]
+    fn test_watcher_translates_events() {
+        use notify::event::{CreateKind, RemoveKind};
+        use crate::watcher::{changes_for_event, FsChange};
+        let (temp_dir, _) = create_test_dir_with_mp3s();
+        let mp3 = temp_dir.path().join("song1.mp3");
+        let txt = temp_dir.path().join("not_mp3.txt");
+
+        let created = notify::Event::new(notify::EventKind::Create(CreateKind::File)).add_path(mp3.clone()).add_path(txt);
+        assert_eq!(changes_for_event(&created), vec![FsChange::Added(mp3.to_string_lossy().to_string())]);
+
+        let gone = temp_dir.path().join("old.mp3");
+        let removed = notify::Event::new(notify::EventKind::Remove(RemoveKind::File)).add_path(gone.clone());
+        assert_eq!(changes_for_event(&removed), vec![FsChange::Removed(gone.to_string_lossy().to_string())]);
+    }
+
+    #[test]
+    fn test_watcher_folder_inside_root() {
+        use crate::watcher::is_under_roots;
+        use std::path::{Path, PathBuf};
+        let roots = vec![PathBuf::from("/music"), PathBuf::from("/podcasts/new")];
+        assert!(is_under_roots(&roots, Path::new("/music")));
+        assert!(is_under_roots(&roots, Path::new("/music/Artist/Album")));
+        assert!(!is_under_roots(&roots, Path::new("/musicals")));
+        assert!(!is_under_roots(&roots, Path::new("/podcasts")));
+    }
+
+    #[test]
+    fn test_watcher_library_ignores_folder_outside_roots() {
+        use crate::library::Library;
+        use crate::watcher::{apply_to_library, FsChange};
+        let root = TempDir::new().expect("Failed to create temp dir");
+        let folder = TempDir::new().expect("Failed to create temp dir");
+        let roots = vec![root.path().to_path_buf()];
+        let inside = root.path().join("in.mp3").to_string_lossy().to_string();
+        let outside = folder.path().join("out.mp3").to_string_lossy().to_string();
+        fs::write(&inside, b"audio").unwrap();
+        fs::write(&outside, b"audio").unwrap();
+
+        let mut library = Library::default();
+        apply_to_library(&FsChange::Added(inside.clone()), &roots, &mut library);
+        apply_to_library(&FsChange::Added(outside.clone()), &roots, &mut library);
+        let paths: Vec<&str> = library.tracks.iter().map(|t| t.path.as_str()).collect();
+        assert_eq!(paths, vec![inside.as_str()]);
+
+        // A library track that happens to match an out-of-root removal stays
+        library.upsert_file(&outside);
+        apply_to_library(&FsChange::Removed(outside.clone()), &roots, &mut library);
+        apply_to_library(&FsChange::Removed(inside.clone()), &roots, &mut library);
+        let paths: Vec<&str> = library.tracks.iter().map(|t| t.path.as_str()).collect();
+        assert_eq!(paths, vec![outside.as_str()]);
+    }
+
+    #[test]
+    fn test_apply_fs_changes_to_queue() {
+        use crate::watcher::{apply_to_queue, FsChange, QueueChange};
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let dir = temp_dir.path().to_string_lossy().to_string();
+        let mut files = vec!["a.mp3".to_string(), "b.mp3".to_string()];
+
+        let new_file = temp_dir.path().join("c.mp3").to_string_lossy().to_string();
+        assert_eq!(apply_to_queue(&mut files, &dir, &FsChange::Added(new_file.clone())), QueueChange::Added);
+        assert_eq!(apply_to_queue(&mut files, &dir, &FsChange::Added(new_file)), QueueChange::Unchanged);
+        assert_eq!(files, vec!["a.mp3", "b.mp3", "c.mp3"]);
+
+        let elsewhere = "/somewhere/else/d.mp3".to_string();
+        assert_eq!(apply_to_queue(&mut files, &dir, &FsChange::Added(elsewhere)), QueueChange::Unchanged);
+
+        let removed = temp_dir.path().join("a.mp3").to_string_lossy().to_string();
+        assert_eq!(apply_to_queue(&mut files, &dir, &FsChange::Removed(removed)), QueueChange::Removed(0));
+        assert_eq!(files, vec!["b.mp3", "c.mp3"]);
+    }
+
+    // Tests for library search
+    #[test]
+    fn test_parse_query_fields_and_ranges() {
+        use crate::search::{parse_query, SearchField, Term};
+        let terms = parse_query("artist:Bowie year:1977..1980 \"sound and vision\" foo:bar");
+        assert_eq!(terms, vec![
+            Term::Field(SearchField::Artist, "bowie".to_string()),
+            Term::Year(Some(1977), Some(1980)),
+            Term::Any("sound and vision".to_string()),
+            Term::Any("foo:bar".to_string()),
+        ]);
+        assert_eq!(parse_query("year:..1980"), vec![Term::Year(None, Some(1980))]);
+        assert_eq!(parse_query("year:1983"), vec![Term::Year(Some(1983), Some(1983))]);
+    }
+
+    #[test]
+    fn test_search_filters_by_field_and_year() {
+        let library = sample_library();
+        let hits = crate::search::search(&library, "artist:bowie year:1977..1980", 10);
+        assert_eq!(hits.len(), 3);
+        assert!(hits.iter().all(|&i| library.tracks[i].tags.artist.as_deref() == Some("Bowie")));
+
+        assert!(crate::search::search(&library, "artist:bowie year:1983", 10).is_empty());
+        assert!(crate::search::search(&library, "", 10).is_empty());
+    }
+
+    #[test]
+    fn test_search_ranks_title_matches_first() {
+        let mut library = sample_library();
+        library.tracks[0].tags.title = Some("Apollo".to_string());
+        // "apollo" is the title of one track and the album of another
+        let hits = crate::search::search(&library, "apollo", 10);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(library.tracks[hits[0]].tags.title.as_deref(), Some("Apollo"));
+    }
+
+    #[test]
+    fn test_search_matches_path() {
+        let library = sample_library();
+        let hits = crate::search::search(&library, "path:e1.mp3", 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(library.tracks[hits[0]].path, "/m/e1.mp3");
+    }
+
+    // Tests for smart playlists
+
+    #[test]
+    fn test_parse_smart_playlist_rule() {
+        use crate::smart_playlist::{parse_rule, Condition, Op, PlaylistOrder, RuleField};
+        let playlist = parse_rule("Jazz", "genre is Free Jazz and year >= 1960 or artist contains miles limit 50 order random").unwrap();
+        assert_eq!(playlist.any_of, vec![
+            vec![
+                Condition { field: RuleField::Genre, op: Op::Is, value: "Free Jazz".to_string() },
+                Condition { field: RuleField::Year, op: Op::GreaterOrEqual, value: "1960".to_string() },
+            ],
+            vec![Condition { field: RuleField::Artist, op: Op::Contains, value: "miles".to_string() }],
+        ]);
+        assert_eq!(playlist.limit, Some(50));
+        assert_eq!(playlist.order, PlaylistOrder::Random);
+
+        let sorted = parse_rule("Old", "genre is not Rock order year desc").unwrap();
+        assert_eq!(sorted.any_of[0][0].op, Op::IsNot);
+        assert_eq!(sorted.order, PlaylistOrder::Field(RuleField::Year, true));
+
+        assert!(parse_rule("Bad", "mood is happy").is_err());
+        assert!(parse_rule("Bad", "genre is").is_err());
+        assert!(parse_rule("Bad", "genre Jazz").is_err());
+        assert!(parse_rule("Bad", "limit x").is_err());
+    }
+
+    #[test]
+    fn test_smart_playlist_quoted_values_and_names() {
+        use crate::smart_playlist::{parse_rule, Condition, Op, RuleField};
+        let playlist = parse_rule("Duo", "artist is \"Simon and Garfunkel\" or album contains \"limit\", limit 5").unwrap();
+        assert_eq!(playlist.any_of, vec![
+            vec![Condition { field: RuleField::Artist, op: Op::Is, value: "Simon and Garfunkel".to_string() }],
+            vec![Condition { field: RuleField::Album, op: Op::Contains, value: "limit".to_string() }],
+        ]);
+        assert_eq!(playlist.limit, Some(5));
+        assert!(parse_rule("Duo", "artist is \"Simon and").is_err());
+        assert!(parse_rule("A = B", "genre is Rock").is_err());
+    }
+
+    #[test]
+    fn test_smart_playlist_evaluate() {
+        let library = sample_library();
+        let stats = crate::stats::PlayStats::default();
+        let ctx = crate::smart_playlist::RuleContext { stats: &stats, now: 0 };
+        let playlist = crate::smart_playlist::parse_rule("Bowie", "artist is bowie and album is not Heroes").unwrap();
+        assert_eq!(playlist.evaluate(&library, &ctx), vec!["/m/b1.mp3".to_string(), "/m/b2.mp3".to_string()]);
+
+        let newest = crate::smart_playlist::parse_rule("Newest", "year > 1900 order year desc limit 1").unwrap();
+        assert_eq!(newest.evaluate(&library, &ctx), vec!["/m/e1.mp3".to_string()]);
+
+        let either = crate::smart_playlist::parse_rule("Either", "genre is Ambient or album contains her").unwrap();
+        assert_eq!(either.evaluate(&library, &ctx).len(), 2);
+
+        let random = crate::smart_playlist::parse_rule("All", "order random limit 3").unwrap();
+        assert_eq!(random.evaluate(&library, &ctx).len(), 3);
+    }
+
+    #[test]
+    fn test_smart_playlists_roundtrip() {
+        use crate::smart_playlist::{load_playlists, parse_rule, save_playlists};
+        let dir = TempDir::new().expect("Failed to create temp dir");
+        let path = dir.path().join("playlists");
+        let path = path.to_str().unwrap();
+        let playlists = vec![
+            parse_rule("Rock", "genre is Rock limit 10").unwrap(),
+            parse_rule("Eighties", "year >= 1980 and year < 1990 order random").unwrap(),
+        ];
+        save_playlists(path, &playlists).unwrap();
+        assert_eq!(load_playlists(path), playlists);
+    }
+
+    // Tests for play statistics
+
+    #[test]
+    fn test_classify_listen() {
+        use crate::stats::{classify_listen, ListenEnd, ListenOutcome};
+        // 200 s track, 50% threshold
+        assert_eq!(classify_listen(30_000, Some(200_000), ListenEnd::Skipped, 50), ListenOutcome::Skipped);
+        assert_eq!(classify_listen(120_000, Some(200_000), ListenEnd::Skipped, 50), ListenOutcome::Played);
+        assert_eq!(classify_listen(30_000, Some(200_000), ListenEnd::Stopped, 50), ListenOutcome::Ignored);
+        assert_eq!(classify_listen(150_000, Some(200_000), ListenEnd::Stopped, 50), ListenOutcome::Played);
+        assert_eq!(classify_listen(199_000, Some(200_000), ListenEnd::Finished, 50), ListenOutcome::Played);
+        assert_eq!(classify_listen(10, None, ListenEnd::Finished, 50), ListenOutcome::Ignored);
+        assert_eq!(classify_listen(5_000, None, ListenEnd::Skipped, 50), ListenOutcome::Skipped);
+    }
+
+    #[test]
+    fn test_listen_session_counts_track_time() {
+        use crate::stats::ListenSession;
+        use std::time::Duration;
+        let mut session = ListenSession::start("song.mp3", 2.0);
+        std::thread::sleep(Duration::from_millis(100));
+        session.pause();
+        let heard = session.listened_ms();
+        assert!((200..400).contains(&heard), "heard {}ms", heard);
+        // Nothing is heard while paused, whatever the speed
+        session.set_speed(0.5);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(session.listened_ms(), heard);
+        session.resume();
+        std::thread::sleep(Duration::from_millis(100));
+        let slow = session.listened_ms() - heard;
+        assert!((50..150).contains(&slow), "heard {}ms at half speed", slow);
+    }
+
+    #[test]
+    fn test_play_stats_record_and_roundtrip() {
+        use crate::stats::{ListenEnd, PlayStats};
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file = temp_dir.path().join("stats");
+        let file = file.to_str().unwrap();
+
+        let mut stats = PlayStats::default();
+        stats.record("/m/a\tb.mp3", 200_000, Some(200_000), ListenEnd::Finished, 50, 1000);
+        stats.record("/m/a\tb.mp3", 5_000, Some(200_000), ListenEnd::Skipped, 50, 2000);
+        stats.record("/m/c.mp3", 5_000, Some(200_000), ListenEnd::Stopped, 50, 3000);
+        let entry = stats.get("/m/a\tb.mp3").unwrap();
+        assert_eq!((entry.play_count, entry.skip_count, entry.last_played), (1, 1, Some(1000)));
+        assert!(stats.get("/m/c.mp3").is_none());
+
+        stats.save_to(file).unwrap();
+        assert_eq!(PlayStats::load_from(file).tracks, stats.tracks);
+    }
+
+    #[test]
+    fn test_play_stats_follow_renamed_file() {
+        use crate::stats::{audio_hash, AudioHashCache, ListenEnd, PlayStats};
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let old_path = temp_dir.path().join("old.mp3");
+        let new_path = temp_dir.path().join("new.mp3");
+        std::fs::write(&old_path, b"ID3\x04\x00\x00\x00\x00\x00\x02abaudio-frames").unwrap();
+        let old_path = old_path.to_str().unwrap().to_string();
+        let new_path = new_path.to_str().unwrap().to_string();
+        let hashes = AudioHashCache::new();
+        let wait_for_hashes = |stats: &mut PlayStats| {
+            for _ in 0..500 {
+                if stats.apply_audio_hashes(&hashes) {
+                    return;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            panic!("audio hash never arrived");
+        };
+
+        let mut stats = PlayStats::default();
+        stats.use_audio_hash = true;
+        stats.record(&old_path, 60_000, Some(60_000), ListenEnd::Finished, 50, 1000);
+        // Hashing happens in the background, not while recording
+        assert_eq!(stats.get(&old_path).unwrap().audio_hash, None);
+        wait_for_hashes(&mut stats);
+        std::fs::rename(&old_path, &new_path).unwrap();
+        // Retagging does not change the audio hash
+        std::fs::write(&new_path, b"ID3\x04\x00\x00\x00\x00\x00\x03xyzaudio-frames").unwrap();
+        assert_eq!(audio_hash(&new_path), stats.get(&old_path).unwrap().audio_hash);
+
+        stats.record(&new_path, 60_000, Some(60_000), ListenEnd::Finished, 50, 2000);
+        stats.set_rating(&new_path, Some(4));
+        wait_for_hashes(&mut stats);
+        assert!(stats.get(&old_path).is_none());
+        let moved = stats.get(&new_path).unwrap();
+        assert_eq!((moved.play_count, moved.last_played, moved.rating), (2, Some(2000), Some(4)));
+    }
+
+    #[test]
+    fn test_queue_keys_follow_queue() {
+        use crate::stats::{stats_key, QueueKeys};
+        let mut keys = QueueKeys::default();
+        let mut files = vec!["a.mp3".to_string(), "b.mp3".to_string()];
+        assert_eq!(keys.update(&files, "/m"), [stats_key("/m/a.mp3"), stats_key("/m/b.mp3")]);
+        files.swap(0, 1);
+        assert_eq!(keys.update(&files, "/m"), [stats_key("/m/b.mp3"), stats_key("/m/a.mp3")]);
+        assert_eq!(keys.update(&files, "/n"), [stats_key("/n/b.mp3"), stats_key("/n/a.mp3")]);
+    }
+
+    #[test]
+    fn test_sort_queue_by_stats() {
+        use crate::stats::{sort_queue, stats_key, ListenEnd, PlayStats, QueueSort};
+        let mut stats = PlayStats::default();
+        stats.record(&stats_key("/m/b.mp3"), 1_000, None, ListenEnd::Finished, 50, 10);
+        stats.record(&stats_key("/m/b.mp3"), 1_000, None, ListenEnd::Finished, 50, 20);
+        stats.record(&stats_key("/m/c.mp3"), 1_000, None, ListenEnd::Finished, 50, 30);
+        let names = |path: &str| (path.to_string(), String::new());
+
+        let mut files = vec!["/m/a.mp3".to_string(), "/m/b.mp3".to_string(), "/m/c.mp3".to_string()];
+        sort_queue(&mut files, ".", QueueSort::Plays, &stats, names);
+        assert_eq!(files, vec!["/m/b.mp3", "/m/c.mp3", "/m/a.mp3"]);
+        sort_queue(&mut files, ".", QueueSort::LastPlayed, &stats, names);
+        assert_eq!(files, vec!["/m/c.mp3", "/m/b.mp3", "/m/a.mp3"]);
+        sort_queue(&mut files, ".", QueueSort::Title, &stats, names);
+        assert_eq!(files, vec!["/m/a.mp3", "/m/b.mp3", "/m/c.mp3"]);
+    }
+
+    #[test]
+    fn test_smart_playlist_stats_fields() {
+        use crate::smart_playlist::{parse_rule, RuleContext};
+        use crate::stats::{ListenEnd, PlayStats};
+        let library = sample_library();
+        let day = 86_400;
+        let mut stats = PlayStats::default();
+        stats.record("/m/b1.mp3", 1_000, None, ListenEnd::Finished, 50, 0);
+        stats.record("/m/b1.mp3", 1_000, None, ListenEnd::Finished, 50, 0);
+        stats.record("/m/e1.mp3", 1_000, None, ListenEnd::Finished, 50, 40 * day);
+        let ctx = RuleContext { stats: &stats, now: 45 * day };
+
+        let stale = parse_rule("Stale", "artist is Bowie and not played in 30 days, order plays desc").unwrap();
+        assert_eq!(stale.evaluate(&library, &ctx), vec!["/m/b1.mp3", "/m/b2.mp3", "/m/x.mp3"]);
+        let recent = parse_rule("Recent", "played in 7 days").unwrap();
+        assert_eq!(recent.evaluate(&library, &ctx), vec!["/m/e1.mp3"]);
+        let favourites = parse_rule("Top", "plays >= 2").unwrap();
+        assert_eq!(favourites.evaluate(&library, &ctx), vec!["/m/b1.mp3"]);
+    }
+
+    // Tests for listening history
+
+    fn history_entry(started_at: u64, status: crate::history::HistoryStatus, title: Option<&str>) -> crate::history::HistoryEntry {
+        crate::history::HistoryEntry {
+            started_at,
+            status,
+            listened_ms: 61_500,
+            path: "/m/a, \"b\".mp3".to_string(),
+            artist: Some("Bowie".to_string()),
+            title: title.map(|t| t.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_history_append_and_load() {
+        use crate::history::{append_history, load_history, HistoryStatus};
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file = temp_dir.path().join("history");
+        let file = file.to_str().unwrap();
+        let entries = vec![
+            history_entry(100, HistoryStatus::Completed, Some("Sound\tand Vision")),
+            history_entry(200, HistoryStatus::Skipped, None),
+        ];
+        for entry in &entries {
+            append_history(file, entry).unwrap();
+        }
+        assert_eq!(load_history(file), entries);
+        assert_eq!(entries[1].label(), "a, \"b\".mp3");
+    }
+
+    #[test]
+    fn test_history_entry_of_relative_queue_entry() {
+        use crate::history::HistoryStatus;
+        use crate::stats::{stats_key, ListenOutcome, ListenSession};
+        use id3::TagLike;
+        // Queue entries of the default "." folder are relative paths
+        let temp_dir = TempDir::new_in(".").expect("Failed to create temp dir");
+        let dir_name = temp_dir.path().file_name().unwrap().to_string_lossy().into_owned();
+        let track = crate::get_track_path(&format!("{}/song.mp3", dir_name), ".");
+        assert!(std::path::Path::new(&track).is_relative());
+        fs::write(&track, b"audio").unwrap();
+        let mut tag = id3::Tag::new();
+        tag.set_title("Heroes");
+        tag.set_artist("Bowie");
+        tag.write_to_path(&track, id3::Version::Id3v24).unwrap();
+
+        let cache = crate::metadata_cache::MetadataCache::new(None);
+        for _ in 0..100 {
+            if cache.get(&track).is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let entry = crate::history_entry(ListenSession::start(&track, 1.0), ListenOutcome::Played, 1_000, &cache);
+        assert_eq!(entry.path, stats_key(&track));
+        assert_eq!(entry.status, HistoryStatus::Completed);
+        assert_eq!((entry.artist.as_deref(), entry.title.as_deref()), (Some("Bowie"), Some("Heroes")));
+    }
+
+    #[test]
+    fn test_history_export_csv_and_json() {
+        use crate::history::{history_to_csv, history_to_json, HistoryStatus};
+        let entries = vec![history_entry(100, HistoryStatus::Skipped, Some("Be My Wife"))];
+        let csv = history_to_csv(&entries);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "played_at,status,listened_seconds,artist,title,path");
+        assert!(lines[1].ends_with(",skipped,61,Bowie,Be My Wife,\"/m/a, \"\"b\"\".mp3\""));
+
+        let json = history_to_json(&entries);
+        assert!(json.contains("\"timestamp\": 100"));
+        assert!(json.contains("\"title\": \"Be My Wife\""));
+        assert!(json.contains("\"path\": \"/m/a, \\\"b\\\".mp3\""));
+        assert_eq!(history_to_json(&[]), "[]\n");
+    }
+
+    // Tests for ratings and favourites
+
+    #[test]
+    fn test_ratings_roundtrip_and_legacy_lines() {
+        use crate::stats::PlayStats;
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let file = temp_dir.path().join("stats");
+        let file = file.to_str().unwrap();
+
+        let mut stats = PlayStats::default();
+        stats.set_rating("/m/a.mp3", Some(4));
+        assert!(stats.toggle_favourite("/m/a.mp3"));
+        stats.set_rating("/m/b.mp3", Some(9));
+        stats.save_to(file).unwrap();
+        let loaded = PlayStats::load_from(file);
+        assert_eq!(loaded.get("/m/a.mp3").map(|s| (s.rating, s.favourite)), Some((Some(4), true)));
+        assert_eq!(loaded.get("/m/b.mp3").and_then(|s| s.rating), Some(5));
+
+        // Lines from before ratings existed still load
+        std::fs::write(file, "/m/c.mp3\t3\t1\t100\t\n").unwrap();
+        let legacy = PlayStats::load_from(file);
+        let entry = legacy.get("/m/c.mp3").unwrap();
+        assert_eq!((entry.play_count, entry.rating, entry.favourite), (3, None, false));
+    }
+
+    #[test]
+    fn test_write_popularimeter_keeps_other_players() {
+        use crate::metadata::{write_popularimeter, POPM_USER};
+        use id3::TagLike;
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let path = temp_dir.path().join("rated.mp3");
+        let path = path.to_str().unwrap();
+        let mut tag = id3::Tag::new();
+        tag.add_frame(id3::frame::Popularimeter { user: "other@player".to_string(), rating: 50, counter: 7 });
+        std::fs::write(path, b"audio").unwrap();
+        tag.write_to_path(path, id3::Version::Id3v24).unwrap();
+
+        let popm = |path: &str| -> Vec<(String, u8, u64)> {
+            id3::Tag::read_from_path(path).unwrap()
+                .frames()
+                .filter_map(|f| f.content().popularimeter().map(|p| (p.user.clone(), p.rating, p.counter)))
+                .collect()
+        };
+        write_popularimeter(path, Some(4), 12).unwrap();
+        let mut frames = popm(path);
+        frames.sort();
+        assert_eq!(frames, vec![("other@player".to_string(), 50, 7), (POPM_USER.to_string(), 196, 12)]);
+
+        write_popularimeter(path, None, 12).unwrap();
+        assert_eq!(popm(path), vec![("other@player".to_string(), 50, 7)]);
+    }
+
+    #[test]
+    fn test_smart_playlist_rating_fields() {
+        use crate::smart_playlist::{parse_rule, RuleContext};
+        let library = sample_library();
+        let mut stats = crate::stats::PlayStats::default();
+        stats.set_rating("/m/b1.mp3", Some(5));
+        stats.set_rating("/m/e1.mp3", Some(3));
+        stats.toggle_favourite("/m/x.mp3");
+        let ctx = RuleContext { stats: &stats, now: 0 };
+
+        let rule = parse_rule("Best", "rating ≥ 4 or favourite is yes").unwrap();
+        assert_eq!(rule.evaluate(&library, &ctx), vec!["/m/b1.mp3", "/m/x.mp3"]);
+        let by_rating = parse_rule("Rated", "rating > 0 order rating desc").unwrap();
+        assert_eq!(by_rating.evaluate(&library, &ctx), vec!["/m/b1.mp3", "/m/e1.mp3"]);
+    }
+
+    // Tests for tag editor
+
+    #[test]
+    fn test_tag_editor_validation() {
+        use crate::tag_editor::{parse_number_pair, TagField};
+        assert_eq!(parse_number_pair("3"), Some((3, None)));
+        assert_eq!(parse_number_pair(" 3 / 12 "), Some((3, Some(12))));
+        assert_eq!(parse_number_pair("3/x"), None);
+        assert!(TagField::Track.validate("").is_ok());
+        assert!(TagField::Track.validate("a").is_err());
+        assert!(TagField::Year.validate("1977").is_ok());
+        assert!(TagField::Year.validate("late 70s").is_err());
+        assert!(TagField::Title.validate("anything").is_ok());
+    }
+
+    #[test]
+    fn test_write_tags_preserves_unknown_frames() {
+        use crate::tag_editor::{write_tags, TagField, TagVersion};
+        use id3::TagLike;
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let path = temp_dir.path().join("song.mp3");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, b"audio").unwrap();
+        let mut tag = id3::Tag::new();
+        tag.set_title("Old Title");
+        tag.set_album("Old Album");
+        tag.add_frame(id3::frame::ExtendedText { description: "MusicBrainz Album Id".to_string(), value: "abc".to_string() });
+        tag.write_to_path(path, id3::Version::Id3v23).unwrap();
+
+        let changes = vec![
+            (TagField::Album, "Low".to_string()),
+            (TagField::Track, "2/11".to_string()),
+            (TagField::Year, "1977".to_string()),
+            (TagField::Comment, "remaster".to_string()),
+        ];
+        write_tags(path, &changes, TagVersion::Keep).unwrap();
+
+        let tag = id3::Tag::read_from_path(path).unwrap();
+        assert_eq!(tag.version(), id3::Version::Id3v23);
+        assert_eq!(tag.title(), Some("Old Title"));
+        assert_eq!(tag.extended_texts().next().map(|t| t.value.as_str()), Some("abc"));
+        let tags = crate::metadata::TrackTags::from_tag(&tag);
+        assert_eq!((tags.album.as_deref(), tags.track, tags.total_tracks, tags.year), (Some("Low"), Some(2), Some(11), Some(1977)));
+        assert_eq!(tags.comment.as_deref(), Some("remaster"));
+        assert!(tag.get("TYER").is_some());
+        // No temporary file is left behind
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        // Upgrading to v2.4 stores the year as a recording date; empty values remove fields
+        write_tags(path, &[(TagField::Year, "1978".to_string()), (TagField::Comment, String::new())], TagVersion::V24).unwrap();
+        let tag = id3::Tag::read_from_path(path).unwrap();
+        assert_eq!(tag.version(), id3::Version::Id3v24);
+        assert_eq!(tag.date_recorded().map(|d| d.year), Some(1978));
+        assert!(tag.get("TYER").is_none());
+        assert_eq!(tag.comments().count(), 0);
+        assert!(std::fs::read(path).unwrap().ends_with(b"audio"));
+    }
+
+    #[test]
+    fn test_write_tags_rejects_invalid_values() {
+        use crate::tag_editor::{write_tags, TagField, TagVersion};
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let path = temp_dir.path().join("song.mp3");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, b"audio").unwrap();
+        assert!(write_tags(path, &[(TagField::Year, "soon".to_string())], TagVersion::Keep).is_err());
+        assert_eq!(std::fs::read(path).unwrap(), b"audio");
+    }
+
+    // Tests for rename by pattern
+
+    #[test]
+    fn test_render_template() {
+        use crate::renamer::{render_template, sanitize_component, DEFAULT_RENAME_TEMPLATE};
+        let tags = crate::metadata::TrackTags {
+            title: Some("What? Now".to_string()),
+            artist: Some("AC/DC".to_string()),
+            album: Some("Live.".to_string()),
+            track: Some(3),
+            ..Default::default()
+        };
+        let source = std::path::Path::new("/in/track01.MP3");
+        let (path, sanitized) = render_template(DEFAULT_RENAME_TEMPLATE, &tags, source).unwrap();
+        assert_eq!(path, std::path::PathBuf::from("AC_DC/Live/03 - What_ Now.mp3"));
+        assert!(sanitized);
+
+        // Missing tags fall back to placeholders and the old file name
+        let (path, sanitized) = render_template("{year}/{album}/{title}.{ext}", &Default::default(), source).unwrap();
+        assert_eq!(path, std::path::PathBuf::from("Unknown Album/track01.mp3"));
+        assert!(!sanitized);
+
+        assert!(render_template("{mood}.{ext}", &tags, source).is_err());
+        assert!(render_template("{title", &tags, source).is_err());
+        assert_eq!(sanitize_component(" .. "), ("_".to_string(), true));
+    }
+
+    #[test]
+    fn test_plan_and_apply_renames() {
+        use crate::metadata::TrackTags;
+        use crate::renamer::{apply_renames, plan_renames, RenameStatus};
+        use id3::TagLike;
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let base = temp_dir.path();
+        let mut paths = Vec::new();
+        for (name, title) in [("track01.mp3", "Intro"), ("track02.mp3", "Intro"), ("track03.mp3", "Outro"), ("track04.mp3", "Taken")] {
+            let path = base.join(name);
+            std::fs::write(&path, b"audio").unwrap();
+            let mut tag = id3::Tag::new();
+            tag.set_title(title);
+            tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
+            paths.push(path.to_str().unwrap().to_string());
+        }
+        std::fs::create_dir(base.join("out")).unwrap();
+        std::fs::write(base.join("out/Taken.mp3"), b"other").unwrap();
+
+        let tracks: Vec<(String, TrackTags)> = paths.iter().map(|p| (p.clone(), TrackTags::read(p))).collect();
+        let plans = plan_renames(&tracks, "out/{title}.{ext}", base);
+        let statuses: Vec<RenameStatus> = plans.iter().map(|p| p.status.clone()).collect();
+        assert_eq!(statuses, vec![RenameStatus::Collision, RenameStatus::Collision, RenameStatus::Ready, RenameStatus::Collision]);
+
+        let (moved, errors) = apply_renames(&plans);
+        assert!(errors.is_empty());
+        let target = base.join("out/Outro.mp3").to_str().unwrap().to_string();
+        assert_eq!(moved, vec![(paths[2].clone(), target.clone())]);
+        assert!(std::path::Path::new(&target).exists());
+        assert!(!std::path::Path::new(&paths[2]).exists());
+
+        // Renaming a file onto its own name is reported as unchanged
+        let tags = TrackTags::read(&target);
+        let plans = plan_renames(&[(target, tags.clone())], "out/{title}.{ext}", base);
+        assert_eq!(plans[0].status, RenameStatus::Unchanged);
+
+        // A failed move is reported, not retried as a copy
+        let plans = plan_renames(&[(paths[2].clone(), tags)], "again/{title}.{ext}", base);
+        let (moved, errors) = apply_renames(&plans);
+        assert!(moved.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert!(!base.join("again/Outro.mp3").exists());
+    }
+
+    // Tests for tags from file names
+
+    #[test]
+    fn test_parse_filename_patterns() {
+        use crate::filename_tags::parse_filename;
+        use crate::tag_editor::TagField;
+        let parsed = parse_filename("{artist} - {title}", "/in/Miles Davis - So What - Live.mp3", false).unwrap();
+        assert_eq!(parsed, Some(vec![
+            (TagField::Artist, "Miles Davis".to_string()),
+            (TagField::Title, "So What - Live".to_string()),
+        ]));
+
+        let parsed = parse_filename("{album}/{track}. {title}", "/in/Kind of Blue/02. Freddie_Freeloader.mp3", true).unwrap();
+        assert_eq!(parsed, Some(vec![
+            (TagField::Album, "Kind of Blue".to_string()),
+            (TagField::Track, "02".to_string()),
+            (TagField::Title, "Freddie Freeloader".to_string()),
+        ]));
+
+        // Numeric placeholders only take digits; {ignore} drops text
+        assert_eq!(parse_filename("{track} {title}", "/in/track01.mp3", false).unwrap(), None);
+        let parsed = parse_filename("{ignore}_{track}", "/in/track_07.mp3", false).unwrap();
+        assert_eq!(parsed, Some(vec![(TagField::Track, "07".to_string())]));
+
+        assert!(parse_filename("{artist}{title}", "/in/x.mp3", false).is_err());
+        assert!(parse_filename("{mood} - {title}", "/in/x.mp3", false).is_err());
+        assert_eq!(parse_filename("{artist}/{album}/{title}", "x.mp3", false).unwrap(), None);
+    }
+
+    #[test]
+    fn test_filename_changes_keep_existing_tags() {
+        use crate::filename_tags::changes_for;
+        use crate::tag_editor::TagField;
+        let parsed = vec![(TagField::Artist, "Eno".to_string()), (TagField::Title, "Ascent".to_string())];
+        let current = crate::metadata::TrackTags { title: Some("An Ending".to_string()), ..Default::default() };
+        assert_eq!(changes_for(&parsed, &current, false), vec![(TagField::Artist, "Eno".to_string())]);
+        assert_eq!(changes_for(&parsed, &current, true), parsed);
+
+        // A track number from the file name keeps the existing total
+        let current = crate::metadata::TrackTags { track: Some(3), total_tracks: Some(12), ..Default::default() };
+        let parsed = vec![(TagField::Track, "02".to_string())];
+        assert_eq!(changes_for(&parsed, &current, true), vec![(TagField::Track, "2/12".to_string())]);
+        let parsed = vec![(TagField::Track, "03".to_string()), (TagField::Disc, "1".to_string())];
+        assert_eq!(changes_for(&parsed, &current, true), vec![(TagField::Disc, "1".to_string())]);
+    }
+
+    // Tests for album art
+
+    fn solid_png(r: u8, g: u8, b: u8, width: u32, height: u32) -> Vec<u8> {
+        use image::ImageEncoder;
+        let image = image::RgbaImage::from_pixel(width, height, image::Rgba([r, g, b, 255]));
+        let mut data = Vec::new();
+        image::codecs::png::PngEncoder::new(&mut data)
+            .write_image(image.as_raw(), width, height, image::ExtendedColorType::Rgba8)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn test_art_protocol_detection() {
+        use crate::album_art::ArtProtocol;
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| vars.iter().find(|(k, _)| *k == name).map(|(_, v)| v.to_string())
+        };
+        assert_eq!(ArtProtocol::detect(env(&[("TERM", "xterm-kitty")])), ArtProtocol::Kitty);
+        assert_eq!(ArtProtocol::detect(env(&[("TERM", "xterm-256color"), ("TERM_PROGRAM", "iTerm.app")])), ArtProtocol::Iterm2);
+        assert_eq!(ArtProtocol::detect(env(&[("TERM", "foot")])), ArtProtocol::Sixel);
+        assert_eq!(ArtProtocol::detect(env(&[("TERM", "linux")])), ArtProtocol::Ascii);
+        assert_eq!(ArtProtocol::detect(env(&[("TERM", "xterm-256color")])), ArtProtocol::HalfBlocks);
+
+        assert_eq!(ArtProtocol::from_setting("off", env(&[("TERM", "xterm-kitty")])), None);
+        assert_eq!(ArtProtocol::from_setting("Sixel", env(&[("TERM", "xterm-kitty")])), Some(ArtProtocol::Sixel));
+        assert_eq!(ArtProtocol::from_setting("auto", env(&[("TERM", "xterm-kitty")])), Some(ArtProtocol::Kitty));
+        assert_eq!(crate::config::Config::parse("album_art = halfblocks").album_art, "halfblocks");
+    }
+
+    #[test]
+    fn test_art_encoding() {
+        use crate::album_art::{ascii_lines, base64_encode, half_block_lines, sixel_sequence};
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+
+        let white = image::RgbaImage::from_pixel(4, 8, image::Rgba([255, 255, 255, 255]));
+        let lines = half_block_lines(&white, 4, 3);
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|l| l.spans.len() == 4));
+        assert_eq!(ascii_lines(&white, 4, 2)[0].spans[0].content, "@@@@");
+
+        // One white 2x6 band: a single colour with full columns, run-length free
+        let sixel = sixel_sequence(&image::RgbaImage::from_pixel(2, 6, image::Rgba([255, 255, 255, 255])));
+        assert!(sixel.starts_with("\x1bP0;1q\"1;1;2;6#251;2;100;100;100"));
+        assert!(sixel.ends_with("#251~~-\x1b\\"));
+    }
+
+    #[test]
+    fn test_art_area_keeps_aspect() {
+        use crate::album_art::art_area;
+        use ratatui::layout::Rect;
+        let panel = Rect::new(10, 5, 40, 30);
+        // Square cover with 8x16 cells: twice as many columns as rows
+        assert_eq!(art_area(panel, (500, 500), (8, 16)), Some(Rect::new(15, 5, 30, 15)));
+        // A wide cover is limited by the panel width
+        assert_eq!(art_area(panel, (1000, 250), (8, 16)), Some(Rect::new(10, 5, 40, 5)));
+        assert_eq!(art_area(Rect::new(0, 0, 40, 4), (500, 500), (8, 16)), None);
+    }
+
+    #[test]
+    fn test_album_art_sources() {
+        use crate::album_art::{album_key, load_art, sidecar_art};
+        use id3::TagLike;
+        let dir = TempDir::new().expect("Failed to create temp dir");
+        let track = dir.path().join("01.mp3");
+        fs::write(&track, b"not really audio").unwrap();
+        let track = track.to_str().unwrap().to_string();
+        assert!(load_art(&track).is_none());
+
+        // A sidecar is found whatever its case
+        fs::write(dir.path().join("Folder.JPG"), solid_png(255, 0, 0, 4, 4)).unwrap();
+        assert_eq!(sidecar_art(&track), Some(dir.path().join("Folder.JPG")));
+        assert_eq!(load_art(&track).unwrap().get_pixel(0, 0).0, [255, 0, 0, 255]);
+
+        // Embedded front covers win over sidecars
+        let mut tag = id3::Tag::new();
+        tag.add_frame(id3::frame::Picture {
+            mime_type: String::from("image/png"),
+            picture_type: id3::frame::PictureType::CoverFront,
+            description: String::new(),
+            data: solid_png(0, 0, 255, 2, 2),
+        });
+        tag.write_to_path(&track, id3::Version::Id3v24).unwrap();
+        assert_eq!(load_art(&track).unwrap().get_pixel(0, 0).0, [0, 0, 255, 255]);
+
+        // Tracks of one album share a key; untagged tracks group by folder
+        let tags = |album: &str| crate::metadata::TrackTags {
+            album: Some(album.to_string()),
+            album_artist: Some("Various".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(album_key("/a/1.mp3", &tags("Hits")), album_key("/b/2.mp3", &tags("HITS")));
+        let untagged = crate::metadata::TrackTags::default();
+        assert_eq!(album_key("/a/1.mp3", &untagged), album_key("/a/2.mp3", &untagged));
+        assert_ne!(album_key("/a/1.mp3", &untagged), album_key("/b/1.mp3", &untagged));
+    }
+
+    // Tests for lyrics and playback position
+
+    #[test]
+    fn test_parse_lrc() {
+        use crate::lyrics::{parse_lrc, LyricLine};
+        let text = "[ar:Someone]\n[offset:+250]\n[00:12.00]First\n[00:05.5][01:00.05]Chorus\n[00:20.123]  Third  \n";
+        let lines = parse_lrc(text);
+        let line = |ms: u64, text: &str| LyricLine { time_ms: Some(ms), text: text.to_string() };
+        assert_eq!(lines, vec![
+            line(5_250, "Chorus"),
+            line(11_750, "First"),
+            line(19_873, "Third"),
+            line(59_800, "Chorus"),
+        ]);
+
+        // Without timestamps the text is kept as plain lines
+        let plain = parse_lrc("\nVerse one\n\nVerse two\n\n");
+        assert_eq!(plain.iter().map(|l| l.text.as_str()).collect::<Vec<_>>(), vec!["Verse one", "", "Verse two"]);
+        assert!(plain.iter().all(|l| l.time_ms.is_none()));
+    }
+
+    #[test]
+    fn test_lyrics_current_line_and_window() {
+        use crate::lyrics::{lyric_lines, parse_lrc, Lyrics, LyricsSource};
+        let lyrics = Lyrics {
+            lines: parse_lrc("[00:01.00]a\n[00:02.00]b\n[00:03.00]c\n[00:04.00]d\n[00:05.00]e"),
+            source: LyricsSource::LrcFile,
+        };
+        assert_eq!(lyrics.current_line(500), None);
+        assert_eq!(lyrics.current_line(2_000), Some(1));
+        assert_eq!(lyrics.current_line(60_000), Some(4));
+
+        // The current line sits in the middle of the window
+        let window = lyric_lines(&lyrics, 3_500, None, 3);
+        let texts: Vec<String> = window.iter().map(|l| l.spans[0].content.to_string()).collect();
+        assert_eq!(texts, vec!["b", "c", "d"]);
+        assert_eq!(window[1].style.fg, Some(ratatui::style::Color::Cyan));
+    }
+
+    #[test]
+    fn test_load_lyrics_sources() {
+        use crate::lyrics::{load_lyrics, LyricsSource};
+        use id3::TagLike;
+        let dir = TempDir::new().expect("Failed to create temp dir");
+        let track = dir.path().join("song.mp3");
+        fs::write(&track, b"not really audio").unwrap();
+        let track = track.to_str().unwrap().to_string();
+        assert_eq!(load_lyrics(&track), None);
+
+        let mut tag = id3::Tag::new();
+        tag.add_frame(id3::frame::Lyrics { lang: "eng".to_string(), description: String::new(), text: "Plain words".to_string() });
+        tag.write_to_path(&track, id3::Version::Id3v24).unwrap();
+        assert_eq!(load_lyrics(&track).unwrap().source, LyricsSource::Uslt);
+
+        // A sidecar .lrc beats USLT, and SYLT beats both
+        fs::write(dir.path().join("song.lrc"), "[00:01.00]Timed words").unwrap();
+        let lyrics = load_lyrics(&track).unwrap();
+        assert_eq!(lyrics.source, LyricsSource::LrcFile);
+        assert!(lyrics.is_synced());
+
+        tag.add_frame(id3::frame::SynchronisedLyrics {
+            lang: "eng".to_string(),
+            timestamp_format: id3::frame::TimestampFormat::Ms,
+            content_type: id3::frame::SynchronisedLyricsType::Lyrics,
+            description: String::new(),
+            content: vec![(1500, "\nHello".to_string())],
+        });
+        tag.write_to_path(&track, id3::Version::Id3v24).unwrap();
+        let lyrics = load_lyrics(&track).unwrap();
+        assert_eq!(lyrics.source, LyricsSource::Sylt);
+        assert_eq!(lyrics.lines[0].text, "Hello");
+        assert_eq!(lyrics.lines[0].time_ms, Some(1500));
+    }
+
+    #[test]
+    fn test_tracked_source_reports_position() {
+        use crate::symphonia_play::TrackedSource;
+        let ctrl = crate::symphonia_control::PlaybackControl::new();
+        // One second of stereo audio at 1 kHz starting two seconds in
+        let source = TrackedSource::new(vec![0.0; 2000], 2, 1000, 2000, &ctrl);
+        assert_eq!(source.count(), 2000);
+        assert!((2_700..3_000).contains(&ctrl.get_position()));
+    }
+
+    #[test]
+    fn test_progress_line() {
+        use crate::metadata::progress_line;
+        let line = progress_line(30_000, Some(60_000), 20, None);
+        assert!(line.starts_with("0:30 ") && line.ends_with(" 1:00"));
+        assert_eq!(line.chars().count(), 20);
+        assert_eq!(line.chars().filter(|c| *c == '●').count(), 1);
+        assert_eq!(progress_line(5_000, None, 20, None), "0:05");
+    }
+
+    // Tests for visualiser
+
+    fn sine(frequency: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32).sin()).collect()
+    }
+
+    #[test]
+    fn test_fft_finds_tone() {
+        use crate::visualiser::fft;
+        // 8 cycles over 64 samples land in bin 8
+        let mut re = sine(8.0, 64, 64);
+        let mut im = vec![0.0; 64];
+        fft(&mut re, &mut im);
+        let magnitudes: Vec<f32> = re.iter().zip(&im).map(|(r, i)| (r * r + i * i).sqrt()).collect();
+        let peak = (0..32).max_by(|a, b| magnitudes[*a].total_cmp(&magnitudes[*b])).unwrap();
+        assert_eq!(peak, 8);
+        assert!((magnitudes[8] - 32.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_band_levels_place_tone() {
+        use crate::visualiser::{band_levels, TAP_SIZE};
+        let levels = band_levels(&sine(1000.0, 44100, TAP_SIZE), 44100, 20);
+        let loudest = (0..20).max_by(|a, b| levels[*a].total_cmp(&levels[*b])).unwrap();
+        // 40 Hz..16 kHz in 20 log bands: 1 kHz falls in band 10
+        assert_eq!(loudest, 10);
+        assert!(levels[loudest] > 0.9);
+        assert!(levels[0] < 0.3);
+        assert!(band_levels(&vec![0.0; TAP_SIZE], 44100, 20).iter().all(|l| *l == 0.0));
+    }
+
+    #[test]
+    fn test_sample_tap_ring_and_try_push() {
+        use crate::visualiser::{SampleTap, TAP_SIZE};
+        let tap = SampleTap::new();
+        assert!(tap.try_push(&[1.0, 2.0], 48000));
+        assert!(tap.try_push(&vec![3.0; TAP_SIZE - 1], 48000));
+        let (samples, rate, written) = tap.snapshot();
+        assert_eq!((samples.len(), rate, written), (TAP_SIZE, 48000, TAP_SIZE as u64 + 1));
+        // The oldest sample (1.0) was overwritten; 2.0 is now first
+        assert_eq!(samples[0], 2.0);
+        assert_eq!(samples[TAP_SIZE - 1], 3.0);
+
+        // The output stream never waits for a reader holding the buffer
+        let ctrl = crate::symphonia_control::PlaybackControl::new();
+        let source = crate::symphonia_play::TrackedSource::new(vec![0.5; 600], 2, 1000, 0, &ctrl);
+        assert_eq!(source.count(), 600);
+        assert_eq!(ctrl.tap.snapshot().2, 300);
+    }
+
+    #[test]
+    fn test_spectrum_peak_hold_and_decay() {
+        use crate::visualiser::{Spectrum, TAP_SIZE};
+        let mut spectrum = Spectrum::new();
+        let tone = sine(1000.0, 44100, TAP_SIZE);
+        spectrum.update(&tone, 44100, 1, 4);
+        let full = spectrum.lines(4);
+        assert_eq!(full.len(), 4);
+        assert_eq!(full[0].spans.len(), 8);
+        let top_row: String = full[0].spans.iter().map(|s| s.content.to_string()).collect();
+        assert!(top_row.contains('█'));
+
+        // Without new samples bars fall while the peak marker stays
+        for _ in 0..10 {
+            spectrum.update(&tone, 44100, 1, 4);
+        }
+        let held: String = spectrum.lines(4)[0].spans.iter().map(|s| s.content.to_string()).collect();
+        assert!(!held.contains('█'));
+        assert!(held.contains('▔'));
+    }
+
+    #[test]
+    fn test_scope_points_trigger_on_rising_edge() {
+        use crate::visualiser::scope_points;
+        let mut samples = vec![0.5, 0.2, -0.3];
+        samples.extend(sine(2.0, 64, 62));
+        let points = scope_points(&samples, 40);
+        assert_eq!(points.len(), 40);
+        assert_eq!(points[0], (0.0, 0.0));
+        assert!(points[1].1 > 0.0);
+    }
+
+    // Tests for waveform
+
+    fn test_waveform(samples: &[f32], sample_rate: u32) -> crate::waveform::Waveform {
+        let mut builder = crate::waveform::WaveformBuilder::default();
+        for &sample in samples {
+            builder.push(sample);
+        }
+        builder.finish(sample_rate)
+    }
+
+    #[test]
+    fn test_waveform_columns_summarise_blocks() {
+        // Two seconds at 1024 Hz: a loud first half and a quiet second half
+        let mut samples = [0.8, -0.8].repeat(1024);
+        samples.extend([0.1, -0.1].repeat(1024));
+        let waveform = test_waveform(&samples, 1024);
+        assert_eq!(waveform.duration_ms, 4000);
+        assert_eq!(waveform.blocks.len(), 4);
+
+        let columns = waveform.columns(2);
+        assert_eq!((columns[0].min, columns[0].max), (-0.8, 0.8));
+        assert!((columns[0].rms - 0.8).abs() < 1e-6);
+        assert!((columns[1].rms - 0.1).abs() < 1e-6);
+        // More columns than blocks repeat blocks instead of failing
+        assert_eq!(waveform.columns(10).len(), 10);
+        assert_eq!(crate::waveform::Waveform::default().columns(3).len(), 3);
+    }
+
+    #[test]
+    fn test_waveform_playhead_and_seek() {
+        use crate::waveform::{position_column, seek_position, waveform_lines};
+        use ratatui::layout::Rect;
+        assert_eq!(position_column(0, 60_000, 61), 0);
+        assert_eq!(position_column(30_000, 60_000, 61), 30);
+        assert_eq!(position_column(90_000, 60_000, 61), 60);
+
+        let area = Rect::new(10, 20, 101, 4);
+        assert_eq!(seek_position(10, area, 200_000), 0);
+        assert_eq!(seek_position(60, area, 200_000), 100_000);
+        assert_eq!(seek_position(500, area, 200_000), 200_000);
+
+        let waveform = test_waveform(&[0.5, -0.5].repeat(4096), 1024);
+        let lines = waveform_lines(&waveform, 8, 4, 4000);
+        assert_eq!(lines.len(), 4);
+        assert!(lines.iter().all(|l| l.spans.len() == 8));
+        // Full-scale bars; the playhead at the middle column is highlighted
+        assert_eq!(lines[0].spans[0].content, "█");
+        assert_eq!(lines[0].spans[4].style.fg, Some(ratatui::style::Color::White));
+        assert_eq!(lines[0].spans[0].style.fg, Some(ratatui::style::Color::LightRed));
+        assert_eq!(lines[0].spans[7].style.fg, Some(ratatui::style::Color::Gray));
+    }
+
+    #[test]
+    fn test_compute_waveform_rejects_non_audio() {
+        let dir = TempDir::new().expect("Failed to create temp dir");
+        let path = dir.path().join("bad.mp3");
+        fs::write(&path, b"definitely not an mp3 stream").unwrap();
+        assert!(crate::waveform::compute_waveform(path.to_str().unwrap()).is_err());
+        assert!(crate::waveform::compute_waveform("/nonexistent/file.mp3").is_err());
+    }
+
+    #[test]
+    fn test_playback_control_seek_request() {
+        let ctrl = crate::symphonia_control::PlaybackControl::new();
+        assert_eq!(ctrl.take_seek(), None);
+        ctrl.seek(42_000);
+        assert_eq!(ctrl.get_position(), 42_000);
+        assert_eq!(ctrl.take_seek(), Some(42_000));
+        assert_eq!(ctrl.take_seek(), None);
+    }
+
+    // Tests for equaliser
+
+    fn equalise(settings: crate::equalizer::EqSettings, input: &[f32]) -> Vec<f32> {
+        let mut samples = input.to_vec();
+        let mut eq = crate::equalizer::Equalizer::new(std::sync::Arc::new(Mutex::new(settings)));
+        eq.process(&mut samples, 1, 44100);
+        samples
+    }
+
+    fn peak(samples: &[f32]) -> f32 {
+        samples.iter().fold(0.0f32, |m, s| m.max(s.abs()))
+    }
+
+    #[test]
+    fn test_equalizer_flat_and_disabled_are_identity() {
+        use crate::equalizer::EqSettings;
+        let input = sine(440.0, 44100, 4096).iter().map(|s| s * 0.5).collect::<Vec<_>>();
+        assert_eq!(equalise(EqSettings::default(), &input), input);
+        let flat = EqSettings { enabled: true, ..EqSettings::default() };
+        let output = equalise(flat, &input);
+        assert!(output.iter().zip(&input).all(|(a, b)| (a - b).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_equalizer_band_gain_at_centre_frequency() {
+        use crate::equalizer::EqSettings;
+        let mut gains = [0.0; 10];
+        gains[5] = 6.0;
+        let settings = EqSettings { enabled: true, gains, preamp_db: Some(0.0) };
+        let input: Vec<f32> = sine(1000.0, 44100, 8820).iter().map(|s| s * 0.1).collect();
+        let output = equalise(settings.clone(), &input);
+        // +6 dB doubles the amplitude once the filter has settled
+        let boosted = peak(&output[4410..]);
+        assert!((boosted - 0.2).abs() < 0.005, "peak {}", boosted);
+        // Far from the band the level is untouched
+        let low: Vec<f32> = sine(40.0, 44100, 44100).iter().map(|s| s * 0.1).collect();
+        assert!((peak(&equalise(settings, &low)[22050..]) - 0.1).abs() < 0.005);
+    }
+
+    #[test]
+    fn test_equalizer_auto_preamp_and_clamping() {
+        use crate::equalizer::EqSettings;
+        let mut settings = EqSettings { enabled: true, gains: [3.0, 6.0, 0.0, -4.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], preamp_db: None };
+        assert_eq!(settings.effective_preamp_db(), -6.0);
+        settings.gains = [-3.0; 10];
+        assert_eq!(settings.effective_preamp_db(), 0.0);
+        settings.preamp_db = Some(12.0);
+        let output = equalise(settings, &[0.9; 256]);
+        assert!(output.iter().all(|s| s.abs() <= 1.0));
+    }
+
+    #[test]
+    fn test_eq_presets_parse() {
+        use crate::equalizer::{builtin_presets, EqPreset};
+        let preset = EqPreset::parse("My Mix: 1 2 3 4 5 6 7 8 9 20").unwrap();
+        assert_eq!(preset.name, "My Mix");
+        assert_eq!(preset.gains, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 12.0]);
+        assert_eq!(EqPreset::parse(&preset.to_config_value()), Some(preset));
+        assert_eq!(EqPreset::parse("Short: 1 2 3"), None);
+        assert_eq!(EqPreset::parse(": 0 0 0 0 0 0 0 0 0 0"), None);
+        let names: Vec<String> = builtin_presets().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, ["Flat", "Rock", "Bass Boost", "Vocal", "Loudness"]);
+    }
+
+    #[test]
+    fn test_config_eq_settings_round_trip() {
+        use crate::config::{eq_values, save_values, Config};
+        let dir = TempDir::new().expect("Failed to create temp dir");
+        let path = dir.path().join("config");
+        let path = path.to_str().unwrap();
+        fs::write(path, "# settings\nskip_threshold = 30\neq_enabled = no\neq_preset = Old: 0 0 0 0 0 0 0 0 0 0\n").unwrap();
+
+        let mut config = Config::load_from(path);
+        assert!(!config.eq.enabled);
+        assert_eq!(config.eq_presets.len(), 1);
+        config.eq.enabled = true;
+        config.eq.gains[0] = 4.5;
+        config.eq.preamp_db = Some(-2.0);
+        config.eq_presets.push(crate::equalizer::EqPreset::parse("New: 1 1 1 1 1 1 1 1 1 1").unwrap());
+        save_values(path, &eq_values(&config.eq, &config.eq_presets)).unwrap();
+
+        let content = fs::read_to_string(path).unwrap();
+        assert!(content.starts_with("# settings\nskip_threshold = 30\neq_enabled = true\n"));
+        let reloaded = Config::load_from(path);
+        assert_eq!(reloaded, config);
+        assert_eq!(Config::parse("eq_preamp = auto").eq.preamp_db, None);
+        assert_eq!(Config::parse("eq_preamp = -20 dB").eq.preamp_db, Some(-12.0));
+    }
+
+    // Tests for ReplayGain
+
+    #[test]
+    fn test_replay_gain_from_tags() {
+        use crate::replaygain::ReplayGain;
+        let gain = ReplayGain::from_tags([
+            ("TXXX:REPLAYGAIN_TRACK_GAIN", "-6.54 dB"),
+            ("TXXX:replaygain_track_peak", "0.988"),
+            ("REPLAYGAIN_ALBUM_GAIN", "+1.5 dB"),
+            ("REPLAYGAIN_ALBUM_PEAK", "not a number"),
+        ]);
+        assert_eq!(gain.track_gain_db, Some(-6.54));
+        assert_eq!(gain.track_peak, Some(0.988));
+        assert_eq!(gain.album_gain_db, Some(1.5));
+        assert_eq!(gain.album_peak, None);
+
+        // R128 gains are Q7.8 relative to -23 LUFS; ReplayGain tags take precedence
+        let opus = ReplayGain::from_tags([("R128_TRACK_GAIN", "-1280"), ("R128_ALBUM_GAIN", "256"), ("REPLAYGAIN_ALBUM_GAIN", "-2 dB")]);
+        assert_eq!(opus.track_gain_db, Some(0.0));
+        assert_eq!(opus.album_gain_db, Some(-2.0));
+        assert_eq!(ReplayGain::from_tags([("TITLE", "x")]), ReplayGain::default());
+    }
+
+    #[test]
+    fn test_replay_gain_modes_and_clipping() {
+        use crate::replaygain::{ReplayGain, ReplayGainMode, ReplayGainSettings};
+        let gain = ReplayGain { track_gain_db: Some(6.0), track_peak: Some(0.9), album_gain_db: Some(-3.0), album_peak: Some(1.0) };
+        let mut settings = ReplayGainSettings::default();
+
+        // Auto follows the album context
+        let track = gain.applied(&settings, false).unwrap();
+        assert_eq!(track.source, ReplayGainMode::Track);
+        // +6 dB would push the 0.9 peak over full scale, so it is held to 1/0.9
+        assert!((track.factor - 1.0 / 0.9).abs() < 1e-6);
+        let album = gain.applied(&settings, true).unwrap();
+        assert_eq!(album.source, ReplayGainMode::Album);
+        assert!((album.db + 3.0).abs() < 1e-4);
+
+        settings.prevent_clipping = false;
+        settings.mode = ReplayGainMode::Track;
+        assert!((gain.applied(&settings, true).unwrap().db - 6.0).abs() < 1e-4);
+        settings.mode = ReplayGainMode::Off;
+        assert_eq!(gain.applied(&settings, true), None);
+
+        // Album mode falls back to the track value
+        settings.mode = ReplayGainMode::Album;
+        let track_only = ReplayGain { track_gain_db: Some(-4.0), ..ReplayGain::default() };
+        assert_eq!(track_only.applied(&settings, false).unwrap().source, ReplayGainMode::Track);
+        assert_eq!(ReplayGain::default().applied(&settings, false), None);
+    }
+
+    #[test]
+    fn test_replay_gain_album_context_and_config() {
+        use crate::replaygain::{album_context, apply_gain, ReplayGainMode};
+        let albums = [Some("A".to_string()), Some("A".to_string()), Some("B".to_string()), None];
+        assert!(album_context(&albums, 0));
+        assert!(album_context(&albums, 1));
+        assert!(!album_context(&albums, 2));
+        assert!(!album_context(&albums, 3));
+
+        let mut samples = [0.5, -0.8, 0.1];
+        apply_gain(&mut samples, 2.0);
+        assert_eq!(samples, [1.0, -1.0, 0.2]);
+
+        let config = crate::config::Config::parse("replaygain = album\nreplaygain_preamp = 3 dB\nreplaygain_clipping = no");
+        assert_eq!(config.replay_gain.mode, ReplayGainMode::Album);
+        assert_eq!(config.replay_gain.preamp_db, 3.0);
+        assert!(!config.replay_gain.prevent_clipping);
+        assert_eq!(crate::config::Config::parse("replaygain = loud").replay_gain.mode, ReplayGainMode::Auto);
+    }
+
+    // Tests for loudness scanner
+
+    fn measure(samples: &[f32], channels: usize, sample_rate: u32) -> crate::loudness::Loudness {
+        let mut meter = crate::loudness::LoudnessMeter::new(channels, sample_rate);
+        meter.push(samples);
+        meter.finish()
+    }
+
+    fn stereo(mono: &[f32]) -> Vec<f32> {
+        mono.iter().flat_map(|&s| [s, s]).collect()
+    }
+
+    #[test]
+    fn test_loudness_of_reference_tone() {
+        // BS.1770 calibration: a 1 kHz stereo tone at -23 dBFS per channel reads -23 LUFS
+        let amplitude = 10f32.powf(-23.0 / 20.0);
+        let tone: Vec<f32> = sine(1000.0, 48000, 48000 * 5).iter().map(|s| s * amplitude).collect();
+        let loudness = measure(&stereo(&tone), 2, 48000);
+        let lufs = loudness.integrated_lufs().unwrap();
+        assert!((lufs + 23.0).abs() < 0.1, "measured {}", lufs);
+        assert!((loudness.true_peak - amplitude as f64).abs() < 0.005);
+    }
+
+    #[test]
+    fn test_loudness_gating_and_true_peak() {
+        use crate::loudness::gated_loudness;
+        assert_eq!(measure(&vec![0.0; 44100 * 2], 1, 44100).integrated_lufs(), None);
+        assert_eq!(measure(&vec![0.5; 1000], 1, 44100).integrated_lufs(), None);
+        // A near-silent stretch is gated out and does not drag the level down
+        let loud = sine(1000.0, 44100, 44100 * 6);
+        let mut with_silence = loud.clone();
+        with_silence.extend(vec![0.0001; 44100 * 3]);
+        let plain = measure(&loud, 1, 44100).integrated_lufs().unwrap();
+        let gated = measure(&with_silence, 1, 44100).integrated_lufs().unwrap();
+        assert!((plain - gated).abs() < 0.2);
+        assert_eq!(gated_loudness(&[]), None);
+
+        // A quarter-rate tone sampled at 45 degrees never hits its crest; true peak still finds it
+        let tone: Vec<f32> = (0..4096).map(|i| (std::f32::consts::FRAC_PI_2 * i as f32 + std::f32::consts::FRAC_PI_4).sin() * 0.9).collect();
+        let loudness = measure(&tone, 1, 44100);
+        assert!(tone.iter().all(|s| s.abs() < 0.65));
+        assert!(loudness.true_peak > 0.85, "true peak {}", loudness.true_peak);
+    }
+
+    #[test]
+    fn test_loudness_compute_gains_per_album() {
+        use crate::loudness::{compute_gains, Loudness};
+        let loud = measure(&sine(1000.0, 44100, 44100 * 2), 1, 44100);
+        let quiet = measure(&sine(1000.0, 44100, 44100 * 2).iter().map(|s| s * 0.5).collect::<Vec<_>>(), 1, 44100);
+        let measured = vec![
+            (String::from("a.mp3"), Some(String::from("album")), loud.clone()),
+            (String::from("b.mp3"), Some(String::from("album")), quiet.clone()),
+            (String::from("c.mp3"), None, quiet.clone()),
+            (String::from("silent.mp3"), None, Loudness::default()),
+        ];
+        let gains = compute_gains(&measured);
+        assert_eq!(gains.len(), 3);
+        // 6 dB apart, so the gains differ by 6 dB and the album sits between them
+        assert!((gains[1].track_gain_db - gains[0].track_gain_db - 6.02).abs() < 0.1);
+        let (album_gain, album_peak) = gains[0].album.unwrap();
+        assert_eq!(gains[1].album, gains[0].album);
+        assert!(album_gain > gains[0].track_gain_db && album_gain < gains[1].track_gain_db);
+        assert_eq!(album_peak, loud.true_peak);
+        assert_eq!(gains[2].album, None);
+        assert!((gains[0].track_gain_db - (crate::loudness::REPLAYGAIN_REFERENCE_LUFS - gains[0].track_lufs)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_loudness_scan_takes_whole_albums() {
+        use crate::loudness::with_album_tracks;
+        let library = sample_library();
+        let paths = vec![String::from("/m/b2.mp3"), String::from("/elsewhere/single.mp3")];
+        // The other track of Low joins; Heroes and Apollo stay out
+        assert_eq!(with_album_tracks(&paths, &library), vec!["/m/b2.mp3", "/elsewhere/single.mp3", "/m/b1.mp3"]);
+        let whole = vec![String::from("/m/b1.mp3"), String::from("/m/b2.mp3")];
+        assert_eq!(with_album_tracks(&whole, &library), whole);
+    }
+
+    #[test]
+    fn test_write_gain_tags_round_trip() {
+        use crate::loudness::{write_gain_tags, GainTags};
+        use crate::replaygain::ReplayGain;
+        use id3::TagLike;
+        let dir = TempDir::new().expect("Failed to create temp dir");
+        let path = dir.path().join("track.mp3");
+        let path = path.to_str().unwrap().to_string();
+        fs::write(&path, b"audio").unwrap();
+        let mut tag = id3::Tag::new();
+        tag.set_title("Song");
+        tag.add_frame(id3::frame::ExtendedText { description: String::from("replaygain_track_gain"), value: String::from("+9.00 dB") });
+        tag.write_to_path(&path, id3::Version::Id3v23).unwrap();
+
+        let gains = GainTags { path: path.clone(), track_lufs: -12.0, track_gain_db: -6.0, track_peak: 0.95, album: Some((-5.5, 0.99)) };
+        write_gain_tags(&gains).unwrap();
+
+        let tag = id3::Tag::read_from_path(&path).unwrap();
+        assert_eq!(tag.title(), Some("Song"));
+        assert_eq!(tag.version(), id3::Version::Id3v23);
+        assert_eq!(tag.extended_texts().count(), 4);
+        let read = ReplayGain::from_tags(tag.extended_texts().map(|t| (t.description.as_str(), t.value.as_str())));
+        assert_eq!(read, ReplayGain { track_gain_db: Some(-6.0), track_peak: Some(0.95), album_gain_db: Some(-5.5), album_peak: Some(0.99) });
+    }
+
+    // Tests for playback speed
+
+    fn zero_crossings(samples: &[f32]) -> usize {
+        samples.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count()
+    }
+
+    fn stretch_all(input: &[f32], channels: usize, speed: f32) -> Vec<f32> {
+        let mut stretch = crate::timestretch::TimeStretch::new(channels, 44100);
+        stretch.reset(0);
+        let mut output = Vec::new();
+        for chunk in input.chunks(1152 * channels) {
+            output.extend(stretch.process(chunk, speed).1);
+        }
+        output.extend(stretch.flush().1);
+        output
+    }
+
+    #[test]
+    fn test_time_stretch_changes_length_not_pitch() {
+        let tone = sine(440.0, 44100, 44100);
+        for speed in [0.5, 1.5, 3.0] {
+            let output = stretch_all(&tone, 1, speed);
+            let expected = tone.len() as f32 / speed;
+            // The final unstretched flush adds at most a segment and a search window
+            assert!((output.len() as f32 - expected).abs() < 44100.0 * 0.1, "speed {}: {} samples", speed, output.len());
+            // Same pitch: about 440 crossings per second of output
+            let per_second = zero_crossings(&output) as f32 / (output.len() as f32 / 44100.0);
+            assert!((per_second - 440.0).abs() < 15.0, "speed {}: {} Hz", speed, per_second);
+            assert!(output.iter().all(|s| s.abs() <= 1.01));
+        }
+    }
+
+    #[test]
+    fn test_time_stretch_stereo_and_positions() {
+        use crate::timestretch::TimeStretch;
+        let left = sine(300.0, 44100, 44100);
+        let input: Vec<f32> = left.iter().flat_map(|&s| [s, -s]).collect();
+        let output = stretch_all(&input, 2, 2.0);
+        assert_eq!(output.len() % 2, 0);
+        assert!(output.chunks(2).all(|f| (f[0] + f[1]).abs() < 1e-5));
+
+        let mut stretch = TimeStretch::new(1, 44100);
+        stretch.reset(10_000);
+        let (start, first) = stretch.process(&left[..22050], 2.0);
+        assert_eq!(start, 10_000);
+        assert!(!first.is_empty());
+        let (next, _) = stretch.process(&left[22050..], 2.0);
+        // Output so far stands for twice as many input frames
+        assert!((next as i64 - 10_000 - 2 * first.len() as i64).abs() <= 1);
+        let (flushed_from, rest) = stretch.flush();
+        assert!(flushed_from > 10_000 && flushed_from + rest.len() as u64 == 10_000 + left.len() as u64);
+    }
+
+    #[test]
+    fn test_speed_limits_and_tracked_position() {
+        use crate::timestretch::clamp_speed;
+        assert_eq!(clamp_speed(0.2), 0.5);
+        assert_eq!(clamp_speed(3.4), 3.0);
+        assert!((clamp_speed(1.26) - 1.3).abs() < 1e-6);
+
+        let settings = crate::symphonia_control::PlaybackSettings::default();
+        assert_eq!(settings.speed(), 1.0);
+        assert!((settings.adjust_speed(0.5) - 1.5).abs() < 1e-6);
+        settings.reset_speed();
+        assert_eq!(settings.speed(), 1.0);
+
+        // Half a second of output at 2x covers a second of the track
+        let ctrl = crate::symphonia_control::PlaybackControl::new();
+        let source = crate::symphonia_play::TrackedSource::new(vec![0.0; 1000], 2, 1000, 0, &ctrl).with_speed(2.0);
+        assert_eq!(source.count(), 1000);
+        assert!((500..1_000).contains(&ctrl.get_position()));
+    }
+
+    // Tests for A-B loop
+
+    #[test]
+    fn test_ab_loop_points() {
+        use crate::symphonia_control::{AbLoop, PlaybackControl};
+        let ctrl = PlaybackControl::new();
+        assert!(!ctrl.set_loop_b(5_000));
+        ctrl.set_loop_a(10_000);
+        assert_eq!(ctrl.get_loop(), Some(AbLoop { a_ms: 10_000, b_ms: None }));
+        assert_eq!(ctrl.get_loop().unwrap().section(), None);
+        // B must come after A
+        assert!(!ctrl.set_loop_b(9_000));
+        assert!(ctrl.set_loop_b(20_000));
+        assert_eq!(ctrl.get_loop().unwrap().section(), Some((10_000, 20_000)));
+        assert!(!ctrl.set_loop_b(30_000));
+        // A new A starts over
+        ctrl.set_loop_a(15_000);
+        assert_eq!(ctrl.get_loop().unwrap().section(), None);
+        ctrl.clear_loop();
+        assert_eq!(ctrl.get_loop(), None);
+        // Each track has its own control, so a loop never carries over
+        assert_eq!(PlaybackControl::new().get_loop(), None);
+    }
+
+    #[test]
+    fn test_progress_line_shows_loop() {
+        use crate::metadata::progress_line;
+        let line = progress_line(30_000, Some(100_000), 31, Some((20_000, Some(60_000))));
+        let bar: Vec<char> = line.chars().skip(5).take(21).collect();
+        assert_eq!(bar[4], '[');
+        assert_eq!(bar[6], '●');
+        assert_eq!(bar[12], ']');
+        let only_a = progress_line(30_000, Some(100_000), 31, Some((20_000, None)));
+        assert_eq!(only_a.matches('[').count(), 1);
+        assert_eq!(only_a.matches(']').count(), 0);
+        assert_eq!(crate::config::Config::parse("ab_loop_speed = 0.75x").ab_loop_speed, 0.75);
+        assert_eq!(crate::config::Config::default().ab_loop_speed, 1.0);
+    }
+
+    // Tests for sleep timer
+
+    #[test]
+    fn test_sleep_timer_remaining_and_fade() {
+        use crate::sleep_timer::{fade_gain, SleepTimer};
+        use std::time::{Duration, Instant};
+        let now = Instant::now();
+        let timer = SleepTimer::At(now + Duration::from_secs(90));
+        assert_eq!(timer.remaining_ms(now, Some(5_000), true), Some(90_000));
+        assert_eq!(timer.remaining_ms(now + Duration::from_secs(100), None, false), Some(0));
+        assert_eq!(timer.label(now), "Sleep 1:30");
+        assert_eq!(SleepTimer::EndOfTrack.remaining_ms(now, Some(12_000), false), Some(12_000));
+        // Only the last track of the queue counts down to the end of the queue
+        assert_eq!(SleepTimer::EndOfQueue.remaining_ms(now, Some(12_000), false), None);
+        assert_eq!(SleepTimer::EndOfQueue.remaining_ms(now, Some(12_000), true), Some(12_000));
+
+        assert_eq!(fade_gain(None), 1.0);
+        assert_eq!(fade_gain(Some(60_000)), 1.0);
+        assert_eq!(fade_gain(Some(0)), 0.0);
+        let half = fade_gain(Some(15_000));
+        assert!((half - std::f32::consts::FRAC_1_SQRT_2).abs() < 1e-6);
+        assert!(fade_gain(Some(29_000)) > fade_gain(Some(10_000)));
+    }
+
+    #[test]
+    fn test_tracked_source_applies_volume() {
+        let ctrl = crate::symphonia_control::PlaybackControl::new();
+        ctrl.settings.set_volume(0.25);
+        let samples: Vec<f32> = crate::symphonia_play::TrackedSource::new(vec![0.8; 8], 2, 1000, 0, &ctrl).collect();
+        assert!(samples.iter().all(|s| (s - 0.2).abs() < 1e-6));
+        ctrl.settings.set_volume(3.0);
+        assert_eq!(ctrl.settings.volume(), 1.0);
+    }
+
+    #[test]
+    fn test_sleep_timer_runs_without_the_ui_loop() {
+        use crate::sleep_timer::SleepTimer;
+        use crate::symphonia_control::PlaybackControl;
+        use crate::timers::Timers;
+        use std::time::{Duration, Instant};
+        let ctrl = PlaybackControl::new();
+        let timers = Timers::start(ctrl.settings.clone(), Default::default());
+        timers.set_playing(Some(&ctrl), false, None);
+        timers.set_sleep_timer(Some(SleepTimer::At(Instant::now() + Duration::from_secs(10))));
+        // Well inside the 30 second fade
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(ctrl.settings.volume() < 0.6);
+
+        timers.set_sleep_timer(Some(SleepTimer::At(Instant::now())));
+        for _ in 0..50 {
+            if timers.sleep_timer().is_none() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        // Paused by the timer thread; the UI loop is left to stop it
+        assert!(timers.take_sleep_expired());
+        assert!(ctrl.is_paused() && !ctrl.is_stopped());
+        assert!(!timers.take_sleep_expired());
+    }
+
+    // Tests for alarms
+
+    #[test]
+    fn test_alarm_parse_and_config() {
+        use crate::alarm::{parse_time, Alarm, DEFAULT_RAMP_SECS};
+        let alarm = Alarm::parse("7:05, Morning, 90s, off").unwrap();
+        assert_eq!(alarm.time, parse_time("07:05").unwrap());
+        assert_eq!(alarm.playlist, "Morning");
+        assert_eq!(alarm.ramp_secs, 90);
+        assert!(!alarm.enabled);
+        assert_eq!(alarm.to_config_value(), "07:05, Morning, 90, off");
+        assert_eq!(Alarm::parse(&alarm.to_config_value()), Some(alarm));
+
+        let queue_alarm = Alarm::parse("06:30").unwrap();
+        assert_eq!((queue_alarm.playlist.as_str(), queue_alarm.ramp_secs, queue_alarm.enabled), ("", DEFAULT_RAMP_SECS, true));
+        assert!(Alarm::parse("25:00").is_none());
+        assert!(Alarm::parse("07:00, Morning, soon").is_none());
+
+        // Playlist names with a comma are quoted
+        let quoted = Alarm::parse("07:00, \"Rise, Shine\", 30").unwrap();
+        assert_eq!((quoted.playlist.as_str(), quoted.ramp_secs), ("Rise, Shine", 30));
+        assert_eq!(quoted.to_config_value(), "07:00, \"Rise, Shine\", 30, on");
+        assert_eq!(Alarm::parse(&quoted.to_config_value()), Some(quoted));
+        assert!(Alarm::parse("07:00, \"Rise, Shine").is_none());
+        assert!(Alarm::parse("07:00, \"Rise\" Shine, 30").is_none());
+
+        let config = crate::config::Config::parse("alarm = 07:00, Morning, 60, on\nalarm = 22:15\n");
+        assert_eq!(config.alarms.len(), 2);
+        assert_eq!(crate::config::alarm_values(&config.alarms)[0].1, vec!["07:00, Morning, 60, on", "22:15, , 60, on"]);
+    }
+
+    #[test]
+    fn test_alarm_due_across_midnight() {
+        use crate::alarm::{alarm_from_args, next_alarm, Alarm};
+        let at = |d: u32, h: u32, m: u32| chrono::NaiveDate::from_ymd_opt(2024, 3, d).unwrap().and_hms_opt(h, m, 0).unwrap();
+        let alarm = Alarm::parse("00:10").unwrap();
+        assert_eq!(alarm.next_after(at(1, 23, 50)), at(2, 0, 10));
+        assert!(alarm.is_due(at(1, 23, 59), at(2, 0, 10)));
+        assert!(!alarm.is_due(at(2, 0, 10), at(2, 0, 11)));
+        assert!(!alarm.is_due(at(1, 23, 0), at(2, 0, 9)));
+
+        let mut off = Alarm::parse("00:05").unwrap();
+        off.enabled = false;
+        assert!(!off.is_due(at(1, 23, 59), at(2, 1, 0)));
+        assert_eq!(next_alarm([&alarm, &off], at(1, 12, 0)), Some(at(2, 0, 10)));
+
+        let args: Vec<String> = ["rdaio", "--at", "07:00", "--playlist", "Wake"].iter().map(|s| s.to_string()).collect();
+        let cli = alarm_from_args(&args).unwrap().unwrap();
+        assert_eq!(cli.to_config_value(), "07:00, Wake, 60, on");
+        assert!(alarm_from_args(&args[..2]).is_err());
+        assert!(alarm_from_args(&args[..1]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_alarm_ramp_gain() {
+        use crate::alarm::ramp_gain;
+        assert!((ramp_gain(0, 60) - 0.01).abs() < 1e-6);
+        assert!((ramp_gain(30_000, 60) - 0.1).abs() < 1e-6);
+        assert_eq!(ramp_gain(60_000, 60), 1.0);
+        assert_eq!(ramp_gain(0, 0), 1.0);
+        assert!(ramp_gain(45_000, 60) > ramp_gain(15_000, 60));
+    }
+
+    // Tests for silence
+
+    fn silence_settings(skip: bool, trim: bool) -> crate::silence::SilenceSettings {
+        let mut settings = crate::silence::SilenceSettings::default();
+        settings.skip.enabled = skip;
+        settings.trim.enabled = trim;
+        settings
+    }
+
+    /// Mono test signal at 1 kHz: `(loud, frames)` runs of 0.5 or silence.
+    fn silence_signal(runs: &[(bool, usize)]) -> Vec<f32> {
+        runs.iter().flat_map(|&(loud, frames)| std::iter::repeat_n(if loud { 0.5 } else { 0.0 }, frames)).collect()
+    }
+
+    /// `(start, frames)` of the segments, joining those that follow on.
+    fn segment_spans(segments: &[(u64, Vec<f32>)]) -> Vec<(u64, usize)> {
+        let mut spans: Vec<(u64, usize)> = Vec::new();
+        for (start, samples) in segments {
+            match spans.last_mut() {
+                Some(last) if last.0 + last.1 as u64 == *start => last.1 += samples.len(),
+                _ => spans.push((*start, samples.len())),
+            }
+        }
+        spans
+    }
+
+    #[test]
+    fn test_skip_silence_drops_long_runs_only() {
+        use crate::silence::SilenceRemover;
+        // Default skip rule: below -50 dB for at least 2 s
+        let mut remover = SilenceRemover::new(&silence_settings(true, false), 1, 1000, true);
+        let signal = silence_signal(&[(true, 1000), (false, 3000), (true, 1000), (false, 500), (true, 1000)]);
+        let mut segments = Vec::new();
+        for (i, chunk) in signal.chunks(700).enumerate() {
+            segments.extend(remover.process(i as u64 * 700, chunk.to_vec()));
+        }
+        segments.extend(remover.finish());
+        assert_eq!(segment_spans(&segments), vec![(0, 1000), (4000, 2500)]);
+        assert_eq!(remover.dropped_frames(), 3000);
+    }
+
+    #[test]
+    fn test_trim_silence_at_track_ends() {
+        use crate::silence::SilenceRemover;
+        // Default trim rule: below -60 dB for at least 0.5 s
+        let signal = silence_signal(&[(false, 800), (true, 1000), (false, 900), (true, 1000), (false, 600)]);
+        let mut remover = SilenceRemover::new(&silence_settings(false, true), 1, 1000, true);
+        let mut segments = remover.process(0, signal.clone());
+        segments.extend(remover.finish());
+        assert_eq!(segment_spans(&segments), vec![(800, 2900)]);
+        assert_eq!(remover.dropped_frames(), 1400);
+
+        // After a seek there is no leading silence, and short trailing silence stays
+        let signal = silence_signal(&[(false, 800), (true, 1000), (false, 300)]);
+        let mut remover = SilenceRemover::new(&silence_settings(false, true), 1, 1000, false);
+        let mut segments = remover.process(5000, signal);
+        segments.extend(remover.finish());
+        assert_eq!(segment_spans(&segments), vec![(5000, 2100)]);
+
+        let mut off = SilenceRemover::new(&silence_settings(false, false), 1, 1000, true);
+        assert_eq!(segment_spans(&off.process(0, vec![0.0; 2000])), vec![(0, 2000)]);
+    }
+
+    #[test]
+    fn test_silence_config() {
+        let config = crate::config::Config::parse(
+            "skip_silence = yes\nskip_silence_threshold = -45 dB\nskip_silence_min = 1.5s\ntrim_silence = on\ntrim_silence_threshold = -200\ntrim_silence_min = 0\n",
+        );
+        assert!(config.silence.skip.enabled && config.silence.trim.enabled);
+        assert_eq!(config.silence.skip.threshold_db, -45.0);
+        assert_eq!(config.silence.skip.min_ms, 1500);
+        assert_eq!(config.silence.trim.threshold_db, -96.0);
+        assert_eq!(config.silence.trim.min_ms, crate::silence::MIN_SILENCE_MS);
+        assert_eq!(crate::config::Config::default().silence, crate::silence::SilenceSettings::default());
+    }
 }
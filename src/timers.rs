@@ -0,0 +1,188 @@
+use crate::alarm::{alarm_tracks, next_alarm, ramp_gain, Alarm};
+use crate::library::Library;
+use crate::sleep_timer::{fade_gain, SleepTimer};
+use crate::stats::{PlayStats, STATS_FILE};
+use crate::symphonia_control::{PlaybackControl, PlaybackSettings};
+use chrono::NaiveDateTime;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How often the timer thread updates the volume and checks the alarms.
+const TICK: Duration = Duration::from_millis(100);
+
+/// An alarm the timer thread started playing, for the UI loop to take over.
+pub struct FiredAlarm {
+    pub alarm: Alarm,
+    /// The alarm's playlist, which replaces the queue; `None` plays from the queue.
+    pub tracks: Option<Vec<String>>,
+    /// Queue index and path of the track that started.
+    pub index: usize,
+    pub path: String,
+    pub ctrl: PlaybackControl,
+    pub handle: JoinHandle<()>,
+    /// Playback the alarm paused; the UI loop stops it and records the listen.
+    pub interrupted: Option<PlaybackControl>,
+}
+
+struct TimerState {
+    sleep: Option<SleepTimer>,
+    /// When the last alarm went off and how long its volume ramp lasts.
+    ramp: Option<(Instant, u64)>,
+    /// The playing track, and whether it is the last in the queue.
+    playing: Option<(PlaybackControl, bool)>,
+    /// The sleep timer ran out and paused playback; the UI loop stops it.
+    sleep_expired: bool,
+    alarms: Vec<Alarm>,
+    /// One-off alarm from `--at`, forgotten once it has gone off.
+    one_off: Option<Alarm>,
+    /// Alarms go off when their time passes between two ticks.
+    alarm_checked: NaiveDateTime,
+    /// Path and index of the queue entry alarms without a playlist start from.
+    queue_start: Option<(String, usize)>,
+    fired: Option<FiredAlarm>,
+}
+
+/// The sleep timer, alarms and the alarm volume ramp. A thread of their own
+/// keeps them going while a sub-screen has the UI thread.
+#[derive(Clone)]
+pub struct Timers {
+    state: Arc<Mutex<TimerState>>,
+}
+
+impl Timers {
+    pub fn start(settings: PlaybackSettings, library: Arc<Mutex<Library>>) -> Self {
+        let state = Arc::new(Mutex::new(TimerState {
+            sleep: None,
+            ramp: None,
+            playing: None,
+            sleep_expired: false,
+            alarms: Vec::new(),
+            one_off: None,
+            alarm_checked: chrono::Local::now().naive_local(),
+            queue_start: None,
+            fired: None,
+        }));
+        std::thread::spawn({
+            let state = state.clone();
+            move || loop {
+                {
+                    let mut state = state.lock().unwrap();
+                    check_alarms(&mut state, &settings, &library, chrono::Local::now().naive_local());
+                    tick(&mut state, &settings, Instant::now());
+                }
+                std::thread::sleep(TICK);
+            }
+        });
+        Self { state }
+    }
+
+    pub fn sleep_timer(&self) -> Option<SleepTimer> {
+        self.state.lock().unwrap().sleep
+    }
+
+    pub fn set_sleep_timer(&self, timer: Option<SleepTimer>) {
+        self.state.lock().unwrap().sleep = timer;
+    }
+
+    pub fn set_alarms(&self, alarms: &[Alarm]) {
+        self.state.lock().unwrap().alarms = alarms.to_vec();
+    }
+
+    pub fn set_one_off_alarm(&self, alarm: Option<Alarm>) {
+        self.state.lock().unwrap().one_off = alarm;
+    }
+
+    /// When the next enabled alarm goes off.
+    pub fn next_alarm(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let state = self.state.lock().unwrap();
+        next_alarm(state.alarms.iter().chain(state.one_off.iter()), now)
+    }
+
+    /// Tells the thread what is playing and where alarms without a playlist
+    /// start; called by the UI loop on every pass.
+    pub fn set_playing(&self, ctrl: Option<&PlaybackControl>, last_in_queue: bool, queue_start: Option<(String, usize)>) {
+        let mut state = self.state.lock().unwrap();
+        state.playing = ctrl.map(|c| (c.clone(), last_in_queue));
+        state.queue_start = queue_start;
+    }
+
+    /// Whether the sleep timer ran out since the last call.
+    pub fn take_sleep_expired(&self) -> bool {
+        std::mem::take(&mut self.state.lock().unwrap().sleep_expired)
+    }
+
+    /// The alarm that went off since the last call, if any.
+    pub fn take_fired_alarm(&self) -> Option<FiredAlarm> {
+        self.state.lock().unwrap().fired.take()
+    }
+}
+
+/// Starts the alarm that went off since the last check, quietly, with its
+/// playlist or the queue.
+fn check_alarms(state: &mut TimerState, settings: &PlaybackSettings, library: &Mutex<Library>, now: NaiveDateTime) {
+    let last_check = std::mem::replace(&mut state.alarm_checked, now);
+    let due = state.alarms.iter().chain(state.one_off.iter()).find(|a| a.is_due(last_check, now)).cloned();
+    if state.one_off.as_ref().is_some_and(|a| a.is_due(last_check, now)) {
+        state.one_off = None;
+    }
+    let Some(alarm) = due else {
+        return;
+    };
+    // Statistics are read back from disk, where the UI loop saves them after every change
+    let tracks = alarm_tracks(&alarm.playlist, &library.lock().unwrap(), &PlayStats::load_from(STATS_FILE));
+    let start = match &tracks {
+        Some(tracks) => Some((tracks[0].clone(), 0)),
+        None => state.queue_start.clone(),
+    };
+    let Some((path, index)) = start else {
+        return;
+    };
+    let mut interrupted = state.playing.take().map(|(ctrl, _)| ctrl);
+    // An alarm the UI loop has not taken over yet gives way to this one
+    if let Some(old) = state.fired.take() {
+        old.ctrl.stop();
+        interrupted = old.interrupted;
+    }
+    // Paused rather than stopped, so the UI loop does not move on to the next track
+    if let Some(ctrl) = &interrupted {
+        ctrl.pause();
+    }
+    settings.set_volume(ramp_gain(0, alarm.ramp_secs));
+    let (ctrl, handle) = crate::start_playback(&path, ".", settings, false);
+    state.playing = Some((ctrl.clone(), false));
+    state.ramp = Some((Instant::now(), alarm.ramp_secs));
+    state.fired = Some(FiredAlarm { alarm, tracks, index, path, ctrl, handle, interrupted });
+}
+
+fn tick(state: &mut TimerState, settings: &PlaybackSettings, now: Instant) {
+    let mut sleep_volume = 1.0;
+    if let Some(timer) = state.sleep {
+        let playing = state.playing.as_ref();
+        let remaining_in_track = playing.and_then(|(ctrl, _)| {
+            ctrl.get_stream_info().and_then(|info| info.duration_ms).map(|d| d.saturating_sub(ctrl.get_position()))
+        });
+        let last_in_queue = playing.is_some_and(|(_, last)| *last);
+        let remaining = timer.remaining_ms(now, remaining_in_track, last_in_queue);
+        // Timers at the end of a track expire in the UI loop, which sees the track end
+        if matches!(timer, SleepTimer::At(_)) && remaining == Some(0) {
+            // Paused rather than stopped, so the UI loop does not take it for a finished track
+            if let Some((ctrl, _)) = playing {
+                ctrl.pause();
+            }
+            state.sleep = None;
+            state.ramp = None;
+            state.sleep_expired = true;
+        } else {
+            sleep_volume = fade_gain(remaining);
+        }
+    }
+    let ramp_volume = match state.ramp {
+        Some((started, ramp_secs)) => ramp_gain(now.saturating_duration_since(started).as_millis() as u64, ramp_secs),
+        None => 1.0,
+    };
+    if ramp_volume >= 1.0 {
+        state.ramp = None;
+    }
+    settings.set_volume(sleep_volume * ramp_volume);
+}
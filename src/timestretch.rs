@@ -0,0 +1,174 @@
+/// Slowest and fastest playback speeds, and the step of the speed keys.
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+pub const SPEED_STEP: f32 = 0.1;
+
+/// Segment length of the time stretcher; 40 ms suits both speech and music.
+const WINDOW_MS: u32 = 40;
+/// How far a segment may move from its nominal position to line up with the previous one.
+const SEARCH_MS: u32 = 10;
+
+/// Whether `speed` is close enough to 1× to play without stretching.
+pub fn is_normal_speed(speed: f32) -> bool {
+    (speed - 1.0).abs() < 0.01
+}
+
+/// Clamps a speed to the supported range and rounds it to the key step.
+pub fn clamp_speed(speed: f32) -> f32 {
+    ((speed / SPEED_STEP).round() * SPEED_STEP).clamp(MIN_SPEED, MAX_SPEED)
+}
+
+/// Changes tempo without changing pitch using WSOLA (waveform-similarity
+/// overlap-add): Hann-windowed segments are read from the input at
+/// `speed` times the rate they are written out, each shifted by up to
+/// `SEARCH_MS` to where it best continues the previous segment.
+pub struct TimeStretch {
+    channels: usize,
+    /// Segment length in frames; segments overlap by half.
+    window: usize,
+    search: usize,
+    fade_in: Vec<f32>,
+    /// Interleaved input; `buffer_start` is the track frame of its first frame.
+    buffer: Vec<f32>,
+    buffer_start: u64,
+    /// Track frame (fractional) where the next segment nominally starts.
+    position: f64,
+    /// Track frame where the last segment was actually taken from.
+    last: Option<u64>,
+    /// Second, fading-out half of the last segment, waiting for the next one.
+    tail: Vec<f32>,
+}
+
+impl TimeStretch {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let channels = channels.max(1);
+        let window = ((sample_rate * WINDOW_MS / 1000) as usize & !1).max(16);
+        let half = window / 2;
+        let fade_in = (0..half)
+            .map(|i| 0.5 - 0.5 * (std::f32::consts::PI * (i as f32 + 0.5) / half as f32).cos())
+            .collect();
+        Self {
+            channels,
+            window,
+            search: (sample_rate * SEARCH_MS / 1000) as usize,
+            fade_in,
+            buffer: Vec::new(),
+            buffer_start: 0,
+            position: 0.0,
+            last: None,
+            tail: Vec::new(),
+        }
+    }
+
+    /// Starts over at track frame `start_frame`, dropping buffered audio.
+    pub fn reset(&mut self, start_frame: u64) {
+        self.buffer.clear();
+        self.buffer_start = start_frame;
+        self.position = start_frame as f64;
+        self.last = None;
+        self.tail.clear();
+    }
+
+    fn frames(&self) -> usize {
+        self.buffer.len() / self.channels
+    }
+
+    /// Track frame the next input is expected to start at.
+    pub fn input_end(&self) -> u64 {
+        self.buffer_start + self.frames() as u64
+    }
+
+    /// Mono sample at track frame `frame`, which must be buffered.
+    fn mono(&self, frame: u64) -> f32 {
+        let start = (frame - self.buffer_start) as usize * self.channels;
+        self.buffer[start..start + self.channels].iter().sum::<f32>()
+    }
+
+    /// Segment start near `nominal` that best continues the previous segment.
+    fn best_offset(&self, nominal: u64) -> u64 {
+        let Some(last) = self.last else {
+            return nominal;
+        };
+        let half = (self.window / 2) as u64;
+        // The previous segment's natural continuation, which the new segment should resemble
+        let template = last + half;
+        let lowest = nominal.saturating_sub(self.search as u64).max(self.buffer_start);
+        let mut best = (f32::MIN, nominal);
+        for candidate in lowest..=nominal + self.search as u64 {
+            let (mut dot, mut energy) = (0.0f32, 0.0f32);
+            // Every other frame is plenty to find the best alignment
+            for i in (0..half).step_by(2) {
+                let x = self.mono(candidate + i);
+                dot += x * self.mono(template + i);
+                energy += x * x;
+            }
+            let score = dot / energy.sqrt().max(1e-6);
+            if score > best.0 {
+                best = (score, candidate);
+            }
+        }
+        best.1
+    }
+
+    /// Adds decoded frames and returns the stretched audio that is ready,
+    /// with the track frame it starts at.
+    pub fn process(&mut self, input: &[f32], speed: f32) -> (u64, Vec<f32>) {
+        self.buffer.extend_from_slice(input);
+        let half = self.window / 2;
+        let start_frame = self.position as u64;
+        let mut output = Vec::new();
+        loop {
+            let nominal = self.position as u64;
+            // The search window and a whole segment, plus the previous continuation, must be buffered
+            let needed = nominal + (self.search + self.window) as u64;
+            let template_end = self.last.map(|l| l + self.window as u64).unwrap_or(0);
+            if needed.max(template_end) > self.buffer_start + self.frames() as u64 {
+                break;
+            }
+            let chosen = self.best_offset(nominal);
+            let base = (chosen - self.buffer_start) as usize * self.channels;
+            for i in 0..half {
+                for c in 0..self.channels {
+                    let first = self.buffer[base + i * self.channels + c];
+                    let mixed = match self.last {
+                        // Cross-fade from the previous segment's tail
+                        Some(_) => first * self.fade_in[i] + self.tail[i * self.channels + c],
+                        None => first,
+                    };
+                    output.push(mixed);
+                }
+            }
+            self.tail = (0..half * self.channels)
+                .map(|k| self.buffer[base + half * self.channels + k] * (1.0 - self.fade_in[k / self.channels]))
+                .collect();
+            self.last = Some(chosen);
+            self.position += half as f64 * speed as f64;
+            self.discard_before(self.position as u64);
+        }
+        (start_frame, output)
+    }
+
+    /// Drops buffered frames that no later segment or template can need.
+    fn discard_before(&mut self, nominal: u64) {
+        let keep_from = nominal.saturating_sub(self.search as u64).min(self.last.unwrap_or(u64::MAX));
+        if keep_from > self.buffer_start + self.window as u64 {
+            let drop = (keep_from - self.buffer_start) as usize;
+            self.buffer.drain(..drop * self.channels);
+            self.buffer_start = keep_from;
+        }
+    }
+
+    /// Returns the buffered audio unstretched, continuing seamlessly from the
+    /// last segment, so playback can go back to normal speed. Starts at the returned track frame.
+    pub fn flush(&mut self) -> (u64, Vec<f32>) {
+        let start = match self.last {
+            Some(last) => last + (self.window / 2) as u64,
+            None => self.position as u64,
+        };
+        let offset = ((start.max(self.buffer_start) - self.buffer_start) as usize * self.channels).min(self.buffer.len());
+        let rest = self.buffer[offset..].to_vec();
+        let end = self.input_end();
+        self.reset(end);
+        (start, rest)
+    }
+}
@@ -0,0 +1,227 @@
+use ratatui::{style::{Color, Style}, text::{Line, Span}};
+use std::sync::{Arc, Mutex};
+
+/// Samples kept for the visualiser; also the FFT size.
+pub const TAP_SIZE: usize = 2048;
+
+/// Lowest and highest frequencies shown by the spectrum.
+const MIN_FREQUENCY: f32 = 40.0;
+const MAX_FREQUENCY: f32 = 16_000.0;
+
+/// Levels below this many dB are drawn as empty bars.
+const FLOOR_DB: f32 = -72.0;
+
+/// How far bars and peaks fall per frame (as a fraction of the height), and
+/// how many frames a peak holds before it falls (about a second at 30 fps).
+const BAR_FALL: f32 = 0.04;
+const PEAK_FALL: f32 = 0.015;
+const PEAK_HOLD_FRAMES: u32 = 30;
+
+#[derive(Default)]
+struct TapBuffer {
+    /// Ring of the last `TAP_SIZE` mono samples; `next` is the oldest.
+    samples: Vec<f32>,
+    next: usize,
+    /// Frames pushed in total, so readers can tell whether anything new arrived.
+    written: u64,
+    sample_rate: u32,
+}
+
+/// The most recent audio heard, copied out of the output stream for the visualiser.
+///
+/// The audio side only ever `try_lock`s, so a UI frame in progress can never
+/// hold up playback; samples that could not be delivered are retried later.
+#[derive(Clone, Default)]
+pub struct SampleTap(Arc<Mutex<TapBuffer>>);
+
+impl SampleTap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds mono samples. Returns `false`, without waiting, when the buffer is busy.
+    pub fn try_push(&self, mono: &[f32], sample_rate: u32) -> bool {
+        let Ok(mut tap) = self.0.try_lock() else {
+            return false;
+        };
+        if tap.samples.len() != TAP_SIZE {
+            tap.samples = vec![0.0; TAP_SIZE];
+            tap.next = 0;
+        }
+        for &sample in mono {
+            let next = tap.next;
+            tap.samples[next] = sample;
+            tap.next = (next + 1) % TAP_SIZE;
+        }
+        tap.written += mono.len() as u64;
+        tap.sample_rate = sample_rate;
+        true
+    }
+
+    /// The buffered samples, oldest first, with the sample rate and the total frames written.
+    pub fn snapshot(&self) -> (Vec<f32>, u32, u64) {
+        let tap = self.0.lock().unwrap();
+        if tap.samples.is_empty() {
+            return (vec![0.0; TAP_SIZE], tap.sample_rate, tap.written);
+        }
+        let mut samples = tap.samples[tap.next..].to_vec();
+        samples.extend_from_slice(&tap.samples[..tap.next]);
+        (samples, tap.sample_rate, tap.written)
+    }
+}
+
+/// In-place iterative radix-2 FFT. Both slices must have the same power-of-two length.
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * std::f32::consts::PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Level of each of `bands` log-spaced frequency bands, from 0 (silent) to 1 (full scale).
+pub fn band_levels(samples: &[f32], sample_rate: u32, bands: usize) -> Vec<f32> {
+    let n = samples.len().next_power_of_two().clamp(2, TAP_SIZE);
+    let samples = &samples[samples.len().saturating_sub(n)..];
+    // Hann window; its coherent gain of 0.5 is undone in the scaling below
+    let mut re: Vec<f32> = (0..n)
+        .map(|i| {
+            let window = 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / n as f32).cos();
+            samples.get(i).copied().unwrap_or(0.0) * window
+        })
+        .collect();
+    let mut im = vec![0.0; n];
+    fft(&mut re, &mut im);
+
+    let rate = sample_rate.max(1) as f32;
+    let top = MAX_FREQUENCY.min(rate / 2.0);
+    let bin_hz = rate / n as f32;
+    let ratio = (top / MIN_FREQUENCY).max(1.0);
+    (0..bands)
+        .map(|band| {
+            let low = MIN_FREQUENCY * ratio.powf(band as f32 / bands as f32);
+            let high = MIN_FREQUENCY * ratio.powf((band + 1) as f32 / bands as f32);
+            let first = ((low / bin_hz).round() as usize).clamp(1, n / 2 - 1);
+            let last = ((high / bin_hz).round() as usize).clamp(first, n / 2 - 1);
+            let magnitude = (first..=last)
+                .map(|bin| (re[bin] * re[bin] + im[bin] * im[bin]).sqrt())
+                .fold(0.0f32, f32::max);
+            let amplitude = magnitude * 4.0 / n as f32;
+            let db = 20.0 * amplitude.max(1e-9).log10();
+            ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+/// Bar spectrum with falling bars and peak-hold markers.
+#[derive(Default)]
+pub struct Spectrum {
+    levels: Vec<f32>,
+    peaks: Vec<f32>,
+    peak_age: Vec<u32>,
+    last_written: u64,
+}
+
+impl Spectrum {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advances one frame. Without new samples (paused, stopped) the bars fall to zero.
+    pub fn update(&mut self, samples: &[f32], sample_rate: u32, written: u64, bands: usize) {
+        let target = if written != self.last_written && sample_rate > 0 {
+            band_levels(samples, sample_rate, bands)
+        } else {
+            vec![0.0; bands]
+        };
+        self.last_written = written;
+        if self.levels.len() != bands {
+            self.levels = vec![0.0; bands];
+            self.peaks = vec![0.0; bands];
+            self.peak_age = vec![0; bands];
+        }
+        for (i, level) in target.into_iter().enumerate() {
+            self.levels[i] = level.max(self.levels[i] - BAR_FALL);
+            if self.levels[i] >= self.peaks[i] {
+                self.peaks[i] = self.levels[i];
+                self.peak_age[i] = 0;
+            } else if self.peak_age[i] < PEAK_HOLD_FRAMES {
+                self.peak_age[i] += 1;
+            } else {
+                self.peaks[i] = (self.peaks[i] - PEAK_FALL).max(self.levels[i]);
+            }
+        }
+    }
+
+    /// Draws the bars with eighth blocks, one column per band plus a gap.
+    pub fn lines(&self, height: u16) -> Vec<Line<'static>> {
+        const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+        let height = height as usize;
+        (0..height)
+            .map(|row| {
+                let from_bottom = height - 1 - row;
+                let colour = match from_bottom * 3 / height.max(1) {
+                    0 => Color::Green,
+                    1 => Color::Yellow,
+                    _ => Color::Red,
+                };
+                let spans: Vec<Span> = self.levels.iter().zip(&self.peaks)
+                    .flat_map(|(&level, &peak)| {
+                        let eighths = (level * (height * 8) as f32).round() as usize;
+                        let filled = eighths.saturating_sub(from_bottom * 8).min(8);
+                        let peak_row = ((peak * height as f32) as usize).min(height - 1);
+                        let cell = if filled == 0 && peak > 0.0 && peak_row == from_bottom {
+                            Span::styled("▔", Style::default().fg(Color::White))
+                        } else {
+                            Span::styled(BLOCKS[filled].to_string(), Style::default().fg(colour))
+                        };
+                        [cell, Span::raw(" ")]
+                    })
+                    .collect();
+                Line::from(spans)
+            })
+            .collect()
+    }
+}
+
+/// Oscilloscope trace: up to `points` samples starting at a rising zero
+/// crossing, so a steady tone stands still. Points are `(index, sample)`.
+pub fn scope_points(samples: &[f32], points: usize) -> Vec<(f64, f64)> {
+    let points = points.clamp(2, samples.len().max(2));
+    let search = samples.len().saturating_sub(points);
+    let start = (1..=search)
+        .find(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0)
+        .unwrap_or(search);
+    samples.iter()
+        .skip(start)
+        .take(points)
+        .enumerate()
+        .map(|(i, &s)| (i as f64, s.clamp(-1.0, 1.0) as f64))
+        .collect()
+}
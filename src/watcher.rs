@@ -0,0 +1,172 @@
+use crate::library::{find_mp3_files, Library};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+
+/// A filesystem change that matters to the queue or the library.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FsChange {
+    /// An MP3 appeared (created, moved in, or rewritten).
+    Added(String),
+    /// A file or directory disappeared.
+    Removed(String),
+}
+
+fn is_mp3(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"))
+}
+
+/// Translates a notify event into changes. Directories moved in are expanded to their MP3s.
+pub fn changes_for_event(event: &Event) -> Vec<FsChange> {
+    let added = |path: &PathBuf| -> Vec<FsChange> {
+        if path.is_dir() {
+            let mut files = Vec::new();
+            find_mp3_files(path, &mut files);
+            files.into_iter().map(FsChange::Added).collect()
+        } else if is_mp3(path) && path.is_file() {
+            vec![FsChange::Added(path.to_string_lossy().to_string())]
+        } else {
+            Vec::new()
+        }
+    };
+    let removed = |path: &PathBuf| vec![FsChange::Removed(path.to_string_lossy().to_string())];
+
+    match &event.kind {
+        EventKind::Create(_) => event.paths.iter().flat_map(added).collect(),
+        EventKind::Remove(_) => event.paths.iter().flat_map(removed).collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => event.paths.iter().flat_map(removed).collect(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+            let mut changes = removed(&event.paths[0]);
+            changes.extend(added(&event.paths[1]));
+            changes
+        }
+        // Any other modification: re-check whether each path still exists
+        EventKind::Modify(_) => event.paths.iter()
+            .flat_map(|p| if p.exists() { added(p) } else { removed(p) })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Watches the library roots and the loaded folder for added or removed MP3s.
+///
+/// Library updates happen on notify's thread; the main loop drains `poll`
+/// to keep the folder list in sync.
+pub struct LibraryWatcher {
+    watcher: RecommendedWatcher,
+    changes: Receiver<FsChange>,
+    roots: Vec<PathBuf>,
+    folder: Option<PathBuf>,
+}
+
+/// Whether `path` lies inside (or is) one of the recursively watched `roots`.
+pub fn is_under_roots(roots: &[PathBuf], path: &Path) -> bool {
+    roots.iter().any(|root| path.starts_with(root))
+}
+
+/// Adds or removes a changed file in the library. Changes in the loaded folder
+/// outside the roots only concern the queue.
+pub fn apply_to_library(change: &FsChange, roots: &[PathBuf], library: &mut Library) {
+    match change {
+        FsChange::Added(path) if is_under_roots(roots, Path::new(path)) => {
+            library.upsert_file(path);
+        }
+        FsChange::Removed(path) if is_under_roots(roots, Path::new(path)) => {
+            library.remove_path(path);
+        }
+        _ => {}
+    }
+}
+
+impl LibraryWatcher {
+    pub fn start(roots: &[String], library: Arc<Mutex<Library>>) -> Result<Self, Box<dyn std::error::Error>> {
+        let (tx, changes) = mpsc::channel();
+        let roots: Vec<PathBuf> = roots.iter()
+            .map(|root| std::path::absolute(root).unwrap_or_else(|_| PathBuf::from(root)))
+            .collect();
+        let library_roots = roots.clone();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            let Ok(event) = res else {
+                return;
+            };
+            for change in changes_for_event(&event) {
+                apply_to_library(&change, &library_roots, &mut library.lock().unwrap());
+                let _ = tx.send(change);
+            }
+        })?;
+        for root in &roots {
+            watcher.watch(root, RecursiveMode::Recursive)?;
+        }
+        Ok(Self { watcher, changes, roots, folder: None })
+    }
+
+    /// Also watches the folder the queue was loaded from (replacing the previous one).
+    ///
+    /// Folders inside a library root are already covered by the root's watch. They
+    /// are not watched again, because inotify gives the same directory a single
+    /// descriptor, and unwatching the folder later would silence it for the root too.
+    pub fn watch_folder(&mut self, directory: &str) {
+        let folder = std::path::absolute(directory).unwrap_or_else(|_| PathBuf::from(directory));
+        if self.folder.as_ref() == Some(&folder) {
+            return;
+        }
+        if let Some(old) = self.folder.take()
+            && !is_under_roots(&self.roots, &old)
+        {
+            let _ = self.watcher.unwatch(&old);
+        }
+        if is_under_roots(&self.roots, &folder) || self.watcher.watch(&folder, RecursiveMode::NonRecursive).is_ok() {
+            self.folder = Some(folder);
+        }
+    }
+
+    /// Drains pending changes without blocking.
+    pub fn poll(&self) -> Vec<FsChange> {
+        self.changes.try_iter().collect()
+    }
+}
+
+/// How a change affected the queue.
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueueChange {
+    Added,
+    Removed(usize),
+    Unchanged,
+}
+
+/// Keeps a folder-loaded queue in sync: new MP3s directly inside `directory`
+/// are appended, removed files are dropped wherever they sit in the queue.
+pub fn apply_to_queue(files: &mut Vec<String>, directory: &str, change: &FsChange) -> QueueChange {
+    let absolute = |entry: &str| {
+        let path = crate::get_track_path(entry, directory);
+        std::path::absolute(&path).unwrap_or_else(|_| PathBuf::from(path))
+    };
+    match change {
+        FsChange::Added(path) => {
+            let path = PathBuf::from(path);
+            let folder = std::path::absolute(directory).unwrap_or_else(|_| PathBuf::from(directory));
+            if path.parent() != Some(folder.as_path()) || files.iter().any(|f| absolute(f) == path) {
+                return QueueChange::Unchanged;
+            }
+            match path.file_name() {
+                Some(name) => {
+                    files.push(name.to_string_lossy().to_string());
+                    QueueChange::Added
+                }
+                None => QueueChange::Unchanged,
+            }
+        }
+        FsChange::Removed(path) => {
+            let path = PathBuf::from(path);
+            match files.iter().position(|f| absolute(f) == path) {
+                Some(idx) => {
+                    files.remove(idx);
+                    QueueChange::Removed(idx)
+                }
+                None => QueueChange::Unchanged,
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use ratatui::{layout::Rect, style::{Color, Style}, text::{Line, Span}};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::default::{get_codecs, get_probe};
+
+/// Frames summarised per block while decoding; blocks are merged into
+/// columns when drawn, so any panel width gets an exact overview.
+const BLOCK_FRAMES: u64 = 1024;
+
+/// Rows taken by the waveform at the bottom of the Now Playing panel.
+pub const WAVEFORM_ROWS: u16 = 4;
+
+/// Minimum, maximum and RMS of a stretch of mono samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bucket {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+impl Bucket {
+    fn peak(&self) -> f32 {
+        self.max.max(-self.min)
+    }
+}
+
+/// Low-resolution overview of a whole track.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Waveform {
+    pub blocks: Vec<Bucket>,
+    pub duration_ms: u64,
+}
+
+/// Collects mono samples into blocks.
+#[derive(Default)]
+pub struct WaveformBuilder {
+    blocks: Vec<Bucket>,
+    min: f32,
+    max: f32,
+    sum_squares: f64,
+    count: u64,
+    frames: u64,
+}
+
+impl WaveformBuilder {
+    pub fn push(&mut self, sample: f32) {
+        if self.count == 0 {
+            (self.min, self.max) = (sample, sample);
+        } else {
+            self.min = self.min.min(sample);
+            self.max = self.max.max(sample);
+        }
+        self.sum_squares += (sample * sample) as f64;
+        self.count += 1;
+        self.frames += 1;
+        if self.count == BLOCK_FRAMES {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            let rms = (self.sum_squares / self.count as f64).sqrt() as f32;
+            self.blocks.push(Bucket { min: self.min, max: self.max, rms });
+            self.sum_squares = 0.0;
+            self.count = 0;
+        }
+    }
+
+    pub fn finish(mut self, sample_rate: u32) -> Waveform {
+        self.flush();
+        Waveform { blocks: self.blocks, duration_ms: self.frames * 1000 / sample_rate.max(1) as u64 }
+    }
+}
+
+impl Waveform {
+    /// Merges the blocks into `columns` buckets covering equal stretches of the track.
+    pub fn columns(&self, columns: usize) -> Vec<Bucket> {
+        if self.blocks.is_empty() || columns == 0 {
+            return vec![Bucket::default(); columns];
+        }
+        (0..columns)
+            .map(|c| {
+                let start = c * self.blocks.len() / columns;
+                let end = ((c + 1) * self.blocks.len() / columns).max(start + 1).min(self.blocks.len());
+                let blocks = &self.blocks[start.min(end - 1)..end];
+                Bucket {
+                    min: blocks.iter().map(|b| b.min).fold(f32::MAX, f32::min),
+                    max: blocks.iter().map(|b| b.max).fold(f32::MIN, f32::max),
+                    rms: (blocks.iter().map(|b| b.rms * b.rms).sum::<f32>() / blocks.len() as f32).sqrt(),
+                }
+            })
+            .collect()
+    }
+
+    /// Loudest sample of the track, used to scale the drawing.
+    fn peak(&self) -> f32 {
+        self.blocks.iter().map(Bucket::peak).fold(0.0, f32::max)
+    }
+}
+
+/// Decodes a whole track to a mono overview.
+pub fn compute_waveform(path: &str) -> Result<Waveform, Box<dyn std::error::Error>> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension("mp3");
+    let probed = get_probe().format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())?;
+    let mut format = probed.format;
+    let track = format.default_track().ok_or("No default track found")?;
+    let track_id = track.id;
+    let mut decoder = get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(44100);
+
+    let mut builder = WaveformBuilder::default();
+    while let Ok(packet) = format.next_packet() {
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let Ok(decoded) = decoder.decode(&packet) else {
+            continue;
+        };
+        let channels = decoded.spec().channels.count().max(1);
+        sample_rate = decoded.spec().rate;
+        let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks(channels) {
+            builder.push(frame.iter().sum::<f32>() / channels as f32);
+        }
+    }
+    Ok(builder.finish(sample_rate))
+}
+
+/// Waveforms per track, computed by a background thread.
+pub struct WaveformCache {
+    entries: Arc<Mutex<HashMap<String, Option<Arc<Waveform>>>>>,
+    pending: Arc<Mutex<HashSet<String>>>,
+    requests: Sender<String>,
+}
+
+impl WaveformCache {
+    pub fn new() -> Self {
+        let entries = Arc::new(Mutex::new(HashMap::new()));
+        let pending = Arc::new(Mutex::new(HashSet::new()));
+        let (requests, rx) = mpsc::channel::<String>();
+
+        std::thread::spawn({
+            let entries = entries.clone();
+            let pending = pending.clone();
+            move || {
+                for track_path in rx {
+                    let waveform = compute_waveform(&track_path).ok().map(Arc::new);
+                    entries.lock().unwrap().insert(track_path.clone(), waveform);
+                    pending.lock().unwrap().remove(&track_path);
+                }
+            }
+        });
+
+        Self { entries, pending, requests }
+    }
+
+    /// `Some(None)` when the track could not be decoded, `None` while it is being scanned.
+    pub fn get(&self, track_path: &str) -> Option<Option<Arc<Waveform>>> {
+        if let Some(waveform) = self.entries.lock().unwrap().get(track_path) {
+            return Some(waveform.clone());
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.insert(track_path.to_string()) {
+            let _ = self.requests.send(track_path.to_string());
+        }
+        None
+    }
+}
+
+/// Column of `width` that shows `position_ms`.
+pub fn position_column(position_ms: u64, duration_ms: u64, width: u16) -> u16 {
+    if duration_ms == 0 || width == 0 {
+        return 0;
+    }
+    ((position_ms.min(duration_ms) as f64 / duration_ms as f64) * (width - 1) as f64).round() as u16
+}
+
+/// Position a click on column `x` of `area` asks for.
+pub fn seek_position(x: u16, area: Rect, duration_ms: u64) -> u64 {
+    let column = x.saturating_sub(area.x).min(area.width.saturating_sub(1));
+    let fraction = column as f64 / area.width.saturating_sub(1).max(1) as f64;
+    (fraction * duration_ms as f64) as u64
+}
+
+/// Draws the waveform as bars: the bright part is the RMS level, the dim tip
+/// the peak. The played part is coloured and the playhead highlighted.
+pub fn waveform_lines(waveform: &Waveform, width: u16, height: u16, position_ms: u64) -> Vec<Line<'static>> {
+    const BLOCKS: [char; 9] = [' ', '▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let columns = waveform.columns(width as usize);
+    let scale = waveform.peak().max(1e-6);
+    let playhead = position_column(position_ms, waveform.duration_ms, width) as usize;
+    let eighths = |level: f32| ((level / scale).min(1.0) * (height as usize * 8) as f32).round() as usize;
+    (0..height as usize)
+        .map(|row| {
+            let from_bottom = height as usize - 1 - row;
+            let spans: Vec<Span> = columns.iter()
+                .enumerate()
+                .map(|(c, bucket)| {
+                    let filled = eighths(bucket.peak()).saturating_sub(from_bottom * 8).min(8);
+                    let in_rms = from_bottom * 8 < eighths(bucket.rms);
+                    let colour = match (c.cmp(&playhead), in_rms) {
+                        (std::cmp::Ordering::Equal, _) => Color::White,
+                        (std::cmp::Ordering::Less, true) => Color::LightRed,
+                        (std::cmp::Ordering::Less, false) => Color::Red,
+                        (std::cmp::Ordering::Greater, true) => Color::Gray,
+                        (std::cmp::Ordering::Greater, false) => Color::DarkGray,
+                    };
+                    let symbol = if c == playhead && filled == 0 { '│' } else { BLOCKS[filled] };
+                    Span::styled(symbol.to_string(), Style::default().fg(colour))
+                })
+                .collect();
+            Line::from(spans)
+        })
+        .collect()
+}